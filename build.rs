@@ -7,11 +7,22 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also converts the images in `assets/` into Rust data, which
+//! `src/assets.rs` includes.
 
 use std::env;
-use std::fs::File;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+// the LED positions, to sample color images
+#[allow(dead_code)]
+#[path = "src/hex.rs"]
+mod hex;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -23,9 +34,176 @@ fn main() {
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
+    let assets = convert_assets(Path::new("assets")).unwrap_or_else(|e| panic!("assets: {}", e));
+    fs::write(out.join("assets.rs"), assets).unwrap();
+
     // By default, Cargo will re-run a build script whenever
     // any file in the project changes. By specifying `memory.x`
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=assets");
+    println!("cargo:rerun-if-changed=src/hex.rs");
+}
+
+/// An RGBA image, 8 bits per channel.
+struct Image {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+}
+
+impl Image {
+    /// The pixel at `(x, y)` over black.
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let p = &self.rgba[(y * self.width + x) * 4..][..4];
+        let over_black = |c: u8| (c as u16 * p[3] as u16 / 255) as u8;
+        [over_black(p[0]), over_black(p[1]), over_black(p[2])]
+    }
+
+    /// Average of the pixels in `x0..x1` and `y0..y1`, at least one pixel.
+    fn average(&self, x0: usize, x1: usize, y0: usize, y1: usize) -> [u8; 3] {
+        let x0 = x0.min(self.width - 1);
+        let y0 = y0.min(self.height - 1);
+        let x1 = x1.min(self.width).max(x0 + 1);
+        let y1 = y1.min(self.height).max(y0 + 1);
+        let mut sum = [0u32; 3];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                for (s, c) in sum.iter_mut().zip(self.pixel(x, y).iter()) {
+                    *s += *c as u32;
+                }
+            }
+        }
+        let n = ((x1 - x0) * (y1 - y0)) as u32;
+        [(sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8]
+    }
+}
+
+fn load_png(path: &Path) -> Result<Image, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    // palettes and low bit depths to 8 bits per channel
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut rgba = vec![0; width * height * 4];
+    let (color_type, _) = reader.output_color_type();
+    for (dst, src) in rgba.chunks_mut(4).zip(buf.chunks(color_type.samples())) {
+        let pixel = match color_type {
+            png::ColorType::Grayscale => [src[0], src[0], src[0], 255],
+            png::ColorType::GrayscaleAlpha => [src[0], src[0], src[0], src[1]],
+            png::ColorType::RGB => [src[0], src[1], src[2], 255],
+            png::ColorType::RGBA => [src[0], src[1], src[2], src[3]],
+            png::ColorType::Indexed => return Err("indexed PNG was not expanded".into()),
+        };
+        dst.copy_from_slice(&pixel);
+    }
+    Ok(Image {
+        width,
+        height,
+        rgba,
+    })
+}
+
+/// The PNGs in `dir` (which may not exist), sorted by name.
+fn pngs(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("png")) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// `assets/oled/status-icon.png` becomes `STATUS_ICON`.
+fn const_name(path: &Path) -> String {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let mut name: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// One bit per pixel, rows padded to whole bytes, the leftmost pixel in the
+/// most significant bit. Pixels brighter than half are on.
+fn bitmap(image: &Image) -> Vec<u8> {
+    let stride = image.width.div_ceil(8);
+    let mut data = vec![0; stride * image.height];
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let [r, g, b] = image.pixel(x, y);
+            let luma = (2 * r as u32 + 5 * g as u32 + b as u32) / 8;
+            if luma >= 128 {
+                data[y * stride + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    data
+}
+
+/// The color of every LED of the panel, the image stretched over the panel
+/// and averaged over the area of each cell.
+fn hex_colors(image: &Image) -> Vec<[u8; 3]> {
+    let layout = &hex::PANEL;
+    (0..layout.len())
+        .map(|i| {
+            let (xs, ys) = layout.image_area(i, image.width, image.height).unwrap();
+            image.average(xs.start, xs.end, ys.start, ys.end)
+        })
+        .collect()
+}
+
+/// The generated module: a `Bitmap` per image in `assets/oled/` and a color
+/// per LED for each image in `assets/leds/`.
+fn convert_assets(assets: &Path) -> Result<String, Box<dyn Error>> {
+    let mut code = String::from("// Generated by build.rs from the images in assets/.\n");
+    for path in pngs(&assets.join("oled"))? {
+        let image = load_png(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let data = bitmap(&image);
+        writeln!(code, "\n/// `{}`", path.display())?;
+        writeln!(code, "pub const {}: Bitmap = Bitmap {{", const_name(&path))?;
+        writeln!(code, "    width: {},", image.width)?;
+        writeln!(code, "    height: {},", image.height)?;
+        writeln!(code, "    data: &[")?;
+        for row in data.chunks(16) {
+            let bytes: Vec<_> = row.iter().map(|b| format!("0x{:02x},", b)).collect();
+            writeln!(code, "        {}", bytes.join(" "))?;
+        }
+        writeln!(code, "    ],\n}};")?;
+    }
+    for path in pngs(&assets.join("leds"))? {
+        let image = load_png(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let colors = hex_colors(&image);
+        writeln!(code, "\n/// `{}`", path.display())?;
+        writeln!(
+            code,
+            "pub static {}: [RGB8; {}] = [",
+            const_name(&path),
+            colors.len()
+        )?;
+        for [r, g, b] in colors {
+            writeln!(code, "    rgb({}, {}, {}),", r, g, b)?;
+        }
+        writeln!(code, "];")?;
+    }
+    Ok(code)
 }
@@ -0,0 +1,158 @@
+#![no_main]
+#![no_std]
+
+// The hex panel built from APA102 / SK9822 LEDs on SPI3 (SCK on PB3, MOSI on
+// PB5). The brightness is applied through the global brightness of the
+// pixels, and the power limiter works on the resulting light output.
+
+extern crate panic_halt;
+
+extern crate stm32l4xx_hal as hal;
+use rtic_stm32::apa102::{self, Apa102, Apa102Pixel};
+use rtic_stm32::clock::AnimationClock;
+use rtic_stm32::effect::{Player, Registry};
+use rtic_stm32::effects::{Plasma, RainbowEffect};
+use rtic_stm32::hex;
+use rtic_stm32::monotonic::{self, Tim2Monotonic};
+use rtic_stm32::power::{PowerLimiter, PowerModel, Zone};
+use smart_leds::{SmartLedsWrite, RGB8};
+
+use hal::{
+    gpio::{Alternate, Floating, Input, AF6, PB3, PB4, PB5},
+    prelude::*,
+    spi::Spi,
+    stm32::SPI3,
+};
+
+const REFRESH_LED_STRIP_HZ: u32 = 60;
+
+// resolution of the monotonic timer
+const TICK_HZ: u32 = 1_000_000;
+
+const NUM_LEDS: usize = 291;
+
+// dark enough for a room, the global brightness keeps the colors smooth
+const BRIGHTNESS: u8 = 40;
+
+// a single supply feeds the whole panel, in mA
+const SUPPLY_MA: u32 = 4_000;
+
+const EFFECT_DURATION_MS: u32 = 20_000;
+const EFFECT_FADE_MS: u32 = 2_000;
+
+type Strip = Apa102<
+    Spi<
+        SPI3,
+        (
+            PB3<Alternate<AF6, Input<Floating>>>,
+            PB4<Alternate<AF6, Input<Floating>>>,
+            PB5<Alternate<AF6, Input<Floating>>>,
+        ),
+    >,
+>;
+
+#[rtic::app(device = hal::stm32, peripherals = true, monotonic = rtic_stm32::monotonic::Tim2Monotonic)]
+const APP: () = {
+    struct Resources {
+        strip: Strip,
+        player: Player<'static, 2>,
+        anim_clock: AnimationClock<monotonic::Instant>,
+        led_strip_data: [RGB8; NUM_LEDS],
+        pixels: [Apa102Pixel; NUM_LEDS],
+        power_limiter: PowerLimiter<1>,
+    }
+
+    #[init(schedule = [refresh_led_strip])]
+    fn init(cx: init::Context) -> init::LateResources {
+        static mut RAINBOW: RainbowEffect = RainbowEffect::new(1);
+        static mut PLASMA: Plasma = Plasma::new(&hex::PANEL);
+        static mut FADE_BUFFER: [RGB8; NUM_LEDS] = [rtic_stm32::color::BLACK; NUM_LEDS];
+
+        let mut rcc = cx.device.RCC.constrain();
+        let mut flash = cx.device.FLASH.constrain();
+        let mut pwr = cx.device.PWR.constrain(&mut rcc.apb1r1);
+
+        let clocks = rcc
+            .cfgr
+            .sysclk(64.mhz())
+            .pclk1(16.mhz())
+            .pclk2(64.mhz())
+            .freeze(&mut flash.acr, &mut pwr);
+        Tim2Monotonic::start(cx.device.TIM2, TICK_HZ, clocks, &mut rcc.apb1r1);
+
+        // ================================================================================
+        // setup the strip, MISO is not connected
+        let mut gpiob = cx.device.GPIOB.split(&mut rcc.ahb2);
+        let pins = (
+            gpiob.pb3.into_af6(&mut gpiob.moder, &mut gpiob.afrl),
+            gpiob.pb4.into_af6(&mut gpiob.moder, &mut gpiob.afrl),
+            gpiob.pb5.into_af6(&mut gpiob.moder, &mut gpiob.afrl),
+        );
+        let spi = Spi::spi3(
+            cx.device.SPI3,
+            pins,
+            apa102::MODE,
+            8.mhz(),
+            clocks,
+            &mut rcc.apb1r1,
+        );
+
+        let mut player = Player::new(
+            Registry::new([("rainbow", RAINBOW), ("plasma", PLASMA)]),
+            FADE_BUFFER,
+        );
+        // dimmed by the global brightness instead
+        player.params.brightness = 255;
+
+        cx.schedule
+            .refresh_led_strip(cx.start + Tim2Monotonic::period(REFRESH_LED_STRIP_HZ))
+            .unwrap();
+
+        init::LateResources {
+            strip: Apa102::new(spi),
+            player,
+            anim_clock: AnimationClock::new(Tim2Monotonic::ticks_per_ms()),
+            led_strip_data: [rtic_stm32::color::BLACK; NUM_LEDS],
+            pixels: [Apa102Pixel::from(rtic_stm32::color::BLACK); NUM_LEDS],
+            power_limiter: PowerLimiter::new(
+                PowerModel::default(),
+                [Zone::new(0..NUM_LEDS, SUPPLY_MA, SUPPLY_MA)],
+            ),
+        }
+    }
+
+    #[task(binds = TIM2, priority = 3)]
+    fn tim2(_: tim2::Context) {
+        Tim2Monotonic::on_interrupt();
+    }
+
+    // the SPI writes block, so this runs at the lowest priority
+    #[task(schedule = [refresh_led_strip], resources = [strip, player, anim_clock, led_strip_data, pixels, power_limiter], priority = 1)]
+    fn refresh_led_strip(cx: refresh_led_strip::Context) {
+        let now = cx.resources.anim_clock.tick(cx.scheduled);
+        let player = &mut *cx.resources.player;
+        if now.wrapping_sub(player.started()) >= EFFECT_DURATION_MS && !player.is_fading() {
+            player.next(now, EFFECT_FADE_MS);
+        }
+        player.render(now, cx.resources.led_strip_data);
+
+        let frame = cx.resources.led_strip_data.iter().cloned();
+        let pixels = &mut *cx.resources.pixels;
+        for (p, c) in pixels
+            .iter_mut()
+            .zip(apa102::with_brightness(frame, BRIGHTNESS))
+        {
+            *p = c;
+        }
+        cx.resources.power_limiter.apply(pixels);
+        cx.resources.strip.write(pixels.iter().cloned()).ok();
+
+        cx.schedule
+            .refresh_led_strip(cx.scheduled + Tim2Monotonic::period(REFRESH_LED_STRIP_HZ))
+            .unwrap();
+    }
+
+    extern "C" {
+        fn COMP();
+    }
+};
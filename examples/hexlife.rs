@@ -4,21 +4,44 @@
 extern crate panic_halt;
 
 extern crate stm32l4xx_hal as hal;
+use rtic_stm32::assets;
+use rtic_stm32::clock::{AnimationClock, WallClock};
+use rtic_stm32::color;
+use rtic_stm32::command::{self, Command, LineBuffer, Replies};
+use rtic_stm32::effect::{Control, Params, Player};
+use rtic_stm32::flash::FlashPage;
+use rtic_stm32::game::{self, Arcade};
+use rtic_stm32::games::{Pong, Snake};
+use rtic_stm32::hex;
+use rtic_stm32::installation::{Effects, Pipeline, NUM_EFFECTS, NUM_LEDS};
+use rtic_stm32::monotonic::{self, Tim2Monotonic};
+use rtic_stm32::power::ZoneReport;
 use rtic_stm32::prelude::*;
+use rtic_stm32::preview::Preview;
+use rtic_stm32::rng::{SeedMode, Seeder};
+use rtic_stm32::rtc::Rtc;
+use rtic_stm32::schedule::{Rule, Scheduler};
+use rtic_stm32::segment::Source;
+use rtic_stm32::stats::{self, CpuLoad, TaskStats};
+use rtic_stm32::timeline::{Action, Cue, Easing, Key, Show, Target, Timeline, Track};
+use rtic_stm32::ws2812_dma::{self, SpiTxDma, Ws2812Dma};
 use smart_leds::RGB8;
-use ws2812::Ws2812;
 
 use core::fmt::Write;
+use core::mem::MaybeUninit;
+use cortex_m::{iprintln, peripheral::ITM};
 use embedded_graphics::{fonts, pixelcolor, prelude::*, style};
 use hal::{
     device::I2C1,
     gpio::gpioa::PA0,
     gpio::{
-        Alternate, Edge, Floating, Input, OpenDrain, Output, PullUp, PushPull, PA1, PA5, PA6, PA7,
-        PB6, PB7, PB8, PB9,
+        Alternate, Edge, Floating, Input, OpenDrain, Output, PullUp, PushPull, PA1, PA2, PA3, PA5,
+        PA6, PA7, PB6, PB7, PB8, PB9,
     },
     i2c::I2c,
     prelude::*,
+    rng::{Rng, RngExt},
+    serial::{self, Serial},
     spi::Spi,
     stm32,
     timer::{Event, Timer},
@@ -29,7 +52,7 @@ use hal::{
 };
 use heapless::consts::*;
 use heapless::String;
-use rtic::cyccnt::U32Ext;
+use rtic::Monotonic;
 use smart_leds::SmartLedsWrite;
 use ssd1306::{mode::GraphicsMode, prelude::*, Builder, I2CDIBuilder};
 use ws2812_spi as ws2812;
@@ -48,42 +71,109 @@ const GAMMA8: [u8; 256] = [
     223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
 ];
 
-const REFRESH_DISPLAY_PERIOD: u32 = 8_000_000 / 4;
-const REFRESH_LED_STRIP_PERIOD: u32 = 8_000_000 / 5;
+const REFRESH_DISPLAY_HZ: u32 = 4;
+const REFRESH_LED_STRIP_HZ: u32 = 40;
 
-fn rgb8_to_power(c: &RGB8) -> u32 {
-    let tmp = c.r as u32 + c.g as u32 + c.b as u32;
-    tmp * 12 / 255
-}
-const NUM_LEDS: usize = 291;
-
-fn estimate_current(data: &[RGB8; NUM_LEDS]) -> [u32; 4] {
-    let start0 = 0;
-    let size0 = 8 + 9 + 10 + 11 + 15 + 16 + 17;
-    let start1 = size0;
-    let size1 = 17 + 17 + 17 + 17;
-    let start2 = start1 + size1;
-    let size2 = 17 + 17 + 17 + 17;
-    let start3 = start2 + size2;
-    let size3 = 16 + 15 + 11 + 10 + 9 + 8;
-    let end3 = start3 + size3;
-    assert!(size0 + size1 + size2 + size3 == 291);
-
-    let zones = [start0..start1, start1..start2, start2..start3, start3..end3];
-
-    let mut out = [0; 4];
-    for (i, range) in zones.iter().enumerate() {
-        out[i] = 78
-            + data[range.clone()]
-                .iter()
-                .map(|c| rgb8_to_power(c))
-                .sum::<u32>();
-    }
+// resolution of the monotonic timer, the task statistics count core cycles
+const TICK_HZ: u32 = 1_000_000;
+
+// the display cycles through the power, the timing and the LED preview page
+const DISPLAY_PAGE_SECS: u32 = 5;
+const DISPLAY_PAGES: u32 = 3;
+const TIMING_PAGE: u32 = 1;
+const PREVIEW_PAGE: u32 = 2;
+// statistics are sent over ITM port 0 this often
+const STATS_REPORT_SECS: u32 = 1;
+const CPU_LOAD_WINDOW_MS: u32 = 1_000;
+
+// effects are switched automatically after this time
+const EFFECT_DURATION_MS: u32 = 30_000;
+const EFFECT_FADE_MS: u32 = 2_000;
+
+// use SeedMode::Fixed(..) to get the same animations on every run
+const SEED_MODE: SeedMode = SeedMode::Entropy;
+
+// the button is read this long after it changed, when it stopped bouncing
+const BUTTON_DEBOUNCE_MS: u32 = 20;
+// holding the button this long starts the next game, or leaves the games
+const LONG_PRESS_MS: u32 = 1_000;
+
+const GAME_TICK_HZ: u32 = 100;
 
-    out
+// time-of-day rules, kept in the last flash page (see memory.x)
+const MAX_RULES: usize = 16;
+const SETTINGS_PAGE: u8 = 63;
+const SCHEDULE_HZ: u32 = 1;
+// rules typed in a row are saved at once, this long after the first one
+const SAVE_DELAY_MS: u32 = 1_000;
+// the strip is checked this often until it is idle
+const SAVE_RETRY_MS: u32 = 1;
+
+// commands are received on USART2 (the ST-LINK virtual COM port)
+const COMMAND_BAUDRATE: u32 = 115_200;
+const COMMAND_LINE_LEN: usize = 80;
+// room for the longest answer, the rule list
+const REPLY_LEN: usize = 1024;
+
+// a minute through some of the effects, fading in and out, for the `show` command
+const TOUR: Show = Show {
+    tracks: &[
+        (
+            Target::Brightness,
+            Track::new(&[
+                Key::new(0, 0, Easing::Linear),
+                Key::new(4_000, 255, Easing::SineOut),
+                Key::new(56_000, 255, Easing::Linear),
+                Key::new(60_000, 0, Easing::SineIn),
+            ]),
+        ),
+        (
+            Target::Speed,
+            Track::new(&[
+                Key::new(0, 256, Easing::Linear),
+                Key::new(30_000, 640, Easing::CubicInOut),
+                Key::new(45_000, 128, Easing::BounceOut),
+                Key::new(60_000, 256, Easing::QuadIn),
+            ]),
+        ),
+        (
+            Target::PalettePosition,
+            Track::new(&[
+                Key::new(0, 0, Easing::Linear),
+                Key::new(60_000, 256, Easing::Linear),
+            ]),
+        ),
+        (
+            // once around the color wheel over the particles
+            Target::HueOffset,
+            Track::new(&[
+                Key::new(45_000, 0, Easing::Linear),
+                Key::new(60_000, 256, Easing::QuadInOut),
+            ]),
+        ),
+    ],
+    cues: &[
+        Cue::new(0, Action::Switch("plasma", 0)),
+        Cue::new(0, Action::Palette("rainbow", 0)),
+        Cue::new(15_000, Action::Switch("ripples", EFFECT_FADE_MS)),
+        Cue::new(20_000, Action::Trigger),
+        Cue::new(30_000, Action::Switch("fire", EFFECT_FADE_MS)),
+        Cue::new(30_000, Action::Palette("heat", EFFECT_FADE_MS)),
+        Cue::new(45_000, Action::Switch("particles", EFFECT_FADE_MS)),
+        Cue::new(45_000, Action::Palette("ocean", EFFECT_FADE_MS)),
+    ],
+    length_ms: 60_000,
+    repeat: true,
+};
+const SHOWS: [(&str, &Show); 1] = [("tour", &TOUR)];
+
+/// The logo in front of the name, on the first line.
+fn draw_header(disp: &mut dyn Console) {
+    disp.write("   hexlife", None);
+    disp.bitmap(&assets::LOGO, 2, 0);
 }
 
-#[rtic::app(device = hal::stm32, peripherals = true, monotonic = rtic::cyccnt::CYCCNT)]
+#[rtic::app(device = hal::stm32, peripherals = true, monotonic = rtic_stm32::monotonic::Tim2Monotonic)]
 const APP: () = {
     struct Resources {
         timer: Timer<stm32::TIM7>,
@@ -99,7 +189,7 @@ const APP: () = {
             >,
             DisplaySize128x64,
         >,
-        led_strip_dev: ws2812_spi::Ws2812<
+        led_strip_dev: Ws2812Dma<
             Spi<
                 hal::pac::SPI1,
                 (
@@ -109,19 +199,61 @@ const APP: () = {
                 ),
             >,
         >,
-        rainbow: Rainbow,
-        led_strip_data: [smart_leds::RGB8; 291],
-        led_strip_current: [u32; 4],
+        player: Player<'static, NUM_EFFECTS>,
+        arcade: Arcade<'static, 2>,
+        // None without the LSE crystal
+        rtc: Option<Rtc>,
+        scheduler: Scheduler<MAX_RULES>,
+        settings: FlashPage,
+        button: PC13<Input<PullUp>>,
+        seeder: Seeder<Rng>,
+        #[init(true)]
+        auto_switch: bool,
+        #[init(None)]
+        timeline: Option<Timeline<'static>>,
+        command_serial: Serial<
+            hal::pac::USART2,
+            (
+                PA2<Alternate<hal::gpio::AF7, Input<Floating>>>,
+                PA3<Alternate<hal::gpio::AF7, Input<Floating>>>,
+            ),
+        >,
+        #[init(LineBuffer::new())]
+        command_line: LineBuffer<COMMAND_LINE_LEN>,
+        #[init(Replies::new())]
+        replies: Replies<REPLY_LEN>,
+        anim_clock: AnimationClock<monotonic::Instant>,
+        wall_clock: WallClock<monotonic::Instant>,
+        led_strip_data: [smart_leds::RGB8; NUM_LEDS],
+        led_strip_current: [ZoneReport; 4],
+        pipeline: Pipeline,
+        itm: ITM,
+        #[init(TaskStats::new())]
+        led_strip_stats: TaskStats,
+        #[init(TaskStats::new())]
+        display_stats: TaskStats,
+        cpu_load: CpuLoad,
     }
 
-    #[init(schedule = [refresh_display, refresh_led_strip])]
+    #[init(schedule = [refresh_display, refresh_led_strip, game_tick, run_schedule])]
     fn init(mut cx: init::Context) -> init::LateResources {
+        static mut SNAKE: Snake<NUM_LEDS> = Snake::new(&hex::PANEL);
+        static mut PONG: Pong = Pong::new(&hex::PANEL);
+        // built at run time, a const initializer would take its size twice, in
+        // flash and in RAM
+        static mut EFFECTS: MaybeUninit<Effects> = MaybeUninit::uninit();
+        static mut FADE_BUFFER: [RGB8; NUM_LEDS] = [rtic_stm32::color::BLACK; NUM_LEDS];
+        static mut LED_STRIP_BUFFER0: [u8; ws2812_dma::buffer_len(NUM_LEDS)] =
+            [0; ws2812_dma::buffer_len(NUM_LEDS)];
+        static mut LED_STRIP_BUFFER1: [u8; ws2812_dma::buffer_len(NUM_LEDS)] =
+            [0; ws2812_dma::buffer_len(NUM_LEDS)];
+
         let mut rcc = cx.device.RCC.constrain();
         let mut flash = cx.device.FLASH.constrain();
         let mut pwr = cx.device.PWR.constrain(&mut rcc.apb1r1);
         let mut cp = cx.core;
 
-        // software tasks won't work without this:
+        // the task statistics count cycles
         cp.DCB.enable_trace();
         cp.DWT.enable_cycle_counter();
 
@@ -130,7 +262,23 @@ const APP: () = {
             .sysclk(64.mhz())
             .pclk1(16.mhz())
             .pclk2(64.mhz())
+            // clock of the RNG
+            .hsi48(true)
             .freeze(&mut flash.acr, &mut pwr);
+        // 64 bits never wrap, unlike CYCCNT, which the schedules outgrow in a minute
+        Tim2Monotonic::start(cx.device.TIM2, TICK_HZ, clocks, &mut rcc.apb1r1);
+        let mut seeder = Seeder::new(cx.device.RNG.enable(&mut rcc.ahb2, clocks), SEED_MODE);
+        let rtc = Rtc::new(
+            cx.device.RTC,
+            clocks,
+            &mut rcc.apb1r1,
+            &mut rcc.bdcr,
+            &mut pwr.cr1,
+        )
+        .ok();
+        let settings = FlashPage::new(flash.keyr, flash.sr, flash.cr, SETTINGS_PAGE);
+        let mut scheduler = Scheduler::new();
+        scheduler.load(settings.words());
 
         // ================================================================================
         // Set up Timer interrupt
@@ -167,10 +315,10 @@ const APP: () = {
         disp.init().unwrap();
         disp.flush().unwrap();
 
-        disp.write("hello world xxx!", None);
+        draw_header(&mut disp);
         disp.flush().unwrap();
         cx.schedule
-            .refresh_display(cx.start + REFRESH_DISPLAY_PERIOD.cycles())
+            .refresh_display(cx.start + Tim2Monotonic::period(REFRESH_DISPLAY_HZ))
             .unwrap();
 
         // ================================================================================
@@ -193,10 +341,49 @@ const APP: () = {
             clocks,
             &mut rcc.apb2,
         );
-        let led_strip_dev = Ws2812::new(spi);
+        let led_strip_dev = Ws2812Dma::new(
+            spi,
+            SpiTxDma::spi1(&mut rcc.ahb1),
+            LED_STRIP_BUFFER0,
+            LED_STRIP_BUFFER1,
+        );
+
+        let effects: &'static mut Effects = EFFECTS.write(Effects::new());
+        let mut player = Player::new(effects.registry(), FADE_BUFFER);
+        player.registry_mut().reseed(|| seeder.next_seed());
+        let arcade = Arcade::new([("snake", SNAKE), ("pong", PONG)], seeder.next_seed());
+        cx.schedule
+            .refresh_led_strip(cx.start + Tim2Monotonic::period(REFRESH_LED_STRIP_HZ))
+            .unwrap();
+
+        // ================================================================================
+        // setup command interface
+        let tx = gpioa.pa2.into_af7(&mut gpioa.moder, &mut gpioa.afrl);
+        let rx = gpioa.pa3.into_af7(&mut gpioa.moder, &mut gpioa.afrl);
+        let mut command_serial = Serial::usart2(
+            cx.device.USART2,
+            (tx, rx),
+            serial::Config::default().baudrate(COMMAND_BAUDRATE.bps()),
+            clocks,
+            &mut rcc.apb1r1,
+        );
+        command_serial.listen(serial::Event::Rxne);
 
+        // ================================================================================
+        // Set up button, it is passed on to the current effect
+        let mut gpioc = cx.device.GPIOC.split(&mut rcc.ahb2);
+        let mut button = gpioc
+            .pc13
+            .into_pull_up_input(&mut gpioc.moder, &mut gpioc.pupdr);
+        button.make_interrupt_source(&mut cx.device.SYSCFG, &mut rcc.apb2);
+        button.enable_interrupt(&mut cx.device.EXTI);
+        button.trigger_on_edge(&mut cx.device.EXTI, Edge::RisingFalling);
+
+        cx.schedule
+            .game_tick(cx.start + Tim2Monotonic::period(GAME_TICK_HZ))
+            .unwrap();
         cx.schedule
-            .refresh_led_strip(cx.start + REFRESH_LED_STRIP_PERIOD.cycles())
+            .run_schedule(cx.start + Tim2Monotonic::period(SCHEDULE_HZ))
             .unwrap();
 
         // Initialization of late resources
@@ -204,9 +391,21 @@ const APP: () = {
             timer,
             disp,
             led_strip_dev,
-            rainbow: Rainbow::step_phase(1, 1),
-            led_strip_data: [rtic_stm32::color::BLACK; 291],
-            led_strip_current: [0; 4],
+            player,
+            arcade,
+            rtc,
+            scheduler,
+            settings,
+            button,
+            seeder,
+            command_serial,
+            anim_clock: AnimationClock::new(Tim2Monotonic::ticks_per_ms()),
+            wall_clock: WallClock::new(Tim2Monotonic::ticks_per_ms()),
+            led_strip_data: [rtic_stm32::color::BLACK; NUM_LEDS],
+            led_strip_current: [ZoneReport::default(); 4],
+            pipeline: Pipeline::new(),
+            itm: cp.ITM,
+            cpu_load: CpuLoad::new(Tim2Monotonic::millis(CPU_LOAD_WINDOW_MS)),
         }
     }
 
@@ -270,45 +469,587 @@ const APP: () = {
     //     cx.resources.delta.lock(|x: &mut i32| *x = delta);
     // }
 
-    #[task(schedule=[refresh_display], resources = [disp, led_strip_current], priority = 1)]
+    #[task(schedule=[refresh_display], resources = [disp, led_strip_current, led_strip_data, player, arcade, rtc, wall_clock, itm, led_strip_stats, display_stats, cpu_load], priority = 1)]
     fn refresh_display(mut cx: refresh_display::Context) {
-        let mut text = String::<U32>::new();
+        static mut REFRESHES: u32 = 0;
+        static mut PREVIEWING: bool = false;
+        // LED strip frames at the last update of the frame rate
+        static mut FRAMES: u32 = 0;
+        static mut FPS: u32 = 0;
+        let probe = cx.resources.display_stats.begin(cx.scheduled);
+        let cycles_per_us = stats::cycles(Tim2Monotonic::millis(1)) / 1_000;
 
-        let a = cx.resources.led_strip_current.lock(|x| x.clone());
+        let led_strip_stats = cx.resources.led_strip_stats.lock(|s| *s);
+        let cpu_load = cx.resources.cpu_load.lock(|l| l.permille());
+        let display_stats = &*cx.resources.display_stats;
+
+        let mut text = String::<U32>::new();
+        let page = *REFRESHES / (DISPLAY_PAGE_SECS * REFRESH_DISPLAY_HZ) % DISPLAY_PAGES;
+        if *REFRESHES % REFRESH_DISPLAY_HZ == 0 {
+            let frames = led_strip_stats.exec.count();
+            *FPS = frames.wrapping_sub(*FRAMES);
+            *FRAMES = frames;
+        }
+        let game = cx.resources.arcade.lock(|a| {
+            a.current_name()
+                .map(|name| (name, a.score(), a.high_score(), a.is_over()))
+        });
+        let preview = game.is_none() && page == PREVIEW_PAGE;
+        if preview != *PREVIEWING {
+            // the preview takes the whole display, the header is back after it
+            cx.resources.disp.clear();
+            if !preview {
+                draw_header(cx.resources.disp);
+            }
+            *PREVIEWING = preview;
+        }
 
-        for (i, c) in a.iter().enumerate() {
+        if let Some((_, score, high_score, over)) = game {
+            // the score replaces the other pages while playing
             text.clear();
+            write!(&mut text, "score {}", score).unwrap();
+            cx.resources.disp.write(&text, Some(1));
+            text.clear();
+            write!(&mut text, "best {}", high_score).unwrap();
+            cx.resources.disp.write(&text, Some(2));
+            cx.resources
+                .disp
+                .write(if over { "game over" } else { "" }, Some(3));
+            cx.resources.disp.write("", Some(4));
+            cx.resources.disp.write("", Some(5));
+        } else if preview {
+            let leds = cx.resources.led_strip_data.lock(|d| *d);
+            Preview::new(&hex::PANEL, &leds, Point::zero())
+                .draw(cx.resources.disp)
+                .unwrap();
+        } else if page == TIMING_PAGE {
+            let us = |c: u32| c / cycles_per_us;
+            let exec = &led_strip_stats.exec;
+            let late = &led_strip_stats.lateness;
+            let jitter = &led_strip_stats.jitter;
+            let lines: [(&str, u32, u32, u32); 4] = [
+                ("led", us(exec.min()), us(exec.avg()), us(exec.max())),
+                ("late", us(late.min()), us(late.avg()), us(late.max())),
+                ("jit", us(jitter.min()), us(jitter.avg()), us(jitter.max())),
+                (
+                    "disp",
+                    us(display_stats.exec.min()),
+                    us(display_stats.exec.avg()),
+                    us(display_stats.exec.max()),
+                ),
+            ];
+            for (i, (name, min, avg, max)) in lines.iter().enumerate() {
+                text.clear();
+                write!(&mut text, "{} {}/{}/{}", name, min, avg, max).unwrap();
+                cx.resources.disp.write(&text, Some(1 + i as i32));
+            }
+            text.clear();
+            write!(
+                &mut text,
+                "ovr {} {} cpu {}.{}%",
+                led_strip_stats.overruns,
+                display_stats.overruns,
+                cpu_load / 10,
+                cpu_load % 10
+            )
+            .unwrap();
+            cx.resources.disp.write(&text, Some(5));
+        } else {
+            let a = cx.resources.led_strip_current.lock(|x| x.clone());
 
-            write!(&mut text, "I({}): {}", i, c).unwrap();
-            cx.resources.disp.write(&text, Some(1 + i as i32));
+            for (i, c) in a.iter().enumerate() {
+                text.clear();
+
+                write!(&mut text, "I({}): {} ({})", i, c.output_ma, c.requested_ma).unwrap();
+                cx.resources.disp.write(&text, Some(1 + i as i32));
+            }
+            text.clear();
+            write!(&mut text, "{:?}", cx.scheduled).unwrap();
+            cx.resources.disp.write(&text, Some(5));
+        }
+        let name = match game {
+            Some((name, ..)) => name,
+            None => cx.resources.player.lock(|p| p.current_name()),
+        };
+        let time = cx
+            .resources
+            .rtc
+            .lock(|rtc| rtc.as_ref().filter(|rtc| rtc.is_set()).map(Rtc::now));
+        if preview {
+            // beside the LEDs
+            let x = Preview::size(&hex::PANEL).width as i32 + 2;
+            cx.resources.disp.write_at(name, x, 0);
+            text.clear();
+            write!(&mut text, "{} fps", *FPS).unwrap();
+            cx.resources.disp.write_at(&text, x, 1);
+        } else {
+            cx.resources.disp.write(name, Some(6));
+            text.clear();
+            match time {
+                Some(t) => write!(
+                    &mut text,
+                    "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+                    t.year, t.month, t.day, t.hour, t.minute, t.second
+                )
+                .unwrap(),
+                None => text.push_str("time not set").unwrap(),
+            }
+            cx.resources.disp.write(&text, Some(7));
         }
-        text.clear();
-        write!(&mut text, "{:?}", cx.scheduled).unwrap();
-        cx.resources.disp.write(&text, Some(5));
         cx.resources.disp.flush().unwrap();
-        cx.schedule
-            .refresh_display(cx.scheduled + REFRESH_DISPLAY_PERIOD.cycles())
-            .unwrap();
+
+        // the wall clock runs on with the monotonic timer in between
+        if let Some(t) = time {
+            let now = cx.scheduled;
+            cx.resources
+                .wall_clock
+                .lock(|c| c.sync(now, t.seconds_of_day()));
+        }
+
+        if *REFRESHES % (STATS_REPORT_SECS * REFRESH_DISPLAY_HZ) == 0 {
+            report_stats(
+                cx.resources.itm,
+                &led_strip_stats,
+                display_stats,
+                cpu_load,
+                cycles_per_us,
+            );
+        }
+        *REFRESHES = REFRESHES.wrapping_add(1);
+
+        let period = Tim2Monotonic::period(REFRESH_DISPLAY_HZ);
+        let busy = cx.resources.display_stats.end(probe, period);
+        cx.resources.cpu_load.lock(|l| l.add_busy(busy));
+        cx.schedule.refresh_display(cx.scheduled + period).unwrap();
+    }
+    #[task(binds = TIM2, priority = 3)]
+    fn tim2(_: tim2::Context) {
+        Tim2Monotonic::on_interrupt();
+    }
+
+    #[task(binds = DMA1_CH3, resources = [led_strip_dev], priority = 3)]
+    fn led_strip_dma(cx: led_strip_dma::Context) {
+        cx.resources.led_strip_dev.on_interrupt();
     }
-    #[task(schedule=[refresh_led_strip], resources = [led_strip_dev, rainbow, led_strip_data, led_strip_current], priority = 3)]
+
+    // rendering runs below the DMA interrupt, the next frame is prepared while the last one is sent
+    #[task(schedule=[refresh_led_strip], resources = [led_strip_dev, player, arcade, auto_switch, timeline, anim_clock, wall_clock, led_strip_data, led_strip_current, pipeline, led_strip_stats, cpu_load], priority = 2)]
     fn refresh_led_strip(mut cx: refresh_led_strip::Context) {
-        for i in 0..291 {
-            cx.resources.led_strip_data[i] = cx.resources.rainbow.next().unwrap();
+        let probe = cx.resources.led_strip_stats.begin(cx.scheduled);
+        let now = cx.resources.anim_clock.tick(cx.scheduled);
+        let player = &mut *cx.resources.player;
+        let arcade = &mut *cx.resources.arcade;
+        let timeline = &mut *cx.resources.timeline;
+        if let Some(t) = timeline {
+            t.update(now, player);
+            if t.is_over(now) {
+                *timeline = None;
+            }
+        } else if *cx.resources.auto_switch
+            && now.wrapping_sub(player.started()) >= EFFECT_DURATION_MS
+            && !player.is_fading()
+        {
+            player.next(now, EFFECT_FADE_MS);
+        }
+        if let Some(ms) = cx.resources.wall_clock.ms_of_day(cx.scheduled) {
+            if let Some(clock) = player.registry().find("clock") {
+                player
+                    .registry_mut()
+                    .get_mut(clock)
+                    .control(Control::Time(ms));
+            }
         }
+        // the panel shows the player or the arcade
+        let panel: &mut dyn Source = if arcade.is_active() { arcade } else { player };
+        *cx.resources.led_strip_current =
+            cx.resources
+                .pipeline
+                .render(panel, now, cx.resources.led_strip_data);
 
-        *cx.resources.led_strip_current = estimate_current(cx.resources.led_strip_data);
+        let data = &*cx.resources.led_strip_data;
         cx.resources
             .led_strip_dev
-            .write(cx.resources.led_strip_data.iter().cloned())
+            .lock(|dev| dev.write(data.iter().cloned()))
+            .unwrap();
+
+        let period = Tim2Monotonic::period(REFRESH_LED_STRIP_HZ);
+        let busy = cx.resources.led_strip_stats.end(probe, period);
+        cx.resources.cpu_load.add_busy(busy);
+        cx.schedule
+            .refresh_led_strip(cx.scheduled + period)
             .unwrap();
+    }
 
+    #[task(schedule = [game_tick], resources = [arcade, anim_clock], priority = 2)]
+    fn game_tick(cx: game_tick::Context) {
+        let now = cx.resources.anim_clock.tick(cx.scheduled);
+        cx.resources.arcade.update(now);
         cx.schedule
-            .refresh_led_strip(cx.scheduled + REFRESH_LED_STRIP_PERIOD.cycles())
+            .game_tick(cx.scheduled + Tim2Monotonic::period(GAME_TICK_HZ))
             .unwrap();
     }
 
+    #[task(binds = EXTI15_10, schedule = [button_settled], resources = [button], priority = 2)]
+    fn button(cx: button::Context) {
+        if cx.resources.button.check_interrupt() {
+            // if we don't clear this bit, the ISR would trigger indefinitely
+            cx.resources.button.clear_interrupt_pending_bit();
+        }
+        // fails while a check is pending, that one covers this edge too
+        let settled = Tim2Monotonic::now() + Tim2Monotonic::millis(BUTTON_DEBOUNCE_MS);
+        cx.schedule.button_settled(settled).ok();
+    }
+
+    // short presses go to the current game or effect, a long press switches
+    // to the next game
+    #[task(resources = [button, player, arcade, anim_clock], priority = 2)]
+    fn button_settled(cx: button_settled::Context) {
+        static mut PRESSED_AT: Option<monotonic::Instant> = None;
+
+        // the button pulls the pin low
+        let pressed = cx.resources.button.is_low().unwrap();
+        let held = match (pressed, *PRESSED_AT) {
+            (true, None) => {
+                *PRESSED_AT = Some(cx.scheduled);
+                return;
+            }
+            (false, Some(at)) => cx.scheduled.duration_since(at),
+            // bounced back
+            _ => return,
+        };
+        *PRESSED_AT = None;
+
+        let arcade = cx.resources.arcade;
+        if held >= Tim2Monotonic::millis(LONG_PRESS_MS) {
+            arcade.next(cx.resources.anim_clock.now_ms());
+        } else if arcade.is_active() {
+            arcade.input(game::Input::Press);
+        } else {
+            cx.resources.player.control(Control::Trigger);
+        }
+    }
+
+    // applies the time-of-day rules, once the clock is set
+    #[task(schedule = [run_schedule], resources = [rtc, scheduler, player, anim_clock, auto_switch, pipeline], priority = 1)]
+    fn run_schedule(mut cx: run_schedule::Context) {
+        let time = cx
+            .resources
+            .rtc
+            .lock(|rtc| rtc.as_ref().filter(|rtc| rtc.is_set()).map(Rtc::now));
+        if let Some(t) = time {
+            let changes = cx
+                .resources
+                .scheduler
+                .lock(|s| s.update(t.seconds_of_day()));
+            let now = cx.resources.anim_clock.lock(|c| c.now_ms());
+            let mut auto_switch = cx.resources.auto_switch;
+            cx.resources.player.lock(|player| {
+                if let Some(effect) = changes.effect {
+                    player.switch_to(effect as usize, now, EFFECT_FADE_MS);
+                    auto_switch.lock(|a| *a = false);
+                }
+                if let Some((_, palette)) = changes
+                    .palette
+                    .and_then(|p| color::PALETTES.get(p as usize))
+                {
+                    player.fade_palette(palette, now, changes.palette_fade_ms);
+                }
+            });
+            if let Some(brightness) = changes.brightness {
+                cx.resources.pipeline.lock(|p| p.brightness = brightness);
+            }
+        }
+        cx.schedule
+            .run_schedule(cx.scheduled + Tim2Monotonic::period(SCHEDULE_HZ))
+            .unwrap();
+    }
+
+    // writing the flash stalls everything, so it waits until the strip is
+    // idle, the next frame is late then but not garbled
+    #[task(schedule = [save_schedule], resources = [scheduler, settings, led_strip_dev], priority = 1)]
+    fn save_schedule(mut cx: save_schedule::Context) {
+        let mut words = [0; MAX_RULES];
+        let len = cx.resources.scheduler.lock(|s| {
+            for (word, rule) in words.iter_mut().zip(s.rules()) {
+                *word = rule.encode();
+            }
+            s.rules().len()
+        });
+        let settings = cx.resources.settings;
+        let saved = cx.resources.led_strip_dev.lock(|dev| {
+            if dev.is_busy() {
+                return false;
+            }
+            // on failure the rules still apply until the next reset
+            settings.write(&words[..len]).ok();
+            true
+        });
+        if !saved {
+            cx.schedule
+                .save_schedule(cx.scheduled + Tim2Monotonic::millis(SAVE_RETRY_MS))
+                .unwrap();
+        }
+    }
+
+    // answers are queued and sent from the same interrupt as the port gets
+    // ready, so long answers don't hold up the LED strip
+    #[task(binds = USART2, schedule = [save_schedule], resources = [command_serial, command_line, replies, player, arcade, seeder, rtc, scheduler, anim_clock, auto_switch, timeline, pipeline], priority = 2)]
+    fn serial_command(cx: serial_command::Context) {
+        let serial = cx.resources.command_serial;
+        let replies = cx.resources.replies;
+        let command_line = cx.resources.command_line;
+        let line = match serial.read() {
+            Ok(byte) => command_line.push(byte),
+            // overrun, framing or noise errors, the line misses bytes
+            Err(nb::Error::Other(_)) => {
+                command_line.discard();
+                None
+            }
+            // the port is only ready to send
+            Err(nb::Error::WouldBlock) => None,
+        };
+        if let Some(line) = line {
+            let cmd = Command::parse(line);
+            let rules_changed = matches!(cmd, Ok(Command::Rule(_)) | Ok(Command::Unrule(_)));
+            let result = match cmd {
+                Ok(cmd) => execute(
+                    cmd,
+                    Controls {
+                        player: cx.resources.player,
+                        arcade: cx.resources.arcade,
+                        seeder: cx.resources.seeder,
+                        rtc: cx.resources.rtc,
+                        scheduler: cx.resources.scheduler,
+                        anim_clock: cx.resources.anim_clock,
+                        auto_switch: cx.resources.auto_switch,
+                        timeline: cx.resources.timeline,
+                        pipeline: cx.resources.pipeline,
+                    },
+                    replies,
+                ),
+                Err(e) => Err(e),
+            };
+            if result.is_ok() && rules_changed {
+                // fails while a save is pending, that one saves the new rules too
+                cx.schedule
+                    .save_schedule(cx.start + Tim2Monotonic::millis(SAVE_DELAY_MS))
+                    .ok();
+            }
+            match result {
+                Ok(()) => writeln!(replies, "ok\r").ok(),
+                Err(e) => writeln!(replies, "error: {}\r", e).ok(),
+            };
+        }
+
+        while let Some(byte) = replies.peek() {
+            if serial.write(byte).is_err() {
+                break;
+            }
+            replies.pop();
+        }
+        if replies.is_empty() {
+            serial.unlisten(serial::Event::Txe);
+        } else {
+            serial.listen(serial::Event::Txe);
+        }
+    }
+
     extern "C" {
         fn COMP();
         fn SDMMC1();
     }
 };
+
+// Sends the task statistics over ITM port 0 (see openocd.gdb). Nothing is sent
+// if no debugger enabled the port, writing would block forever otherwise.
+fn report_stats(
+    itm: &mut ITM,
+    led_strip: &TaskStats,
+    display: &TaskStats,
+    cpu_load: u16,
+    cycles_per_us: u32,
+) {
+    const ITM_TCR_ITMENA: u32 = 1;
+    if itm.tcr.read() & ITM_TCR_ITMENA == 0 || itm.ter[0].read() & 1 == 0 {
+        return;
+    }
+    let mut text = String::<U128>::new();
+    let stim = &mut itm.stim[0];
+    for (name, stats) in [("led_strip", led_strip), ("display", display)].iter() {
+        text.clear();
+        // a truncated line is better than none
+        stats.write_summary(&mut text, name, cycles_per_us).ok();
+        iprintln!(stim, "{}", text);
+    }
+    iprintln!(stim, "cpu: {}.{}%", cpu_load / 10, cpu_load % 10);
+}
+
+// Everything the commands can change.
+struct Controls<'a> {
+    player: &'a mut Player<'static, NUM_EFFECTS>,
+    arcade: &'a mut Arcade<'static, 2>,
+    seeder: &'a mut Seeder<Rng>,
+    rtc: &'a mut Option<Rtc>,
+    scheduler: &'a mut Scheduler<MAX_RULES>,
+    anim_clock: &'a mut AnimationClock<monotonic::Instant>,
+    auto_switch: &'a mut bool,
+    timeline: &'a mut Option<Timeline<'static>>,
+    pipeline: &'a mut Pipeline,
+}
+
+// Selecting an effect (or showing a text) stops switching effects
+// automatically, `next` starts it again.
+fn execute(
+    cmd: Command,
+    controls: Controls,
+    replies: &mut Replies<REPLY_LEN>,
+) -> Result<(), command::Error> {
+    let Controls {
+        player,
+        arcade,
+        seeder,
+        rtc,
+        scheduler,
+        anim_clock,
+        auto_switch,
+        timeline,
+        pipeline,
+    } = controls;
+    let now = anim_clock.now_ms();
+    match cmd {
+        Command::Effect(name) => {
+            if !player.switch_by_name(name, now, EFFECT_FADE_MS) {
+                return Err(command::Error::InvalidArgument);
+            }
+            *auto_switch = false;
+        }
+        Command::Next => {
+            player.next(now, EFFECT_FADE_MS);
+            *auto_switch = true;
+        }
+        Command::Brightness(b) => player.params.brightness = b,
+        Command::Ambient(brightness) => pipeline.set_ambient(brightness),
+        Command::Speed(s) => player.params.speed = s,
+        Command::Palette(name) => {
+            let palette = color::find_palette(name).ok_or(command::Error::InvalidArgument)?;
+            player.fade_palette(palette, now, EFFECT_FADE_MS);
+        }
+        Command::Text(text) => {
+            let marquee = player.registry().find("marquee").unwrap();
+            player
+                .registry_mut()
+                .get_mut(marquee)
+                .control(Control::Text(text));
+            player.switch_to(marquee, now, EFFECT_FADE_MS);
+            *auto_switch = false;
+        }
+        Command::Pause => anim_clock.pause(),
+        Command::Resume => anim_clock.resume(),
+        Command::Rate(rate) => anim_clock.set_speed(rate),
+        Command::Step(ms) => {
+            anim_clock.step(ms.unwrap_or(1_000 / REFRESH_LED_STRIP_HZ));
+        }
+        Command::Seed(seed) => {
+            seeder.set_mode(seed.map_or(SeedMode::Entropy, SeedMode::Fixed));
+            player.registry_mut().reseed(|| seeder.next_seed());
+            player.restart(now);
+        }
+        Command::Play(Some(name)) => {
+            if !arcade.start_by_name(name, now) {
+                return Err(command::Error::InvalidArgument);
+            }
+        }
+        Command::Play(None) => arcade.stop(),
+        Command::Show(Some(name)) => {
+            let &(_, show) = SHOWS
+                .iter()
+                .find(|(n, _)| *n == name)
+                .ok_or(command::Error::InvalidArgument)?;
+            *timeline = Some(Timeline::new(show, now));
+            *auto_switch = false;
+        }
+        Command::Show(None) => {
+            // back to the parameters the tracks animate, the palette stays
+            if timeline.take().is_some() {
+                let defaults = Params::default();
+                player.params.brightness = defaults.brightness;
+                player.params.speed = defaults.speed;
+                player.params.palette_offset = defaults.palette_offset;
+                player.params.hue_offset = defaults.hue_offset;
+            }
+        }
+        Command::Input(input) => arcade.input(input),
+        Command::Time(hour, minute, second) => {
+            let rtc = rtc.as_mut().ok_or(command::Error::Unavailable)?;
+            let mut time = rtc.now();
+            time.hour = hour;
+            time.minute = minute;
+            time.second = second;
+            rtc.set(&time).map_err(|_| command::Error::Unavailable)?;
+        }
+        Command::Date(year, month, day) => {
+            let rtc = rtc.as_mut().ok_or(command::Error::Unavailable)?;
+            let mut time = rtc.now();
+            time.year = year;
+            time.month = month;
+            time.day = day;
+            rtc.set(&time).map_err(|_| command::Error::Unavailable)?;
+        }
+        Command::Rule(spec) => {
+            let invalid = command::Error::InvalidArgument;
+            let effect = match spec.effect {
+                Some(name) => Some(player.registry().find(name).ok_or(invalid)? as u8),
+                None => None,
+            };
+            let palette = match spec.palette {
+                Some(name) => Some(
+                    color::PALETTES
+                        .iter()
+                        .position(|(n, _)| *n == name)
+                        .ok_or(invalid)? as u8,
+                ),
+                None => None,
+            };
+            let rule = Rule {
+                at: spec.at,
+                fade_minutes: spec.fade_minutes,
+                effect,
+                palette,
+                brightness: spec.brightness,
+            };
+            if !scheduler.add(rule) {
+                return Err(command::Error::NoRoom);
+            }
+        }
+        Command::Rules => {
+            let registry = player.registry();
+            for (i, rule) in scheduler.rules().iter().enumerate() {
+                write!(replies, "{}: {:02}:{:02}", i, rule.at / 60, rule.at % 60).ok();
+                // rules saved by an older firmware may name missing effects
+                if let Some(effect) = rule.effect.filter(|e| (*e as usize) < registry.len()) {
+                    write!(replies, " effect {}", registry.name(effect as usize)).ok();
+                }
+                if let Some((name, _)) = rule.palette.and_then(|p| color::PALETTES.get(p as usize))
+                {
+                    write!(replies, " palette {}", name).ok();
+                }
+                if let Some(brightness) = rule.brightness {
+                    write!(replies, " brightness {}", brightness).ok();
+                }
+                writeln!(replies, " fade {}\r", rule.fade_minutes).ok();
+            }
+        }
+        Command::Unrule(Some(index)) => {
+            if !scheduler.remove(index) {
+                return Err(command::Error::InvalidArgument);
+            }
+        }
+        Command::Unrule(None) => scheduler.clear(),
+        Command::Help => {
+            for line in command::HELP.iter() {
+                writeln!(replies, "{}\r", line).ok();
+            }
+        }
+    }
+    Ok(())
+}
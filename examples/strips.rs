@@ -0,0 +1,199 @@
+#![no_main]
+#![no_std]
+
+// The hex panel split over two WS2812 chains, the first half on SPI1 (PA5/6/7),
+// the second half on SPI3 (PB3/4/5), wired from the far end.
+
+extern crate panic_halt;
+
+extern crate stm32l4xx_hal as hal;
+use rtic_stm32::clock::AnimationClock;
+use rtic_stm32::effect::{Player, Registry};
+use rtic_stm32::effects::{Plasma, RainbowEffect};
+use rtic_stm32::hex;
+use rtic_stm32::monotonic::{self, Tim2Monotonic};
+use rtic_stm32::output::{MultiOutput, Route};
+use rtic_stm32::ws2812_dma::{self, SpiTxDma, Ws2812Dma};
+use smart_leds::RGB8;
+
+use hal::{
+    gpio::{Alternate, Floating, Input, AF5, AF6, PA5, PA6, PA7, PB3, PB4, PB5},
+    prelude::*,
+    spi::Spi,
+    stm32::{SPI1, SPI3},
+};
+use ws2812_spi as ws2812;
+
+const REFRESH_LED_STRIP_HZ: u32 = 40;
+
+// resolution of the monotonic timer
+const TICK_HZ: u32 = 1_000_000;
+
+const NUM_LEDS: usize = 291;
+const FIRST_CHAIN_LEDS: usize = 150;
+const SECOND_CHAIN_LEDS: usize = NUM_LEDS - FIRST_CHAIN_LEDS;
+
+const EFFECT_DURATION_MS: u32 = 20_000;
+const EFFECT_FADE_MS: u32 = 2_000;
+
+static ROUTES: [Route; 2] = [
+    Route::new(0, 0..FIRST_CHAIN_LEDS),
+    Route::reversed(1, FIRST_CHAIN_LEDS..NUM_LEDS),
+];
+
+type Chain1 = Ws2812Dma<
+    Spi<
+        SPI1,
+        (
+            PA5<Alternate<AF5, Input<Floating>>>,
+            PA6<Alternate<AF5, Input<Floating>>>,
+            PA7<Alternate<AF5, Input<Floating>>>,
+        ),
+    >,
+>;
+type Chain2 = Ws2812Dma<
+    Spi<
+        SPI3,
+        (
+            PB3<Alternate<AF6, Input<Floating>>>,
+            PB4<Alternate<AF6, Input<Floating>>>,
+            PB5<Alternate<AF6, Input<Floating>>>,
+        ),
+    >,
+>;
+
+#[rtic::app(device = hal::stm32, peripherals = true, monotonic = rtic_stm32::monotonic::Tim2Monotonic)]
+const APP: () = {
+    struct Resources {
+        outputs: MultiOutput<(Chain1, Chain2)>,
+        player: Player<'static, 2>,
+        anim_clock: AnimationClock<monotonic::Instant>,
+        led_strip_data: [RGB8; NUM_LEDS],
+    }
+
+    #[init(schedule = [refresh_led_strip])]
+    fn init(cx: init::Context) -> init::LateResources {
+        static mut RAINBOW: RainbowEffect = RainbowEffect::new(1);
+        static mut PLASMA: Plasma = Plasma::new(&hex::PANEL);
+        static mut FADE_BUFFER: [RGB8; NUM_LEDS] = [rtic_stm32::color::BLACK; NUM_LEDS];
+        static mut CHAIN1_BUFFER0: [u8; ws2812_dma::buffer_len(FIRST_CHAIN_LEDS)] =
+            [0; ws2812_dma::buffer_len(FIRST_CHAIN_LEDS)];
+        static mut CHAIN1_BUFFER1: [u8; ws2812_dma::buffer_len(FIRST_CHAIN_LEDS)] =
+            [0; ws2812_dma::buffer_len(FIRST_CHAIN_LEDS)];
+        static mut CHAIN2_BUFFER0: [u8; ws2812_dma::buffer_len(SECOND_CHAIN_LEDS)] =
+            [0; ws2812_dma::buffer_len(SECOND_CHAIN_LEDS)];
+        static mut CHAIN2_BUFFER1: [u8; ws2812_dma::buffer_len(SECOND_CHAIN_LEDS)] =
+            [0; ws2812_dma::buffer_len(SECOND_CHAIN_LEDS)];
+
+        let mut rcc = cx.device.RCC.constrain();
+        let mut flash = cx.device.FLASH.constrain();
+        let mut pwr = cx.device.PWR.constrain(&mut rcc.apb1r1);
+
+        let clocks = rcc
+            .cfgr
+            .sysclk(64.mhz())
+            .pclk1(16.mhz())
+            .pclk2(64.mhz())
+            .freeze(&mut flash.acr, &mut pwr);
+        Tim2Monotonic::start(cx.device.TIM2, TICK_HZ, clocks, &mut rcc.apb1r1);
+
+        // ================================================================================
+        // first chain on SPI1
+        let mut gpioa = cx.device.GPIOA.split(&mut rcc.ahb2);
+        let pins = (
+            gpioa.pa5.into_af5(&mut gpioa.moder, &mut gpioa.afrl),
+            gpioa.pa6.into_af5(&mut gpioa.moder, &mut gpioa.afrl),
+            gpioa.pa7.into_af5(&mut gpioa.moder, &mut gpioa.afrl),
+        );
+        let spi = Spi::spi1(
+            cx.device.SPI1,
+            pins,
+            ws2812::MODE,
+            3_000_000.hz(),
+            clocks,
+            &mut rcc.apb2,
+        );
+        let chain1 = Ws2812Dma::new(
+            spi,
+            SpiTxDma::spi1(&mut rcc.ahb1),
+            CHAIN1_BUFFER0,
+            CHAIN1_BUFFER1,
+        );
+
+        // ================================================================================
+        // second chain on SPI3
+        let mut gpiob = cx.device.GPIOB.split(&mut rcc.ahb2);
+        let pins = (
+            gpiob.pb3.into_af6(&mut gpiob.moder, &mut gpiob.afrl),
+            gpiob.pb4.into_af6(&mut gpiob.moder, &mut gpiob.afrl),
+            gpiob.pb5.into_af6(&mut gpiob.moder, &mut gpiob.afrl),
+        );
+        let spi = Spi::spi3(
+            cx.device.SPI3,
+            pins,
+            ws2812::MODE,
+            3_000_000.hz(),
+            clocks,
+            &mut rcc.apb1r1,
+        );
+        let chain2 = Ws2812Dma::new(
+            spi,
+            SpiTxDma::spi3(&mut rcc.ahb1),
+            CHAIN2_BUFFER0,
+            CHAIN2_BUFFER1,
+        );
+
+        let player = Player::new(
+            Registry::new([("rainbow", RAINBOW), ("plasma", PLASMA)]),
+            FADE_BUFFER,
+        );
+
+        cx.schedule
+            .refresh_led_strip(cx.start + Tim2Monotonic::period(REFRESH_LED_STRIP_HZ))
+            .unwrap();
+
+        init::LateResources {
+            outputs: MultiOutput::new((chain1, chain2), &ROUTES),
+            player,
+            anim_clock: AnimationClock::new(Tim2Monotonic::ticks_per_ms()),
+            led_strip_data: [rtic_stm32::color::BLACK; NUM_LEDS],
+        }
+    }
+
+    #[task(binds = TIM2, priority = 3)]
+    fn tim2(_: tim2::Context) {
+        Tim2Monotonic::on_interrupt();
+    }
+
+    #[task(binds = DMA1_CH3, resources = [outputs], priority = 3)]
+    fn chain1_dma(cx: chain1_dma::Context) {
+        cx.resources.outputs.on_interrupt(0);
+    }
+
+    #[task(binds = DMA2_CH2, resources = [outputs], priority = 3)]
+    fn chain2_dma(cx: chain2_dma::Context) {
+        cx.resources.outputs.on_interrupt(1);
+    }
+
+    #[task(schedule = [refresh_led_strip], resources = [outputs, player, anim_clock, led_strip_data], priority = 2)]
+    fn refresh_led_strip(mut cx: refresh_led_strip::Context) {
+        let now = cx.resources.anim_clock.tick(cx.scheduled);
+        let player = &mut *cx.resources.player;
+        if now.wrapping_sub(player.started()) >= EFFECT_DURATION_MS && !player.is_fading() {
+            player.next(now, EFFECT_FADE_MS);
+        }
+        player.render(now, cx.resources.led_strip_data);
+
+        // dropped while either chain is still sending, so both chains stay in sync
+        let data = &*cx.resources.led_strip_data;
+        cx.resources.outputs.lock(|o| o.write(data)).ok();
+
+        cx.schedule
+            .refresh_led_strip(cx.scheduled + Tim2Monotonic::period(REFRESH_LED_STRIP_HZ))
+            .unwrap();
+    }
+
+    extern "C" {
+        fn COMP();
+    }
+};
@@ -0,0 +1,143 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+extern crate stm32l4xx_hal as hal;
+use rtic_stm32::monotonic::Tim2Monotonic;
+use rtic_stm32::prelude::*;
+
+use core::fmt::Write;
+use hal::{
+    device::I2C1,
+    gpio::{Alternate, OpenDrain, Output, PB6, PB7},
+    i2c::I2c,
+    prelude::*,
+};
+use heapless::consts::*;
+use heapless::String;
+use ssd1306::{mode::GraphicsMode, prelude::*, Builder, I2CDIBuilder};
+
+// resolution of the monotonic timer
+const TICK_HZ: u32 = 10_000;
+
+#[rtic::app(device = hal::stm32, peripherals = true, monotonic = rtic_stm32::monotonic::Tim2Monotonic)]
+const APP: () = {
+    struct Resources {
+        disp: GraphicsMode<
+            I2CInterface<
+                I2c<
+                    I2C1,
+                    (
+                        PB6<Alternate<hal::gpio::AF4, Output<OpenDrain>>>,
+                        PB7<Alternate<hal::gpio::AF4, Output<OpenDrain>>>,
+                    ),
+                >,
+            >,
+            DisplaySize128x64,
+        >,
+        #[init(0)]
+        hours: u32,
+    }
+
+    #[init(schedule = [refresh_display, hourly])]
+    fn init(cx: init::Context) -> init::LateResources {
+        let mut rcc = cx.device.RCC.constrain();
+        let mut flash = cx.device.FLASH.constrain();
+        let mut pwr = cx.device.PWR.constrain(&mut rcc.apb1r1);
+
+        let clocks = rcc
+            .cfgr
+            .sysclk(64.mhz())
+            .pclk1(16.mhz())
+            .pclk2(64.mhz())
+            .freeze(&mut flash.acr, &mut pwr);
+
+        // ================================================================================
+        // Set up the 64 bit monotonic timer
+        Tim2Monotonic::start(cx.device.TIM2, TICK_HZ, clocks, &mut rcc.apb1r1);
+
+        // ================================================================================
+        // set up OLED i2c
+        let mut gpiob = cx.device.GPIOB.split(&mut rcc.ahb2);
+        let mut scl = gpiob
+            .pb6
+            .into_open_drain_output(&mut gpiob.moder, &mut gpiob.otyper);
+        scl.internal_pull_up(&mut gpiob.pupdr, true);
+        let scl = scl.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
+        let mut sda = gpiob
+            .pb7
+            .into_open_drain_output(&mut gpiob.moder, &mut gpiob.otyper);
+        sda.internal_pull_up(&mut gpiob.pupdr, true);
+        let sda = sda.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
+
+        let i2c = I2c::i2c1(
+            cx.device.I2C1,
+            (scl, sda),
+            800.khz(),
+            clocks,
+            &mut rcc.apb1r1,
+        );
+
+        let interface = I2CDIBuilder::new().init(i2c);
+        let mut disp: GraphicsMode<_, _> = Builder::new().connect(interface).into();
+        disp.init().unwrap();
+        disp.clear();
+        disp.write("uptime", None);
+        disp.flush().unwrap();
+
+        cx.schedule
+            .refresh_display(cx.start + Tim2Monotonic::secs(1))
+            .unwrap();
+        // way beyond what CYCCNT could schedule
+        cx.schedule
+            .hourly(cx.start + Tim2Monotonic::secs(3600))
+            .unwrap();
+
+        init::LateResources { disp }
+    }
+
+    #[task(binds = TIM2, priority = 3)]
+    fn tim2(_: tim2::Context) {
+        Tim2Monotonic::on_interrupt();
+    }
+
+    #[task(schedule = [hourly], resources = [hours], priority = 2)]
+    fn hourly(cx: hourly::Context) {
+        *cx.resources.hours += 1;
+        cx.schedule
+            .hourly(cx.scheduled + Tim2Monotonic::secs(3600))
+            .unwrap();
+    }
+
+    #[task(schedule = [refresh_display], resources = [disp, hours], priority = 1)]
+    fn refresh_display(mut cx: refresh_display::Context) {
+        let secs = cx.scheduled.as_millis() / 1_000;
+        let hours = cx.resources.hours.lock(|h| *h);
+
+        let mut text = String::<U32>::new();
+        write!(
+            &mut text,
+            "{}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+        .unwrap();
+        cx.resources.disp.write(&text, Some(2));
+
+        text.clear();
+        write!(&mut text, "hours: {}", hours).unwrap();
+        cx.resources.disp.write(&text, Some(3));
+        cx.resources.disp.flush().unwrap();
+
+        cx.schedule
+            .refresh_display(cx.scheduled + Tim2Monotonic::secs(1))
+            .unwrap();
+    }
+
+    extern "C" {
+        fn COMP();
+        fn SDMMC1();
+    }
+};
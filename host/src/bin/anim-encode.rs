@@ -0,0 +1,180 @@
+//! Converts a GIF or a sequence of PNG images into an animation for the
+//! firmware (see `rtic_stm32::anim`).
+//!
+//! ```text
+//! anim-encode [options] <output.hxa> <input.gif | frame.png...>
+//!
+//!     --fps <n>          frame rate, default 25 (GIFs are resampled)
+//!     --loop <frame>     continue with this frame after the last, default 0
+//!     --once             play once and keep showing the last frame
+//!     --layout <layout>  hex (default) or strip:<leds>
+//! ```
+//!
+//! The images are stretched over the layout and every LED gets the average
+//! color of the area it covers.
+
+use hexlife_host::images::{self, Image, Placement};
+use rtic_stm32::{
+    anim::{self, Header},
+    hex,
+};
+use smart_leds::RGB8;
+use std::{error::Error, path::Path, process};
+
+const DEFAULT_FPS: u8 = 25;
+
+struct Options {
+    fps: u8,
+    loop_frame: Option<usize>,
+    placement: Placement,
+    layout: u8,
+    output: String,
+    inputs: Vec<String>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: anim-encode [--fps <n>] [--loop <frame> | --once] [--layout hex|strip:<leds>] \
+         <output.hxa> <input.gif | frame.png...>"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Result<Options, Box<dyn Error>> {
+    let mut options = Options {
+        fps: DEFAULT_FPS,
+        loop_frame: Some(0),
+        placement: Placement::Hex(&hex::PANEL),
+        layout: anim::LAYOUT_HEX_PANEL,
+        output: String::new(),
+        inputs: Vec::new(),
+    };
+    let mut args = std::env::args().skip(1);
+    let mut files = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--fps" => options.fps = value()?.parse()?,
+            "--loop" => options.loop_frame = Some(value()?.parse()?),
+            "--once" => options.loop_frame = None,
+            "--layout" => {
+                let layout = value()?;
+                if layout == "hex" {
+                    options.placement = Placement::Hex(&hex::PANEL);
+                    options.layout = anim::LAYOUT_HEX_PANEL;
+                } else if let Some(leds) = layout.strip_prefix("strip:") {
+                    options.placement = Placement::Strip(leds.parse()?);
+                    options.layout = anim::LAYOUT_STRIP;
+                } else {
+                    return Err(format!("unknown layout {}", layout).into());
+                }
+            }
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg).into()),
+            _ => files.push(arg),
+        }
+    }
+    if files.len() < 2 || options.fps == 0 {
+        usage();
+    }
+    options.output = files.remove(0);
+    options.inputs = files;
+    Ok(options)
+}
+
+/// The input images with their durations in ms.
+fn load(options: &Options) -> Result<Vec<(Image, u32)>, Box<dyn Error>> {
+    let frame_ms = 1000 / options.fps as u32;
+    let mut frames = Vec::new();
+    for input in &options.inputs {
+        let path = Path::new(input);
+        let is_gif = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("gif"));
+        if is_gif {
+            frames.extend(images::load_gif(path)?);
+        } else {
+            frames.push((images::load_png(path)?, frame_ms));
+        }
+    }
+    Ok(frames)
+}
+
+/// Picks the image shown at each frame of the output.
+fn resample(frames: &[(Image, u32)], fps: u8) -> Vec<&Image> {
+    let total_ms: u32 = frames.iter().map(|(_, ms)| ms).sum();
+    let count = (total_ms as u64 * fps as u64 / 1000).max(1) as u32;
+    let mut shown = Vec::new();
+    let mut source = 0;
+    let mut source_end = frames[0].1;
+    for i in 0..count {
+        let t = (i as u64 * 1000 / fps as u64) as u32;
+        while t >= source_end && source + 1 < frames.len() {
+            source += 1;
+            source_end += frames[source].1;
+        }
+        shown.push(&frames[source].0);
+    }
+    shown
+}
+
+fn encode(options: &Options, frames: &[Vec<RGB8>]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if frames.len() >= 0xffff {
+        return Err("too many frames".into());
+    }
+    if let Some(f) = options.loop_frame.filter(|f| *f >= frames.len()) {
+        return Err(format!("loop frame {} is after the last frame", f).into());
+    }
+    let mut header = Header {
+        layout: options.layout,
+        fps: options.fps,
+        led_count: options.placement.led_count() as u16,
+        frame_count: frames.len() as u16,
+        loop_frame: options.loop_frame.map(|f| f as u16),
+        loop_offset: 0,
+    };
+
+    let mut data = vec![0; anim::HEADER_LEN];
+    let mut previous = None;
+    for (i, frame) in frames.iter().enumerate() {
+        let is_loop = Some(i) == options.loop_frame;
+        if is_loop {
+            header.loop_offset = data.len() as u32;
+        }
+        // playback starts over at the first and the loop frame
+        let base = if i == 0 || is_loop { None } else { previous };
+        anim::encode_frame(base, frame, |bytes| data.extend_from_slice(bytes));
+        previous = Some(&frame[..]);
+    }
+    data[..anim::HEADER_LEN].copy_from_slice(&header.to_bytes());
+    Ok(data)
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let options = parse_args()?;
+    let frames = load(&options)?;
+    if frames.is_empty() {
+        return Err("no frames".into());
+    }
+    let leds: Vec<_> = resample(&frames, options.fps)
+        .into_iter()
+        .map(|image| options.placement.sample(image))
+        .collect();
+    let data = encode(&options, &leds)?;
+    std::fs::write(&options.output, &data)?;
+    eprintln!(
+        "{}: {} frames, {} bytes",
+        options.output,
+        leds.len(),
+        data.len()
+    );
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("anim-encode: {}", e);
+        process::exit(1);
+    }
+}
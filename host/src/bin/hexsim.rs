@@ -0,0 +1,165 @@
+//! Renders the effects of the firmware on a PC, to review them without the
+//! hardware.
+//!
+//! ```text
+//! hexsim [options] <output.gif | directory>
+//!
+//!     --effect <name>     the effect to show, default rainbow
+//!     --seconds <s>       length, default 10
+//!     --fps <n>           frame rate of the output, default 20
+//!     --speed <n>         effect speed, 256 is normal
+//!     --brightness <n>    0 to 255
+//!     --palette <name>    see `color::PALETTES`
+//!     --seed <n>          reseed the effects
+//!     --cell <px>         pixels per LED spacing, default 16
+//!     --glow              light around the LEDs
+//!     --list              the names of the effects
+//! ```
+//!
+//! A directory gets a PNG per frame, `frame-0000.png` and up.
+
+use hexlife_host::{
+    images::{self, GifWriter},
+    render::Renderer,
+    sim::{Simulation, REFRESH_HZ},
+};
+use rtic_stm32::{
+    color,
+    effect::Player,
+    hex,
+    installation::{Effects, NUM_LEDS},
+    rng::XorShift32,
+};
+use std::{error::Error, path::Path, process};
+
+struct Options {
+    effect: String,
+    seconds: u32,
+    fps: u32,
+    speed: Option<u16>,
+    brightness: Option<u8>,
+    palette: Option<String>,
+    seed: Option<u32>,
+    cell_px: u32,
+    glow: bool,
+    output: String,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: hexsim [--effect <name>] [--seconds <s>] [--fps <n>] [--speed <n>] \
+         [--brightness <n>] [--palette <name>] [--seed <n>] [--cell <px>] [--glow] \
+         <output.gif | directory>\n       hexsim --list"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Result<Options, Box<dyn Error>> {
+    let mut options = Options {
+        effect: "rainbow".into(),
+        seconds: 10,
+        fps: 20,
+        speed: None,
+        brightness: None,
+        palette: None,
+        seed: None,
+        cell_px: 16,
+        glow: false,
+        output: String::new(),
+    };
+    let mut args = std::env::args().skip(1);
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--effect" => options.effect = value()?,
+            "--seconds" => options.seconds = value()?.parse()?,
+            "--fps" => options.fps = value()?.parse()?,
+            "--speed" => options.speed = Some(value()?.parse()?),
+            "--brightness" => options.brightness = Some(value()?.parse()?),
+            "--palette" => options.palette = Some(value()?),
+            "--seed" => options.seed = Some(value()?.parse()?),
+            "--cell" => options.cell_px = value()?.parse()?,
+            "--glow" => options.glow = true,
+            "--list" => {
+                let mut effects = Effects::new();
+                let registry = effects.registry();
+                for i in 0..registry.len() {
+                    println!("{}", registry.name(i));
+                }
+                process::exit(0);
+            }
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg).into()),
+            _ if output.is_none() => output = Some(arg),
+            _ => usage(),
+        }
+    }
+    if options.fps == 0 || options.fps > REFRESH_HZ {
+        return Err(format!("the frame rate must be 1 to {}", REFRESH_HZ).into());
+    }
+    if options.cell_px == 0 {
+        return Err("the cells need at least a pixel".into());
+    }
+    options.output = output.unwrap_or_else(|| usage());
+    Ok(options)
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let options = parse_args()?;
+    let mut effects = Effects::new();
+    let mut scratch = vec![color::BLACK; NUM_LEDS];
+    let mut player = Player::new(effects.registry(), &mut scratch);
+    if !player.switch_by_name(&options.effect, 0, 0) {
+        return Err(format!("unknown effect {}, see --list", options.effect).into());
+    }
+    if let Some(seed) = options.seed {
+        let mut rng = XorShift32::new(seed);
+        player.registry_mut().reseed(|| rng.next_u32());
+        player.restart(0);
+    }
+    if let Some(speed) = options.speed {
+        player.params.speed = speed;
+    }
+    if let Some(brightness) = options.brightness {
+        player.params.brightness = brightness;
+    }
+    if let Some(name) = &options.palette {
+        player.params.palette =
+            *color::find_palette(name).ok_or_else(|| format!("unknown palette {}", name))?;
+    }
+
+    let mut renderer = Renderer::new(&hex::PANEL);
+    renderer.cell_px = options.cell_px;
+    renderer.glow = options.glow;
+    let (width, height) = renderer.size();
+    let output = Path::new(&options.output);
+    let mut gif = if output.is_dir() {
+        None
+    } else {
+        Some(GifWriter::create(output, width, height)?)
+    };
+
+    let mut sim = Simulation::new(player);
+    let frame_ms = 1000 / options.fps;
+    let frames = options.seconds * options.fps;
+    for i in 0..frames {
+        let image = renderer.render(sim.run_until(i * frame_ms));
+        match &mut gif {
+            Some(gif) => gif.add(&image, frame_ms)?,
+            None => images::save_png(&output.join(format!("frame-{:04}.png", i)), &image)?,
+        }
+    }
+    eprintln!(
+        "{}: {} frames, {}x{}",
+        options.output, frames, width, height
+    );
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("hexsim: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,113 @@
+//! Fits the power model of the firmware (see `rtic_stm32::power`) to currents
+//! measured on a bench supply.
+//!
+//! ```text
+//! power-fit [--leds <n>] <samples.txt>
+//!
+//!     --leds <n>    LEDs on the strip, default 291 (the hex panel)
+//! ```
+//!
+//! Every line of the samples is a frame and the current it drew:
+//!
+//! ```text
+//! # r   g   b   mA     [lit LEDs, default all]
+//! 0     0   0   262
+//! 255   0   0   3790
+//! 0     255 0   3710
+//! 0     0   255 3650
+//! 255   255 255 2405   50
+//! ```
+//!
+//! The lit LEDs show the color, the others are off. At least the four frames
+//! at the top are needed, more samples average out the noise of the meter.
+//! The result is printed as a `PowerModel` for the firmware.
+
+use rtic_stm32::power::Calibration;
+use smart_leds::RGB8;
+use std::{error::Error, fs, process};
+
+const DEFAULT_LEDS: usize = 291;
+
+fn usage() -> ! {
+    eprintln!("usage: power-fit [--leds <n>] <samples.txt>");
+    process::exit(2);
+}
+
+fn parse_args() -> Result<(usize, String), Box<dyn Error>> {
+    let mut leds = DEFAULT_LEDS;
+    let mut input = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--leds" => leds = args.next().ok_or("--leds needs a value")?.parse()?,
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg).into()),
+            _ if input.is_none() => input = Some(arg),
+            _ => usage(),
+        }
+    }
+    if leds == 0 {
+        return Err("the strip needs at least an LED".into());
+    }
+    Ok((leds, input.unwrap_or_else(|| usage())))
+}
+
+/// A frame and the current it was measured to draw, in mA.
+type Sample = (Vec<RGB8>, f32);
+
+/// Parses a line of the samples, `None` for blank lines and comments.
+fn sample(line: &str, leds: usize) -> Result<Option<Sample>, Box<dyn Error>> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 4 && fields.len() != 5 {
+        return Err("expected r g b mA [lit LEDs]".into());
+    }
+    let color = RGB8::new(fields[0].parse()?, fields[1].parse()?, fields[2].parse()?);
+    let measured_ma: f32 = fields[3].parse()?;
+    let lit = match fields.get(4) {
+        Some(n) => n.parse()?,
+        None => leds,
+    };
+    if lit > leds {
+        return Err(format!("{} lit LEDs on a strip of {}", lit, leds).into());
+    }
+    let mut frame = vec![RGB8::default(); leds];
+    for c in &mut frame[..lit] {
+        *c = color;
+    }
+    Ok(Some((frame, measured_ma)))
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let (leds, input) = parse_args()?;
+    let text = fs::read_to_string(&input)?;
+    let mut calibration = Calibration::new();
+    for (i, line) in text.lines().enumerate() {
+        match sample(line, leds) {
+            Ok(Some((frame, measured_ma))) => calibration.add_sample(&frame, measured_ma),
+            Ok(None) => {}
+            Err(e) => return Err(format!("{}:{}: {}", input, i + 1, e).into()),
+        }
+    }
+    let model = calibration.fit().ok_or(
+        "the samples don't determine the model, measure off, red, green and blue frames",
+    )?;
+    eprintln!("{}: {} samples", input, calibration.num_samples());
+    println!("PowerModel {{");
+    println!("    red_ua: {},", model.red_ua);
+    println!("    green_ua: {},", model.green_ua);
+    println!("    blue_ua: {},", model.blue_ua);
+    println!("    quiescent_ua: {},", model.quiescent_ua);
+    println!("}}");
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("power-fit: {}", e);
+        process::exit(1);
+    }
+}
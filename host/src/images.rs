@@ -0,0 +1,204 @@
+//! Loading and saving images, and sampling them onto the LEDs.
+
+use rtic_stm32::hex::Layout;
+use smart_leds::RGB8;
+use std::{error::Error, fs::File, io::BufWriter, path::Path};
+
+/// An RGBA image, 8 bits per channel.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    /// A transparent image.
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            rgba: vec![0; width * height * 4],
+        }
+    }
+
+    /// The pixel at `(x, y)` over black.
+    pub fn pixel(&self, x: usize, y: usize) -> RGB8 {
+        let p = &self.rgba[(y * self.width + x) * 4..][..4];
+        let over_black = |c: u8| (c as u16 * p[3] as u16 / 255) as u8;
+        RGB8::new(over_black(p[0]), over_black(p[1]), over_black(p[2]))
+    }
+
+    /// Average of the pixels in `x0..x1` and `y0..y1`, at least one pixel.
+    pub fn average(&self, x0: usize, x1: usize, y0: usize, y1: usize) -> RGB8 {
+        let x0 = x0.min(self.width - 1);
+        let y0 = y0.min(self.height - 1);
+        let x1 = x1.min(self.width).max(x0 + 1);
+        let y1 = y1.min(self.height).max(y0 + 1);
+        let mut sum = [0u32; 3];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let p = self.pixel(x, y);
+                sum[0] += p.r as u32;
+                sum[1] += p.g as u32;
+                sum[2] += p.b as u32;
+            }
+        }
+        let n = ((x1 - x0) * (y1 - y0)) as u32;
+        RGB8::new((sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8)
+    }
+}
+
+pub fn load_png(path: &Path) -> Result<Image, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    // palettes and low bit depths to 8 bits per channel
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut image = Image::new(width, height);
+    let (color_type, _) = reader.output_color_type();
+    let channels = color_type.samples();
+    for (dst, src) in image.rgba.chunks_mut(4).zip(buf.chunks(channels)) {
+        let rgba = match color_type {
+            png::ColorType::Grayscale => [src[0], src[0], src[0], 255],
+            png::ColorType::GrayscaleAlpha => [src[0], src[0], src[0], src[1]],
+            png::ColorType::RGB => [src[0], src[1], src[2], 255],
+            png::ColorType::RGBA => [src[0], src[1], src[2], src[3]],
+            png::ColorType::Indexed => return Err("indexed PNG was not expanded".into()),
+        };
+        dst.copy_from_slice(&rgba);
+    }
+    Ok(image)
+}
+
+pub fn save_png(path: &Path, image: &Image) -> Result<(), Box<dyn Error>> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&image.rgba)?;
+    Ok(())
+}
+
+/// The frames of a GIF, composed like a browser shows them, with their
+/// durations in ms.
+pub fn load_gif(path: &Path) -> Result<Vec<(Image, u32)>, Box<dyn Error>> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(File::open(path)?)?;
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+
+    let mut screen = Image::new(width, height);
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame()? {
+        let before = screen.clone();
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        let (w, h) = (frame.width as usize, frame.height as usize);
+        let visible = |x: usize, y: usize| left + x < width && top + y < height;
+        for y in 0..h {
+            for x in (0..w).filter(|x| visible(*x, y)) {
+                let src = &frame.buffer[(y * w + x) * 4..][..4];
+                // transparent pixels show the frame before
+                if src[3] != 0 {
+                    let i = ((top + y) * width + left + x) * 4;
+                    screen.rgba[i..i + 4].copy_from_slice(src);
+                }
+            }
+        }
+        // browsers show frames without a delay for 100 ms
+        let ms = match frame.delay {
+            0 | 1 => 100,
+            delay => delay as u32 * 10,
+        };
+        frames.push((screen.clone(), ms));
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in 0..h {
+                    for x in (0..w).filter(|x| visible(*x, y)) {
+                        let i = ((top + y) * width + left + x) * 4;
+                        screen.rgba[i..i + 4].copy_from_slice(&[0; 4]);
+                    }
+                }
+            }
+            gif::DisposalMethod::Previous => screen = before,
+            _ => {}
+        }
+    }
+    Ok(frames)
+}
+
+/// Writes an animated GIF that loops forever, frame by frame.
+pub struct GifWriter {
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: u16,
+    height: u16,
+}
+
+impl GifWriter {
+    pub fn create(path: &Path, width: usize, height: usize) -> Result<Self, Box<dyn Error>> {
+        let (width, height) = (width as u16, height as u16);
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = gif::Encoder::new(file, width, height, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        Ok(GifWriter {
+            encoder,
+            width,
+            height,
+        })
+    }
+
+    /// Adds a frame of the size of the GIF, shown for `ms` (in steps of
+    /// 10 ms).
+    pub fn add(&mut self, image: &Image, ms: u32) -> Result<(), Box<dyn Error>> {
+        let mut rgba = image.rgba.clone();
+        // the palette of each frame is picked from its colors
+        let mut frame = gif::Frame::from_rgba_speed(self.width, self.height, &mut rgba, 10);
+        frame.delay = (ms / 10) as u16;
+        self.encoder.write_frame(&frame)?;
+        Ok(())
+    }
+}
+
+/// Where the LEDs are on an image.
+#[derive(Clone, Copy, Debug)]
+pub enum Placement {
+    /// a row of LEDs across the image
+    Strip(usize),
+    /// the image is stretched over the layout
+    Hex(&'static Layout),
+}
+
+impl Placement {
+    pub fn led_count(&self) -> usize {
+        match self {
+            Placement::Strip(n) => *n,
+            Placement::Hex(layout) => layout.len(),
+        }
+    }
+
+    /// The color of every LED, averaged over the area it covers.
+    pub fn sample(&self, image: &Image) -> Vec<RGB8> {
+        match *self {
+            Placement::Strip(n) => (0..n)
+                .map(|i| {
+                    image.average(
+                        i * image.width / n,
+                        (i + 1) * image.width / n,
+                        0,
+                        image.height,
+                    )
+                })
+                .collect(),
+            Placement::Hex(layout) => (0..layout.len())
+                .map(|i| {
+                    let (xs, ys) = layout.image_area(i, image.width, image.height).unwrap();
+                    image.average(xs.start, xs.end, ys.start, ys.end)
+                })
+                .collect(),
+        }
+    }
+}
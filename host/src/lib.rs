@@ -0,0 +1,3 @@
+pub mod images;
+pub mod render;
+pub mod sim;
@@ -0,0 +1,100 @@
+//! Pictures of the LEDs at their real positions.
+
+use crate::images::Image;
+use rtic_stm32::hex::{self, Layout};
+use smart_leds::RGB8;
+
+/// Color of the LEDs that are off, so the layout stays visible. Lit LEDs
+/// that are darker show it too.
+const UNLIT: f32 = 24.0;
+/// Radius of an LED, in cell spacings.
+const LED_RADIUS: f32 = 0.3;
+/// How far the glow reaches, in cell spacings.
+const GLOW_RADIUS: f32 = 1.5;
+/// Brightness of the glow next to an LED, relative to the LED.
+const GLOW_STRENGTH: f32 = 0.5;
+
+pub struct Renderer {
+    pub layout: &'static Layout,
+    /// pixels per cell spacing
+    pub cell_px: u32,
+    /// light around the LEDs, as it falls on a wall or a diffuser
+    pub glow: bool,
+}
+
+impl Renderer {
+    pub fn new(layout: &'static Layout) -> Self {
+        Renderer {
+            layout,
+            cell_px: 16,
+            glow: false,
+        }
+    }
+
+    /// Size of the pictures in pixels, with a margin of a cell spacing.
+    pub fn size(&self) -> (usize, usize) {
+        let (w, h) = hex::cartesian(self.layout.width() - 1, self.layout.height() - 1);
+        let px = |c: i32| (c as usize + 2 * 16) * self.cell_px as usize / 16;
+        (px(w), px(h))
+    }
+
+    /// Center of the LED `index` in pixels.
+    fn center(&self, index: usize) -> (f32, f32) {
+        let (x, y) = self.layout.position(index).unwrap();
+        let (cx, cy) = hex::cartesian(x, y);
+        let px = |c: i32| (c + 16) as f32 * self.cell_px as f32 / 16.0;
+        (px(cx), px(cy))
+    }
+
+    /// The LEDs (in layout order) on black.
+    pub fn render(&self, leds: &[RGB8]) -> Image {
+        let (width, height) = self.size();
+        let mut light = vec![[0f32; 3]; width * height];
+        let cell = self.cell_px as f32;
+        let reach = if self.glow {
+            GLOW_RADIUS
+        } else {
+            LED_RADIUS + 0.1
+        };
+
+        for (i, led) in leds.iter().enumerate().take(self.layout.len()) {
+            let (cx, cy) = self.center(i);
+            let rgb = [led.r as f32, led.g as f32, led.b as f32];
+            let body = if rgb.iter().all(|c| *c < UNLIT) {
+                [UNLIT; 3]
+            } else {
+                rgb
+            };
+            let x0 = (cx - reach * cell).max(0.0) as usize;
+            let y0 = (cy - reach * cell).max(0.0) as usize;
+            let x1 = ((cx + reach * cell) as usize + 1).min(width);
+            let y1 = ((cy + reach * cell) as usize + 1).min(height);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let dx = x as f32 + 0.5 - cx;
+                    let dy = y as f32 + 0.5 - cy;
+                    // in cell spacings
+                    let d = (dx * dx + dy * dy).sqrt() / cell;
+                    // antialiased over a pixel
+                    let coverage = ((LED_RADIUS - d) * cell + 0.5).clamp(0.0, 1.0);
+                    let glow = if self.glow {
+                        GLOW_STRENGTH * (-(d * d) / 0.2).exp()
+                    } else {
+                        0.0
+                    };
+                    let p = &mut light[y * width + x];
+                    for (c, (b, g)) in p.iter_mut().zip(body.iter().zip(rgb.iter())) {
+                        *c += b * coverage + g * glow;
+                    }
+                }
+            }
+        }
+
+        let mut image = Image::new(width, height);
+        for (dst, p) in image.rgba.chunks_mut(4).zip(light.iter()) {
+            let c = |v: f32| v.round().min(255.0) as u8;
+            dst.copy_from_slice(&[c(p[0]), c(p[1]), c(p[2]), 255]);
+        }
+        image
+    }
+}
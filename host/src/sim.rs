@@ -0,0 +1,83 @@
+//! Runs the effects of the firmware with a simulated clock.
+//!
+//! ```ignore
+//! let mut effects = Effects::new();
+//! let mut scratch = vec![color::BLACK; NUM_LEDS];
+//! let mut sim = Simulation::new(Player::new(effects.registry(), &mut scratch));
+//! sim.player.switch_by_name("fire", 0, 0);
+//! let frame = sim.run_until(2_000);
+//! ```
+
+use rtic_stm32::{
+    clock::{AnimationClock, Ticks},
+    color,
+    effect::Player,
+    installation::{Pipeline, NUM_LEDS},
+};
+use smart_leds::RGB8;
+
+/// How often the firmware renders a frame.
+pub const REFRESH_HZ: u32 = 40;
+
+/// Instants of the simulated clock, in ms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimInstant(pub u32);
+
+impl Ticks for SimInstant {
+    fn ticks_since(self, earlier: Self) -> u32 {
+        self.0.wrapping_sub(earlier.0)
+    }
+}
+
+/// A [`Player`] rendering at the refresh rate of the firmware, with the
+/// animation time from an [`AnimationClock`] that follows the simulated time.
+/// The frames go through the [`Pipeline`] of the firmware.
+pub struct Simulation<'a, const N: usize> {
+    pub player: Player<'a, N>,
+    pub pipeline: Pipeline,
+    pub clock: AnimationClock<SimInstant>,
+    /// simulated time of the next frame, in ms
+    now: u32,
+    frame: Vec<RGB8>,
+}
+
+impl<'a, const N: usize> Simulation<'a, N> {
+    pub fn new(player: Player<'a, N>) -> Self {
+        let mut clock = AnimationClock::new(1);
+        clock.tick(SimInstant(0));
+        Simulation {
+            player,
+            pipeline: Pipeline::new(),
+            clock,
+            now: 0,
+            frame: vec![color::BLACK; NUM_LEDS],
+        }
+    }
+
+    /// Simulated time of the next frame, in ms.
+    pub fn now(&self) -> u32 {
+        self.now
+    }
+
+    /// The last frame.
+    pub fn frame(&self) -> &[RGB8] {
+        &self.frame
+    }
+
+    /// Renders the frame at [`Simulation::now`] and advances the time by a
+    /// refresh period.
+    pub fn step(&mut self) -> &[RGB8] {
+        let t = self.clock.tick(SimInstant(self.now));
+        self.pipeline.render(&mut self.player, t, &mut self.frame);
+        self.now += 1000 / REFRESH_HZ;
+        &self.frame
+    }
+
+    /// Steps through all frames up to `ms`, returns the last one.
+    pub fn run_until(&mut self, ms: u32) -> &[RGB8] {
+        while self.now <= ms {
+            self.step();
+        }
+        &self.frame
+    }
+}
@@ -0,0 +1,142 @@
+//! Compares renderings of the effects with the reviewed pictures in
+//! `tests/golden/`.
+//!
+//! A mismatch or a missing picture fails and leaves the rendering next to the
+//! picture, as `<name>.actual.png`. Run with `UPDATE_GOLDEN=1` to write the
+//! pictures after an intended change, and review them before committing.
+
+use hexlife_host::{
+    images::{self, Image},
+    render::Renderer,
+    sim::Simulation,
+};
+use rtic_stm32::{
+    color,
+    effect::Player,
+    hex,
+    installation::{Effects, NUM_EFFECTS, NUM_LEDS},
+};
+use std::{env, fs, path::PathBuf};
+
+/// Small pictures, a few pixels per LED are enough to spot a change.
+const CELL_PX: u32 = 8;
+
+/// Renders `effect` at `ms` after switching to it, `setup` can change the
+/// player and the pipeline first.
+fn render(effect: &str, ms: u32, setup: impl FnOnce(&mut Simulation<'_, NUM_EFFECTS>)) -> Image {
+    let mut effects = Effects::new();
+    let mut scratch = vec![color::BLACK; NUM_LEDS];
+    let mut player = Player::new(effects.registry(), &mut scratch);
+    assert!(player.switch_by_name(effect, 0, 0), "no effect {}", effect);
+    let mut sim = Simulation::new(player);
+    setup(&mut sim);
+    let mut renderer = Renderer::new(&hex::PANEL);
+    // no glow, it is computed with exp(), which may differ between platforms
+    renderer.cell_px = CELL_PX;
+    renderer.render(sim.run_until(ms))
+}
+
+fn check(name: &str, image: &Image) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let path = dir.join(format!("{}.png", name));
+    let actual = dir.join(format!("{}.actual.png", name));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(&dir).unwrap();
+        images::save_png(&path, image).unwrap();
+        eprintln!("wrote {}", path.display());
+        return;
+    }
+    if !path.exists() {
+        fs::create_dir_all(&dir).unwrap();
+        images::save_png(&actual, image).unwrap();
+        panic!(
+            "{} is missing, review {} and run with UPDATE_GOLDEN=1",
+            path.display(),
+            actual.display()
+        );
+    }
+    let golden = images::load_png(&path).unwrap();
+    let same_size = (golden.width, golden.height) == (image.width, image.height);
+    let differences = golden
+        .rgba
+        .chunks(4)
+        .zip(image.rgba.chunks(4))
+        .filter(|(a, b)| a != b)
+        .count();
+    if same_size && differences == 0 {
+        fs::remove_file(&actual).ok();
+        return;
+    }
+    images::save_png(&actual, image).unwrap();
+    panic!(
+        "{} differs from {} ({} pixels), see {}",
+        name,
+        path.display(),
+        if same_size {
+            differences
+        } else {
+            image.rgba.len() / 4
+        },
+        actual.display()
+    );
+}
+
+#[test]
+fn rainbow() {
+    check("rainbow", &render("rainbow", 1_000, |_| {}));
+}
+
+#[test]
+fn fire() {
+    check("fire", &render("fire", 2_000, |_| {}));
+}
+
+#[test]
+fn plasma_ocean() {
+    let ocean = *color::find_palette("ocean").unwrap();
+    check(
+        "plasma-ocean",
+        &render("plasma", 1_500, |s| s.player.params.palette = ocean),
+    );
+}
+
+#[test]
+fn ripples() {
+    check("ripples", &render("ripples", 3_000, |_| {}));
+}
+
+#[test]
+fn life() {
+    check("life", &render("life", 5_000, |_| {}));
+}
+
+#[test]
+fn marquee() {
+    check("marquee", &render("marquee", 2_000, |_| {}));
+}
+
+#[test]
+fn heart_dimmed() {
+    check(
+        "heart-dimmed",
+        &render("heart", 0, |s| s.player.params.brightness = 64),
+    );
+}
+
+#[test]
+fn crossfade() {
+    // halfway from the rainbow to the fire
+    let image = render("rainbow", 1_500, |s| {
+        s.player.switch_by_name("fire", 1_000, 1_000);
+    });
+    check("crossfade", &image);
+}
+
+#[test]
+fn fire_with_ambient() {
+    // the rainbow at the top and bottom, over the fire
+    check(
+        "fire-ambient",
+        &render("fire", 2_000, |s| s.pipeline.set_ambient(Some(96))),
+    );
+}
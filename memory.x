@@ -2,7 +2,9 @@ MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
   /* TODO Adjust these memory regions to match your device memory layout */
-  FLASH : ORIGIN = 0x8000000, LENGTH = 128K 
+  /* the last 2K page of the 128K holds the settings, see src/flash.rs, the
+     firmware has to fit into the rest (.github/workflows/firmware.yml links it) */
+  FLASH : ORIGIN = 0x8000000, LENGTH = 126K
   RAM : ORIGIN = 0x20000000, LENGTH = 64K
 }
 
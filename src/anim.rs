@@ -0,0 +1,437 @@
+//! A compact format for recorded animations, played back from flash.
+//!
+//! Animations are designed offline, converted with `anim-encode` (see
+//! `host/`) and included in the firmware, where they are decoded one frame at
+//! a time:
+//!
+//! ```ignore
+//! static mut INTRO: Animation<NUM_LEDS> =
+//!     Animation::new(include_bytes!("../assets/anim/rings.hxa"), anim::LAYOUT_HEX_PANEL);
+//! ```
+//!
+//! The data starts with a [`Header`], followed by the frames. A frame is a
+//! flags byte and runs that cover all LEDs in order:
+//!
+//! | run byte    | meaning                                          |
+//! |-------------|--------------------------------------------------|
+//! | `00nn_nnnn` | the next `n + 1` LEDs keep their color           |
+//! | `01nn_nnnn` | `n + 1` colors follow (RGB), one per LED         |
+//! | `10nn_nnnn` | one color follows, for the next `n + 1` LEDs     |
+//!
+//! [`KEY_FRAME`]s start from black instead of the previous frame, so
+//! playback can start over at them. The first frame and the loop frame are
+//! key frames.
+
+use crate::{
+    color,
+    effect::{Effect, Params},
+};
+use smart_leds::RGB8;
+
+pub const MAGIC: [u8; 4] = *b"HXA1";
+pub const HEADER_LEN: usize = 16;
+
+/// LEDs in strip order, no geometry.
+pub const LAYOUT_STRIP: u8 = 0;
+/// [`hex::PANEL`](crate::hex::PANEL)
+pub const LAYOUT_HEX_PANEL: u8 = 1;
+
+/// Frame flag, the frame doesn't depend on the previous one.
+pub const KEY_FRAME: u8 = 1;
+
+const NO_LOOP: u16 = 0xffff;
+const RUN_SKIP: u8 = 0b00 << 6;
+const RUN_LITERAL: u8 = 0b01 << 6;
+const RUN_REPEAT: u8 = 0b10 << 6;
+const MAX_RUN: usize = 64;
+/// Frames decoded at most per render. An animation that falls further
+/// behind, e.g. at a high speed, plays slower instead of hogging the CPU.
+const MAX_CATCH_UP: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// the data doesn't start with [`MAGIC`]
+    NotAnAnimation,
+    /// the data ends within a frame or the header
+    Truncated,
+    /// an invalid run, or runs that don't add up to the LED count
+    Corrupt,
+}
+
+/// Little endian, [`HEADER_LEN`] bytes: magic, layout, fps, LED count,
+/// frame count, loop frame (`0xffff` for none) and the loop frame's offset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// which layout the LED indices refer to, e.g. [`LAYOUT_HEX_PANEL`]
+    pub layout: u8,
+    pub fps: u8,
+    pub led_count: u16,
+    pub frame_count: u16,
+    /// the frame playback continues with after the last one, the last frame
+    /// stays on without one
+    pub loop_frame: Option<u16>,
+    /// offset of the loop frame from the start of the data, in bytes
+    pub loop_offset: u32,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Header, Error> {
+        if data.len() < HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        if data[..4] != MAGIC {
+            return Err(Error::NotAnAnimation);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        Ok(Header {
+            layout: data[4],
+            fps: data[5],
+            led_count: u16_at(6),
+            frame_count: u16_at(8),
+            loop_frame: Some(u16_at(10)).filter(|f| *f != NO_LOOP),
+            loop_offset: u32::from_le_bytes([data[12], data[13], data[14], data[15]]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.layout;
+        bytes[5] = self.fps;
+        bytes[6..8].copy_from_slice(&self.led_count.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.frame_count.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.loop_frame.unwrap_or(NO_LOOP).to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.loop_offset.to_le_bytes());
+        bytes
+    }
+}
+
+/// Decodes the frames of an animation one after the other.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    header: Header,
+    /// offset of the next frame
+    pos: usize,
+    next_frame: u16,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        let header = Header::parse(data)?;
+        Ok(Decoder {
+            data,
+            header,
+            pos: HEADER_LEN,
+            next_frame: 0,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Index of the frame [`Decoder::decode_next`] decodes.
+    pub fn next_frame(&self) -> u16 {
+        self.next_frame
+    }
+
+    /// Continues with the loop frame, returns false if there is none.
+    pub fn seek_loop(&mut self) -> bool {
+        match self.header.loop_frame {
+            Some(frame) => {
+                self.pos = self.header.loop_offset as usize;
+                self.next_frame = frame;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Decodes the next frame into `frame`, which must hold the previous
+    /// one. Returns false after the last frame.
+    ///
+    /// LEDs beyond the end of `frame` are skipped, LEDs beyond the LED count
+    /// of the animation are left alone.
+    pub fn decode_next(&mut self, frame: &mut [RGB8]) -> Result<bool, Error> {
+        if self.next_frame >= self.header.frame_count {
+            return Ok(false);
+        }
+        let data = self.data;
+        let mut pos = self.pos;
+        let mut byte = || {
+            let b = data.get(pos).copied().ok_or(Error::Truncated);
+            pos += 1;
+            b
+        };
+
+        let flags = byte()?;
+        let count = self.header.led_count as usize;
+        if flags & KEY_FRAME != 0 {
+            let len = count.min(frame.len());
+            frame[..len].fill(color::BLACK);
+        }
+        let mut led = 0;
+        while led < count {
+            let run = byte()?;
+            let n = (run & 0x3f) as usize + 1;
+            if led + n > count {
+                return Err(Error::Corrupt);
+            }
+            match run & 0xc0 {
+                RUN_SKIP => {}
+                RUN_LITERAL => {
+                    for i in led..led + n {
+                        let c = RGB8::new(byte()?, byte()?, byte()?);
+                        if let Some(p) = frame.get_mut(i) {
+                            *p = c;
+                        }
+                    }
+                }
+                RUN_REPEAT => {
+                    let c = RGB8::new(byte()?, byte()?, byte()?);
+                    let end = (led + n).min(frame.len());
+                    if led < end {
+                        frame[led..end].fill(c);
+                    }
+                }
+                _ => return Err(Error::Corrupt),
+            }
+            led += n;
+        }
+
+        self.pos = pos;
+        self.next_frame += 1;
+        Ok(true)
+    }
+}
+
+/// Encodes `frame` as the difference to `previous` (of the same length), or
+/// as a key frame if there is none. The bytes are passed to `out` in order,
+/// returns their number.
+pub fn encode_frame(
+    previous: Option<&[RGB8]>,
+    frame: &[RGB8],
+    mut out: impl FnMut(&[u8]),
+) -> usize {
+    // key frames start from black
+    let base = |i: usize| previous.map_or(color::BLACK, |p| p[i]);
+    let run_len = |start: usize, same: &dyn Fn(usize) -> bool| {
+        (start..frame.len().min(start + MAX_RUN))
+            .take_while(|i| same(*i))
+            .count()
+    };
+    let rgb = |c: RGB8| [c.r, c.g, c.b];
+
+    let mut written = 0;
+    let mut emit = |bytes: &[u8]| {
+        out(bytes);
+        written += bytes.len();
+    };
+    emit(&[if previous.is_none() { KEY_FRAME } else { 0 }]);
+
+    let mut i = 0;
+    while i < frame.len() {
+        let unchanged = run_len(i, &|j| frame[j] == base(j));
+        let repeated = run_len(i, &|j| frame[j] == frame[i]);
+        if unchanged > 0 {
+            emit(&[RUN_SKIP | (unchanged - 1) as u8]);
+            i += unchanged;
+        } else if repeated > 1 {
+            emit(&[RUN_REPEAT | (repeated - 1) as u8]);
+            emit(&rgb(frame[i]));
+            i += repeated;
+        } else {
+            // up to the next run that is cheaper on its own
+            let literal = run_len(i, &|j| {
+                j == i || (frame[j] != base(j) && frame.get(j + 1) != Some(&frame[j]))
+            });
+            emit(&[RUN_LITERAL | (literal - 1) as u8]);
+            for c in &frame[i..i + literal] {
+                emit(&rgb(*c));
+            }
+            i += literal;
+        }
+    }
+    written
+}
+
+/// Plays an animation in the format above from flash, on up to `N` LEDs.
+///
+/// The animation runs at its own frame rate, scaled by the speed. Data that
+/// can't be decoded (or is made for another layout) shows black.
+pub struct Animation<const N: usize> {
+    data: &'static [u8],
+    layout: u8,
+    decoder: Option<Decoder<'static>>,
+    frame: [RGB8; N],
+    /// frames decoded since the start, loops included
+    decoded: u32,
+    /// frames that were due but skipped, playback is behind by this many
+    lag: u32,
+}
+
+impl<const N: usize> Animation<N> {
+    pub const fn new(data: &'static [u8], layout: u8) -> Self {
+        Animation {
+            data,
+            layout,
+            decoder: None,
+            frame: [color::BLACK; N],
+            decoded: 0,
+            lag: 0,
+        }
+    }
+
+    /// Decodes the next frame, continues with the loop frame after the last
+    /// one. Returns false when the animation is over.
+    fn advance(&mut self) -> bool {
+        let decoder = match self.decoder.as_mut() {
+            Some(d) => d,
+            None => return false,
+        };
+        let result = match decoder.decode_next(&mut self.frame) {
+            Ok(false) if decoder.seek_loop() => decoder.decode_next(&mut self.frame),
+            result => result,
+        };
+        match result {
+            Ok(more) => {
+                self.decoded += more as u32;
+                more
+            }
+            Err(_) => {
+                self.decoder = None;
+                self.frame = [color::BLACK; N];
+                false
+            }
+        }
+    }
+}
+
+impl<const N: usize> Effect for Animation<N> {
+    fn reset(&mut self) {
+        self.decoder = Decoder::new(self.data)
+            .ok()
+            .filter(|d| d.header().layout == self.layout);
+        self.frame = [color::BLACK; N];
+        self.decoded = 0;
+        self.lag = 0;
+    }
+
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        let fps = match &self.decoder {
+            Some(d) => d.header().fps.max(1) as u64,
+            None => {
+                frame.fill(color::BLACK);
+                return;
+            }
+        };
+        // frames that should have been shown by now, the first one at 0
+        let due = (params.scale_time(t) as u64 * fps / 1000) as u32 + 1;
+        if due < self.decoded + self.lag {
+            // the time went back
+            self.reset();
+        }
+        let due = due - self.lag;
+        let mut budget = MAX_CATCH_UP;
+        while self.decoded < due && budget > 0 && self.advance() {
+            budget -= 1;
+        }
+        if budget == 0 {
+            self.lag += due - self.decoded;
+        }
+
+        let len = frame.len().min(N);
+        frame[..len].copy_from_slice(&self.frame[..len]);
+        frame[len..].fill(color::BLACK);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ten frames for one LED at 10 fps, frame `k` is `(k, 0, 0)`, played
+    /// once.
+    #[rustfmt::skip]
+    static COUNTER: [u8; HEADER_LEN + 10 * 5] = [
+        b'H', b'X', b'A', b'1', LAYOUT_STRIP, 10, 1, 0, 10, 0, 0xff, 0xff, 0, 0, 0, 0,
+        KEY_FRAME, RUN_REPEAT, 0, 0, 0,
+        0, RUN_REPEAT, 1, 0, 0,
+        0, RUN_REPEAT, 2, 0, 0,
+        0, RUN_REPEAT, 3, 0, 0,
+        0, RUN_REPEAT, 4, 0, 0,
+        0, RUN_REPEAT, 5, 0, 0,
+        0, RUN_REPEAT, 6, 0, 0,
+        0, RUN_REPEAT, 7, 0, 0,
+        0, RUN_REPEAT, 8, 0, 0,
+        0, RUN_REPEAT, 9, 0, 0,
+    ];
+
+    #[test]
+    fn frames_survive_encoding() {
+        let mut frames = [[color::BLACK; 150]; 3];
+        for (i, c) in frames[0].iter_mut().enumerate() {
+            *c = RGB8::new(i as u8, 0, 255 - i as u8);
+        }
+        frames[1] = frames[0];
+        // a run of one color, a single change and one longer than a run
+        frames[1][10..20].fill(RGB8::new(1, 2, 3));
+        frames[1][40] = color::BLACK;
+        frames[1][80..].fill(RGB8::new(9, 9, 9));
+        frames[2] = frames[1];
+        frames[2][149] = RGB8::new(255, 255, 255);
+
+        let mut data = [0u8; 2048];
+        let mut len = HEADER_LEN;
+        for i in 0..frames.len() {
+            let previous = i.checked_sub(1).map(|p| &frames[p][..]);
+            encode_frame(previous, &frames[i], |bytes| {
+                data[len..len + bytes.len()].copy_from_slice(bytes);
+                len += bytes.len();
+            });
+        }
+        let header = Header {
+            layout: LAYOUT_HEX_PANEL,
+            fps: 25,
+            led_count: 150,
+            frame_count: frames.len() as u16,
+            loop_frame: Some(0),
+            loop_offset: HEADER_LEN as u32,
+        };
+        data[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+
+        let mut decoder = Decoder::new(&data[..len]).unwrap();
+        assert_eq!(decoder.header(), &header);
+        let mut frame = [color::BLACK; 150];
+        for expected in &frames {
+            assert_eq!(decoder.decode_next(&mut frame), Ok(true));
+            assert_eq!(&frame[..], &expected[..]);
+        }
+        assert_eq!(decoder.decode_next(&mut frame), Ok(false));
+        assert!(decoder.seek_loop());
+        assert_eq!(decoder.decode_next(&mut frame), Ok(true));
+        assert_eq!(&frame[..], &frames[0][..]);
+    }
+
+    #[test]
+    fn catching_up_is_limited() {
+        let mut animation = Animation::<1>::new(&COUNTER, LAYOUT_STRIP);
+        animation.reset();
+        let params = Params::default();
+        let mut frame = [color::BLACK; 1];
+        let mut shown = |animation: &mut Animation<1>, t: u32| {
+            animation.render(t, &params, &mut frame);
+            frame[0].r
+        };
+        assert_eq!(shown(&mut animation, 0), 0);
+        // frame 5 is due, only four more are decoded
+        assert_eq!(shown(&mut animation, 500), 4);
+        // and the animation stays behind
+        assert_eq!(shown(&mut animation, 600), 5);
+        // the last frame stays on
+        assert_eq!(shown(&mut animation, 5_000), 9);
+        assert_eq!(shown(&mut animation, 6_000), 9);
+        // the time went back
+        assert_eq!(shown(&mut animation, 100), 1);
+    }
+}
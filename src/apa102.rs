@@ -0,0 +1,225 @@
+//! APA102 / SK9822 driver.
+//!
+//! Clocked LEDs take a 5-bit global brightness per pixel in addition to the
+//! 8-bit color. Dimming through the global brightness keeps the full 8-bit
+//! color resolution, so dark colors can be shown much more precisely than by
+//! scaling the RGB values (see [`Apa102Pixel::from_rgb16`]). To use that,
+//! keep the effect brightness at 255 and apply it with [`with_brightness`]:
+//!
+//! ```ignore
+//! player.params.brightness = 255;
+//! player.render(now, &mut frame);
+//! let dimmed = apa102::with_brightness(frame.iter().cloned(), brightness);
+//! for (p, c) in pixels.iter_mut().zip(dimmed) {
+//!     *p = c;
+//! }
+//! // the power limiter sees the light output, see `power::Led`
+//! power_limiter.apply(&mut pixels);
+//! strip.write(pixels.iter().cloned())?;
+//! ```
+
+use crate::power::Led;
+use embedded_hal::{blocking::spi::Write, spi};
+use smart_leds::{SmartLedsWrite, RGB8};
+
+/// SPI mode of APA102 and SK9822
+pub const MODE: spi::Mode = spi::MODE_0;
+
+/// Largest value of the global brightness
+pub const MAX_GLOBAL: u8 = 31;
+
+/// An 8-bit color with a 5-bit global brightness.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Apa102Pixel {
+    pub color: RGB8,
+    /// 0 to 31
+    pub global: u8,
+}
+
+impl From<RGB8> for Apa102Pixel {
+    fn from(color: RGB8) -> Self {
+        Apa102Pixel {
+            color,
+            global: MAX_GLOBAL,
+        }
+    }
+}
+
+impl Apa102Pixel {
+    /// Pixel for a linear 16-bit color, using the smallest global brightness
+    /// that can show the brightest channel.
+    pub fn from_rgb16(r: u16, g: u16, b: u16) -> Self {
+        let max = r.max(g).max(b) as u32;
+        if max == 0 {
+            return Apa102Pixel {
+                color: RGB8::default(),
+                global: 0,
+            };
+        }
+        // round up, so the channels still fit into 8 bits
+        let global = (max * MAX_GLOBAL as u32).div_ceil(0xffff).max(1);
+        // output = global / 31 * c / 255 = v / 65535
+        let channel = |v: u16| {
+            let c = (v as u32 * MAX_GLOBAL as u32 + 257 * global / 2) / (257 * global);
+            c.min(255) as u8
+        };
+        Apa102Pixel {
+            color: RGB8 {
+                r: channel(r),
+                g: channel(g),
+                b: channel(b),
+            },
+            global: global as u8,
+        }
+    }
+
+    /// 8-bit color with the same light output, e.g. for the power model.
+    pub fn effective_rgb(&self) -> RGB8 {
+        let g = self.global.min(MAX_GLOBAL) as u16;
+        let scale = |c: u8| ((c as u16 * g + MAX_GLOBAL as u16 / 2) / MAX_GLOBAL as u16) as u8;
+        RGB8 {
+            r: scale(self.color.r),
+            g: scale(self.color.g),
+            b: scale(self.color.b),
+        }
+    }
+
+    /// Light output of a channel as linear 16-bit value, scaled by `scale` / 255.
+    fn linear16(&self, c: u8, scale: u8) -> u16 {
+        let g = self.global.min(MAX_GLOBAL) as u64;
+        // 255 * 31 * 255 is the full output
+        (c as u64 * g * scale as u64 * 0xffff / (255 * MAX_GLOBAL as u64 * 255)) as u16
+    }
+}
+
+impl Led for Apa102Pixel {
+    fn rgb(&self) -> RGB8 {
+        self.effective_rgb()
+    }
+
+    /// Dims through the global brightness where possible, like
+    /// [`with_brightness`].
+    fn scaled(self, scale: u8) -> Self {
+        if scale == 255 {
+            return self;
+        }
+        let c = self.color;
+        Apa102Pixel::from_rgb16(
+            self.linear16(c.r, scale),
+            self.linear16(c.g, scale),
+            self.linear16(c.b, scale),
+        )
+    }
+}
+
+/// Like `smart_leds::brightness`, but dims through the global brightness
+/// instead of losing color resolution.
+pub fn with_brightness<I: Iterator<Item = RGB8>>(
+    iter: I,
+    brightness: u8,
+) -> impl Iterator<Item = Apa102Pixel> {
+    // 255 * 255 * 257 / 255 is 0xffff, so full white stays at full output
+    let b = brightness as u32 * 257;
+    let linear = move |c: u8| (c as u32 * b / 255) as u16;
+    iter.map(move |c| Apa102Pixel::from_rgb16(linear(c.r), linear(c.g), linear(c.b)))
+}
+
+/// APA102 / SK9822 strip on a SPI bus (only MOSI and SCK are used).
+pub struct Apa102<SPI> {
+    spi: SPI,
+}
+
+impl<SPI, E> Apa102<SPI>
+where
+    SPI: Write<u8, Error = E>,
+{
+    /// `spi` must be configured with [`MODE`], APA102 can be clocked with
+    /// several MHz.
+    pub fn new(spi: SPI) -> Self {
+        Apa102 { spi }
+    }
+
+    pub fn free(self) -> SPI {
+        self.spi
+    }
+}
+
+impl<SPI, E> SmartLedsWrite for Apa102<SPI>
+where
+    SPI: Write<u8, Error = E>,
+{
+    type Error = E;
+    type Color = Apa102Pixel;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), E>
+    where
+        T: Iterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        self.spi.write(&[0x00; 4])?;
+        let mut count = 0;
+        for pixel in iterator {
+            let p: Apa102Pixel = pixel.into();
+            self.spi.write(&[
+                0xe0 | p.global.min(MAX_GLOBAL),
+                p.color.b,
+                p.color.g,
+                p.color.r,
+            ])?;
+            count += 1;
+        }
+        // SK9822 latches on a zero frame, APA102 needs half a clock per LED
+        // to push the data through the chain
+        self.spi.write(&[0x00; 4])?;
+        for _ in 0..(count + 15) / 16 {
+            self.spi.write(&[0x00])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dim(c: RGB8, brightness: u8) -> Apa102Pixel {
+        with_brightness(core::iter::once(c), brightness)
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn full_brightness_keeps_full_white() {
+        let white = RGB8::new(255, 255, 255);
+        assert_eq!(dim(white, 255), Apa102Pixel::from(white));
+        assert_eq!(dim(white, 255).effective_rgb(), white);
+    }
+
+    #[test]
+    fn dimming_keeps_the_light_output() {
+        for &brightness in [1u8, 7, 64, 128, 200, 254].iter() {
+            for &v in [1u8, 30, 128, 255].iter() {
+                let expected = v as u32 * brightness as u32 / 255;
+                let shown = dim(RGB8::new(v, 0, 0), brightness).effective_rgb().r as u32;
+                assert!(
+                    (shown as i32 - expected as i32).abs() <= 1,
+                    "{} at {}: {}",
+                    v,
+                    brightness,
+                    shown
+                );
+            }
+        }
+        assert_eq!(dim(RGB8::new(10, 20, 30), 0).global, 0);
+    }
+
+    #[test]
+    fn scaling_halves_the_output() {
+        let pixel = Apa102Pixel::from(RGB8::new(200, 100, 40));
+        let half = pixel.scaled(128).rgb();
+        assert!((half.r as i32 - 100).abs() <= 1, "{:?}", half);
+        assert!((half.g as i32 - 50).abs() <= 1, "{:?}", half);
+        assert!((half.b as i32 - 20).abs() <= 1, "{:?}", half);
+        assert_eq!(pixel.scaled(255), pixel);
+    }
+}
@@ -0,0 +1,39 @@
+//! Images converted at build time, see `build.rs`.
+//!
+//! PNGs in `assets/oled/` become [`Bitmap`]s for the display, pixels brighter
+//! than half are on. PNGs in `assets/leds/` are stretched over
+//! [`hex::PANEL`](crate::hex::PANEL) and become a color per LED, for the
+//! [`Picture`](crate::effects::Picture) effect. The constants are named after
+//! the files, `assets/oled/logo.png` is [`LOGO`]:
+//!
+//! ```ignore
+//! disp.bitmap(&assets::LOGO, 0, 0);
+//! static mut HEART: Picture = Picture::new(&assets::HEART);
+//! ```
+
+use embedded_graphics::{image::ImageRaw, pixelcolor::BinaryColor};
+use smart_leds::RGB8;
+
+/// A monochrome image. The rows are `width` bits from the left, padded to
+/// whole bytes, the leftmost pixel is the most significant bit.
+#[derive(Clone, Copy, Debug)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: &'static [u8],
+}
+
+impl Bitmap {
+    /// The bitmap for embedded-graphics, to be drawn with an
+    /// [`Image`](embedded_graphics::image::Image).
+    pub fn raw(&self) -> ImageRaw<'static, BinaryColor> {
+        ImageRaw::new(self.data, self.width, self.height)
+    }
+}
+
+#[allow(dead_code)]
+const fn rgb(r: u8, g: u8, b: u8) -> RGB8 {
+    RGB8 { r, g, b }
+}
+
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
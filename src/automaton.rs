@@ -0,0 +1,957 @@
+//! A generic cellular automaton engine.
+//!
+//! An [`Automaton`] runs a [`Rule`] on the cells of a [`Topology`]. Every LED
+//! is one cell, the topology decides which cells are neighbors. Cell states
+//! are small numbers, state 0 is "empty" and shown black, the other states
+//! are colored through the palette.
+//!
+//! Presets:
+//!
+//! * [`LifeLike`] rules like [`HEX_LIFE`] (B2/S34) or Conway's [`CONWAY`]
+//! * [`BriansBrain`]
+//! * [`Wireworld`]
+//! * [`Ant`]s: Langton's ant ([`Ant::langton`]) and the hex ant ([`Ant::hex`])
+//!
+//! The automaton watches for stagnation: a hash of every generation is kept
+//! for the last [`HISTORY_LEN`] generations, so extinction, still lifes and
+//! oscillators with a period up to that length are detected. The board then
+//! fades out and starts again with a new seed, either random or made of
+//! [`Pattern`]s (see [`Automaton::life_patterns`]). Stagnation only counts
+//! after [`MIN_GENERATIONS`], so a board of oscillators is shown for a while
+//! before it is replaced.
+
+use crate::{
+    color,
+    effect::{Control, Effect, Params},
+    hex::{Layout, NEIGHBOR_OFFSETS},
+    rng::XorShift32,
+};
+use smart_leds::RGB8;
+
+/// Neighbor counts are kept for this many states, larger states are counted
+/// as the last one.
+pub const MAX_STATES: usize = 8;
+
+const MAX_GENERATIONS_PER_FRAME: u32 = 4;
+
+/// Number of generation hashes kept for the stagnation detection, enough
+/// for the longest period in [`HEX_LIFE_PATTERNS`] and so for every board
+/// made of them.
+pub const HISTORY_LEN: usize = 48;
+
+/// A new board runs at least this many generations before it counts as
+/// stagnant. Extinction ends it right away.
+pub const MIN_GENERATIONS: u32 = 100;
+
+/// Duration of the fade out and the fade in when reseeding, in ms.
+pub const RESEED_FADE_MS: u32 = 1_000;
+
+/// Which cells are neighbors.
+///
+/// Neighbors are numbered by direction, going clockwise, so turning right
+/// means going to the next direction.
+pub trait Topology {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of directions, i.e. the maximal number of neighbors.
+    fn directions(&self) -> usize;
+
+    /// The neighbor of `index` in direction `dir`, if there is one.
+    fn neighbor(&self, index: usize, dir: usize) -> Option<usize>;
+
+    /// The cell at `offset` from `index` in doubled hex coordinates, used to
+    /// place [`Pattern`]s. Only topologies on hex layouts know about that.
+    fn offset(&self, _index: usize, _offset: (i16, i16)) -> Option<usize> {
+        None
+    }
+}
+
+/// The six hex neighbors.
+impl Topology for Layout {
+    fn len(&self) -> usize {
+        Layout::len(self)
+    }
+
+    fn directions(&self) -> usize {
+        NEIGHBOR_OFFSETS.len()
+    }
+
+    fn neighbor(&self, index: usize, dir: usize) -> Option<usize> {
+        let (x, y) = self.position(index)?;
+        let (dx, dy) = NEIGHBOR_OFFSETS[dir];
+        self.index(x + dx, y + dy)
+    }
+
+    fn offset(&self, index: usize, (dx, dy): (i16, i16)) -> Option<usize> {
+        let (x, y) = self.position(index)?;
+        self.index(x + dx, y + dy)
+    }
+}
+
+impl<T: Topology + ?Sized> Topology for &T {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn directions(&self) -> usize {
+        (**self).directions()
+    }
+
+    fn neighbor(&self, index: usize, dir: usize) -> Option<usize> {
+        (**self).neighbor(index, dir)
+    }
+
+    fn offset(&self, index: usize, offset: (i16, i16)) -> Option<usize> {
+        (**self).offset(index, offset)
+    }
+}
+
+// clockwise, starting to the right
+const VON_NEUMANN: [(i16, i16); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const MOORE: [(i16, i16); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// Square grid neighborhoods on the LEDs of a hex layout.
+///
+/// The cells of a row form the columns, shifted by half a cell in every other
+/// row, so square automata look slightly sheared.
+pub struct SquareNeighbors {
+    layout: &'static Layout,
+    diagonal: bool,
+}
+
+impl SquareNeighbors {
+    /// 4 neighbors
+    pub const fn von_neumann(layout: &'static Layout) -> Self {
+        SquareNeighbors {
+            layout,
+            diagonal: false,
+        }
+    }
+
+    /// 8 neighbors
+    pub const fn moore(layout: &'static Layout) -> Self {
+        SquareNeighbors {
+            layout,
+            diagonal: true,
+        }
+    }
+}
+
+impl Topology for SquareNeighbors {
+    fn len(&self) -> usize {
+        self.layout.len()
+    }
+
+    fn directions(&self) -> usize {
+        if self.diagonal {
+            MOORE.len()
+        } else {
+            VON_NEUMANN.len()
+        }
+    }
+
+    fn neighbor(&self, index: usize, dir: usize) -> Option<usize> {
+        let (dc, dr) = if self.diagonal {
+            MOORE[dir]
+        } else {
+            VON_NEUMANN[dir]
+        };
+        let (x, y) = self.layout.position(index)?;
+        let row = self.layout.rows.get((y + dr) as usize)?;
+        let parity = (row.offset % 2) as i16;
+        self.layout.index(2 * (x / 2 + dc) + parity, y + dr)
+    }
+
+    fn offset(&self, index: usize, offset: (i16, i16)) -> Option<usize> {
+        self.layout.offset(index, offset)
+    }
+}
+
+/// Palette index of `state`, spreading states 1 to `states - 1` over the palette.
+fn spread(state: u8, states: u8) -> u8 {
+    if states <= 2 {
+        return 0;
+    }
+    ((state.saturating_sub(1) as u16 * 255) / (states as u16 - 2)) as u8
+}
+
+pub trait Rule: Send {
+    /// Number of states, including 0.
+    fn states(&self) -> u8;
+
+    /// Computes the generation after `cells` into `next`.
+    fn step(&mut self, topology: &dyn Topology, cells: &[u8], next: &mut [u8]);
+
+    /// Sets up a new start configuration.
+    fn seed(&mut self, topology: &dyn Topology, cells: &mut [u8], rng: &mut XorShift32);
+
+    /// Nothing will happen anymore, the automaton needs a new seed.
+    fn extinct(&self, cells: &[u8]) -> bool {
+        cells.iter().all(|c| *c == 0)
+    }
+
+    /// Palette index for `state`, state 0 is always black.
+    fn color_index(&self, state: u8) -> u8 {
+        spread(state, self.states())
+    }
+
+    /// The part of `state` that matters for the stagnation detection.
+    fn hash_state(&self, state: u8) -> u8 {
+        state
+    }
+}
+
+/// A rule where the next state of a cell only depends on its state and the
+/// number of neighbors in each state.
+pub trait Totalistic: Send {
+    fn states(&self) -> u8;
+
+    /// `counts[s]` is the number of neighbors in state `s`.
+    fn next(&self, state: u8, counts: &[u8; MAX_STATES]) -> u8;
+
+    /// State of a cell in a random start configuration.
+    fn random_state(&self, rng: &mut XorShift32) -> u8;
+
+    /// See [`Rule::extinct`].
+    fn extinct(&self, cells: &[u8]) -> bool {
+        cells.iter().all(|c| *c == 0)
+    }
+
+    fn color_index(&self, state: u8) -> u8 {
+        spread(state, self.states())
+    }
+
+    /// See [`Rule::hash_state`].
+    fn hash_state(&self, state: u8) -> u8 {
+        state
+    }
+}
+
+impl<R: Totalistic> Rule for R {
+    fn states(&self) -> u8 {
+        Totalistic::states(self)
+    }
+
+    fn step(&mut self, topology: &dyn Topology, cells: &[u8], next: &mut [u8]) {
+        for (i, n) in next.iter_mut().enumerate().take(cells.len()) {
+            let mut counts = [0u8; MAX_STATES];
+            for dir in 0..topology.directions() {
+                if let Some(c) = topology.neighbor(i, dir).and_then(|j| cells.get(j)) {
+                    counts[(*c as usize).min(MAX_STATES - 1)] += 1;
+                }
+            }
+            *n = self.next(cells[i], &counts);
+        }
+    }
+
+    fn seed(&mut self, _topology: &dyn Topology, cells: &mut [u8], rng: &mut XorShift32) {
+        for c in cells.iter_mut() {
+            *c = self.random_state(rng);
+        }
+    }
+
+    fn extinct(&self, cells: &[u8]) -> bool {
+        Totalistic::extinct(self, cells)
+    }
+
+    fn color_index(&self, state: u8) -> u8 {
+        Totalistic::color_index(self, state)
+    }
+
+    fn hash_state(&self, state: u8) -> u8 {
+        Totalistic::hash_state(self, state)
+    }
+}
+
+/// Two state rules like Conway's Life, given as bit masks of the neighbor
+/// counts that let a cell be born or survive.
+///
+/// Living cells count their age (up to 255), older cells are colored further
+/// along the palette.
+#[derive(Clone, Copy, Debug)]
+pub struct LifeLike {
+    pub birth: u16,
+    pub survive: u16,
+}
+
+impl LifeLike {
+    pub const fn new(birth: u16, survive: u16) -> Self {
+        LifeLike { birth, survive }
+    }
+}
+
+/// B2/S34, which works well with six neighbors
+pub const HEX_LIFE: LifeLike = LifeLike::new(1 << 2, 1 << 3 | 1 << 4);
+/// B3/S23, meant for [`SquareNeighbors::moore`]
+pub const CONWAY: LifeLike = LifeLike::new(1 << 3, 1 << 2 | 1 << 3);
+
+impl Totalistic for LifeLike {
+    fn states(&self) -> u8 {
+        255
+    }
+
+    fn next(&self, state: u8, counts: &[u8; MAX_STATES]) -> u8 {
+        let alive: u8 = counts[1..].iter().sum();
+        let mask = 1 << alive;
+        match state {
+            0 if self.birth & mask != 0 => 1,
+            0 => 0,
+            age if self.survive & mask != 0 => age.saturating_add(1),
+            _ => 0,
+        }
+    }
+
+    /// Fills about a third of the board.
+    fn random_state(&self, rng: &mut XorShift32) -> u8 {
+        rng.chance(85) as u8
+    }
+
+    fn color_index(&self, state: u8) -> u8 {
+        state.saturating_mul(16)
+    }
+
+    /// Alive or dead, the age changes even in still lifes.
+    fn hash_state(&self, state: u8) -> u8 {
+        (state != 0) as u8
+    }
+}
+
+/// Brian's Brain: off cells with exactly two firing neighbors fire, firing
+/// cells start dying and dying cells turn off.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BriansBrain;
+
+const BRAIN_FIRING: u8 = 1;
+const BRAIN_DYING: u8 = 2;
+
+impl Totalistic for BriansBrain {
+    fn states(&self) -> u8 {
+        3
+    }
+
+    fn next(&self, state: u8, counts: &[u8; MAX_STATES]) -> u8 {
+        match state {
+            0 if counts[BRAIN_FIRING as usize] == 2 => BRAIN_FIRING,
+            BRAIN_FIRING => BRAIN_DYING,
+            _ => 0,
+        }
+    }
+
+    fn random_state(&self, rng: &mut XorShift32) -> u8 {
+        rng.chance(80) as u8
+    }
+}
+
+/// Wireworld: electrons (a head followed by a tail) travel along conductors.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Wireworld;
+
+pub const WIRE_HEAD: u8 = 1;
+pub const WIRE_TAIL: u8 = 2;
+pub const WIRE_CONDUCTOR: u8 = 3;
+
+impl Totalistic for Wireworld {
+    fn states(&self) -> u8 {
+        4
+    }
+
+    fn next(&self, state: u8, counts: &[u8; MAX_STATES]) -> u8 {
+        match state {
+            WIRE_HEAD => WIRE_TAIL,
+            WIRE_TAIL => WIRE_CONDUCTOR,
+            WIRE_CONDUCTOR => match counts[WIRE_HEAD as usize] {
+                1 | 2 => WIRE_HEAD,
+                _ => WIRE_CONDUCTOR,
+            },
+            _ => 0,
+        }
+    }
+
+    /// A random mesh of conductors with a few electrons.
+    fn random_state(&self, rng: &mut XorShift32) -> u8 {
+        match rng.next_u8() {
+            0..=5 => WIRE_HEAD,
+            6..=120 => WIRE_CONDUCTOR,
+            _ => 0,
+        }
+    }
+
+    fn extinct(&self, cells: &[u8]) -> bool {
+        !cells.iter().any(|c| *c == WIRE_HEAD || *c == WIRE_TAIL)
+    }
+}
+
+/// A turmite: an ant that turns depending on the state of its cell, then
+/// advances the state of the cell and moves on.
+///
+/// `turns[s]` is the turn in state `s`, in directions of the topology
+/// (positive is clockwise). The cell then changes to state `s + 1`, wrapping
+/// around after the last state.
+pub struct Ant {
+    turns: &'static [i8],
+    index: usize,
+    dir: usize,
+}
+
+impl Ant {
+    pub const fn new(turns: &'static [i8]) -> Self {
+        Ant {
+            turns,
+            index: 0,
+            dir: 0,
+        }
+    }
+
+    /// Langton's ant (RL), meant for [`SquareNeighbors::von_neumann`].
+    pub const fn langton() -> Self {
+        Ant::new(&[1, -1])
+    }
+
+    /// The hex ant L2 N N L1 L2 L1, which builds a highway.
+    pub const fn hex() -> Self {
+        Ant::new(&[-2, 0, 0, -1, -2, -1])
+    }
+}
+
+impl Rule for Ant {
+    fn states(&self) -> u8 {
+        self.turns.len() as u8
+    }
+
+    fn step(&mut self, topology: &dyn Topology, cells: &[u8], next: &mut [u8]) {
+        next.copy_from_slice(cells);
+        let dirs = topology.directions() as i32;
+        let state = cells[self.index] as usize % self.turns.len();
+        self.dir = (self.dir as i32 + self.turns[state] as i32).rem_euclid(dirs) as usize;
+        next[self.index] = ((state + 1) % self.turns.len()) as u8;
+
+        match topology.neighbor(self.index, self.dir) {
+            Some(n) if n < cells.len() => self.index = n,
+            // at the edge, turn around
+            _ => self.dir = (self.dir + dirs as usize / 2) % dirs as usize,
+        }
+    }
+
+    /// An empty board with the ant in the middle.
+    fn seed(&mut self, topology: &dyn Topology, cells: &mut [u8], _rng: &mut XorShift32) {
+        cells.fill(0);
+        self.index = topology.len().min(cells.len()) / 2;
+        self.dir = 0;
+    }
+
+    fn extinct(&self, _cells: &[u8]) -> bool {
+        false
+    }
+}
+
+/// A group of cells, in doubled hex coordinates relative to its first cell.
+#[derive(Clone, Copy, Debug)]
+pub struct Pattern {
+    pub name: &'static str,
+    pub cells: &'static [(i16, i16)],
+    /// generations until the pattern repeats on its own, 1 for still lifes
+    pub period: u32,
+}
+
+/// Oscillators of [`HEX_LIFE`] with their periods, found by simulating random
+/// soups. None of them moves, a board of patterns only changes when they grow
+/// into each other.
+///
+/// There are no gliders to add: none came out of 200 000 random soups, and
+/// an exhaustive search of all patterns of up to 10 cells in 4 rows (up to 8
+/// cells in 5 rows) found none either.
+pub const HEX_LIFE_PATTERNS: &[Pattern] = &[
+    Pattern {
+        name: "pair",
+        cells: &[(0, 0), (2, 0)],
+        period: 2,
+    },
+    Pattern {
+        name: "triangle",
+        cells: &[(0, 0), (0, 2), (3, 1)],
+        period: 2,
+    },
+    Pattern {
+        name: "ring",
+        cells: &[(0, 0), (0, 2), (3, -1), (3, 3), (6, 0), (6, 2)],
+        period: 3,
+    },
+    Pattern {
+        name: "bar",
+        cells: &[(0, 0), (0, 2), (0, 4)],
+        period: 4,
+    },
+    Pattern {
+        name: "hook",
+        cells: &[(0, 0), (2, 0), (2, 2), (3, 1)],
+        period: 4,
+    },
+    Pattern {
+        name: "spark",
+        cells: &[(0, 0), (0, 2), (3, 3), (6, 2), (7, 1)],
+        period: 12,
+    },
+    Pattern {
+        name: "flower",
+        cells: &[(0, 2), (1, 3), (2, 0), (3, 1), (4, 2), (4, 4)],
+        period: 12,
+    },
+    Pattern {
+        name: "pulsar",
+        cells: &[(0, 2), (3, 1), (4, 0), (5, 1), (7, -1), (8, 0)],
+        period: 48,
+    },
+];
+
+/// Rotates a doubled hex offset by `turns` times 60° clockwise.
+fn rotate(offset: (i16, i16), turns: u32) -> (i16, i16) {
+    // cube coordinates
+    let (mut q, mut r) = ((offset.0 - offset.1) / 2, offset.1);
+    for _ in 0..turns % 6 {
+        let s = -q - r;
+        let (q1, r1) = (-r, -s);
+        q = q1;
+        r = r1;
+    }
+    (2 * q + r, r)
+}
+
+/// How new boards are set up.
+#[derive(Clone, Copy, Debug)]
+pub enum Seeding {
+    /// by the rule, usually a random soup
+    Random,
+    /// `count` patterns at random places, rotated randomly
+    Patterns(&'static [Pattern], u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transition {
+    FadeOut { start: u32 },
+    FadeIn { start: u32 },
+}
+
+/// Runs a [`Rule`] as an [`Effect`], one generation every `generation_ms`.
+pub struct Automaton<T, R, const N: usize> {
+    topology: T,
+    pub rule: R,
+    pub seeding: Seeding,
+    /// start over when a still life or an oscillator is detected, turn off
+    /// for rules where that is the expected outcome, e.g. wireworld circuits
+    pub reseed_stagnant: bool,
+    cells: [u8; N],
+    next: [u8; N],
+    rng: XorShift32,
+    generation_ms: u32,
+    generation_time: u32,
+    history: [u32; HISTORY_LEN],
+    history_len: usize,
+    history_pos: usize,
+    /// generations since the last seed
+    generations: u32,
+    transition: Option<Transition>,
+}
+
+impl<T, R, const N: usize> Automaton<T, R, N> {
+    pub const fn new(topology: T, rule: R, seed: u32, generation_ms: u32) -> Self {
+        Automaton {
+            topology,
+            rule,
+            seeding: Seeding::Random,
+            reseed_stagnant: true,
+            cells: [0; N],
+            next: [0; N],
+            rng: XorShift32::new(seed),
+            generation_ms,
+            generation_time: 0,
+            history: [0; HISTORY_LEN],
+            history_len: 0,
+            history_pos: 0,
+            generations: 0,
+            transition: None,
+        }
+    }
+
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [u8] {
+        &mut self.cells
+    }
+
+    /// Number of non-empty cells.
+    pub fn population(&self) -> usize {
+        self.cells.iter().filter(|c| **c != 0).count()
+    }
+}
+
+impl<T: Topology, R: Rule, const N: usize> Automaton<T, R, N> {
+    pub fn seed(&mut self) {
+        match self.seeding {
+            Seeding::Random => self
+                .rule
+                .seed(&self.topology, &mut self.cells, &mut self.rng),
+            Seeding::Patterns(patterns, count) => {
+                self.cells.fill(0);
+                let len = self.topology.len().min(N) as u32;
+                for _ in 0..count {
+                    if patterns.is_empty() || len == 0 {
+                        break;
+                    }
+                    let pattern = &patterns[self.rng.below(patterns.len() as u32) as usize];
+                    let anchor = self.rng.below(len) as usize;
+                    let turns = self.rng.below(6);
+                    for offset in pattern.cells.iter() {
+                        let cell = self.topology.offset(anchor, rotate(*offset, turns));
+                        if let Some(cell) = cell.filter(|c| *c < N) {
+                            self.cells[cell] = 1;
+                        }
+                    }
+                }
+            }
+        }
+        self.history_len = 0;
+        self.generations = 0;
+    }
+
+    pub fn step(&mut self) {
+        self.rule.step(&self.topology, &self.cells, &mut self.next);
+        core::mem::swap(&mut self.cells, &mut self.next);
+        self.generations = self.generations.saturating_add(1);
+    }
+
+    /// FNV-1a over the states of all cells.
+    fn hash(&self) -> u32 {
+        self.cells.iter().fold(0x811c_9dc5, |h, c| {
+            (h ^ self.rule.hash_state(*c) as u32).wrapping_mul(0x0100_0193)
+        })
+    }
+
+    /// Remembers the current generation and returns true if it was seen
+    /// before, i.e. the automaton is stuck in a cycle.
+    fn stagnant(&mut self) -> bool {
+        let hash = self.hash();
+        let seen = self.history[..self.history_len].contains(&hash);
+        self.history[self.history_pos] = hash;
+        self.history_pos = (self.history_pos + 1) % HISTORY_LEN;
+        self.history_len = (self.history_len + 1).min(HISTORY_LEN);
+        seen
+    }
+}
+
+impl<T: Topology + Send, R: Rule, const N: usize> Effect for Automaton<T, R, N> {
+    fn reset(&mut self) {
+        self.seed();
+        self.generation_time = 0;
+        self.transition = None;
+    }
+
+    fn control(&mut self, control: Control) -> bool {
+        match control {
+            Control::Reseed(seed) => self.rng = XorShift32::new(seed),
+            _ => return false,
+        }
+        true
+    }
+
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        if t < self.generation_time {
+            // restarted
+            self.generation_time = 0;
+            self.transition = None;
+        }
+        let interval = params.scale_interval(self.generation_ms).max(1);
+        let behind = (t - self.generation_time) / interval;
+        // don't try to catch up after long pauses
+        for _ in 0..behind.min(MAX_GENERATIONS_PER_FRAME) {
+            self.step();
+            if self.rule.extinct(&self.cells) {
+                // nothing to fade out
+                self.seed();
+                self.transition = Some(Transition::FadeIn { start: t });
+            } else if self.stagnant()
+                && self.reseed_stagnant
+                && self.generations >= MIN_GENERATIONS
+                && self.transition.is_none()
+            {
+                self.transition = Some(Transition::FadeOut { start: t });
+            }
+        }
+        self.generation_time += behind * interval;
+
+        let brightness = match self.transition {
+            Some(Transition::FadeOut { start }) if t - start < RESEED_FADE_MS => {
+                255 - (t - start) * 255 / RESEED_FADE_MS
+            }
+            Some(Transition::FadeOut { .. }) => {
+                self.seed();
+                self.transition = Some(Transition::FadeIn { start: t });
+                0
+            }
+            Some(Transition::FadeIn { start }) if t - start < RESEED_FADE_MS => {
+                (t - start) * 255 / RESEED_FADE_MS
+            }
+            _ => {
+                self.transition = None;
+                255
+            }
+        } as u8;
+
+        for (c, state) in frame.iter_mut().zip(self.cells.iter()) {
+            *c = match state {
+                0 => color::BLACK,
+                s => color::scale(params.palette.lookup(self.rule.color_index(*s)), brightness),
+            };
+        }
+    }
+}
+
+/// Hex Life on the panel (rule B2/S34), see [`HEX_LIFE`].
+pub type Life<const N: usize> = Automaton<&'static Layout, LifeLike, N>;
+
+/// Time between two generations of [`Life`] at normal speed, in ms.
+pub const LIFE_GENERATION_MS: u32 = 200;
+
+/// Number of patterns on a board of [`Automaton::life_patterns`].
+pub const LIFE_PATTERN_COUNT: u8 = 8;
+
+impl<const N: usize> Automaton<&'static Layout, LifeLike, N> {
+    pub const fn life(layout: &'static Layout, seed: u32) -> Self {
+        Automaton::new(layout, HEX_LIFE, seed, LIFE_GENERATION_MS)
+    }
+
+    /// [`Life`] on boards made of [`HEX_LIFE_PATTERNS`] instead of a random
+    /// soup.
+    pub const fn life_patterns(layout: &'static Layout, seed: u32) -> Self {
+        Automaton {
+            seeding: Seeding::Patterns(HEX_LIFE_PATTERNS, LIFE_PATTERN_COUNT),
+            ..Automaton::life(layout, seed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex::{self, Row};
+
+    /// 7 × 7 cells, wired row by row from the top left.
+    static GRID: Layout = Layout {
+        rows: &[
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+        ],
+        serpentine: false,
+    };
+    const CELLS: usize = 49;
+
+    /// Cell index from a column and row, in either topology.
+    fn at(col: i16, row: i16) -> usize {
+        GRID.index(2 * col + row % 2, row).unwrap()
+    }
+
+    /// The non-empty cells as (column, row, state), in index order.
+    fn live<T, R>(automaton: &Automaton<T, R, CELLS>) -> ([(i16, i16, u8); 8], usize) {
+        let mut out = [(0, 0, 0); 8];
+        let mut len = 0;
+        for (i, state) in automaton.cells().iter().enumerate() {
+            if *state != 0 {
+                let (x, y) = GRID.position(i).unwrap();
+                out[len] = (x / 2, y, *state);
+                len += 1;
+            }
+        }
+        (out, len)
+    }
+
+    fn assert_live<T, R>(automaton: &Automaton<T, R, CELLS>, expected: &[(i16, i16, u8)]) {
+        let (cells, len) = live(automaton);
+        assert_eq!(&cells[..len], expected);
+    }
+
+    #[test]
+    fn brain_fires_between_two_firing_cells() {
+        let mut brain = Automaton::<_, _, CELLS>::new(&GRID, BriansBrain, 1, 100);
+        brain.cells_mut()[at(2, 3)] = BRAIN_FIRING;
+        brain.cells_mut()[at(3, 3)] = BRAIN_FIRING;
+        brain.step();
+        // the two cells above and below touch both
+        assert_live(
+            &brain,
+            &[
+                (3, 2, BRAIN_FIRING),
+                (2, 3, BRAIN_DYING),
+                (3, 3, BRAIN_DYING),
+                (3, 4, BRAIN_FIRING),
+            ],
+        );
+        brain.step();
+        assert_live(&brain, &[(3, 2, BRAIN_DYING), (3, 4, BRAIN_DYING)]);
+        brain.step();
+        assert_eq!(brain.population(), 0);
+    }
+
+    #[test]
+    fn electron_moves_along_the_wire() {
+        let mut wireworld = Automaton::<_, _, CELLS>::new(&GRID, Wireworld, 1, 100);
+        for col in 0..7 {
+            wireworld.cells_mut()[at(col, 3)] = WIRE_CONDUCTOR;
+        }
+        wireworld.cells_mut()[at(0, 3)] = WIRE_TAIL;
+        wireworld.cells_mut()[at(1, 3)] = WIRE_HEAD;
+        for _ in 0..3 {
+            wireworld.step();
+        }
+        let mut wire = [0; 7];
+        for (col, c) in wire.iter_mut().enumerate() {
+            *c = wireworld.cells()[at(col as i16, 3)];
+        }
+        let (c, h, t) = (WIRE_CONDUCTOR, WIRE_HEAD, WIRE_TAIL);
+        assert_eq!(wire, [c, c, c, t, h, c, c]);
+        // runs off the end of the wire
+        for _ in 0..4 {
+            wireworld.step();
+        }
+        assert!(Rule::extinct(&wireworld.rule, wireworld.cells()));
+    }
+
+    #[test]
+    fn langtons_ant_turns_right_on_empty_cells() {
+        let mut ant = Automaton::<_, _, CELLS>::new(
+            SquareNeighbors::von_neumann(&GRID),
+            Ant::langton(),
+            1,
+            100,
+        );
+        ant.seed();
+        assert_eq!(ant.rule.index, at(3, 3));
+        // right, down, left, up: a square clockwise
+        for _ in 0..4 {
+            ant.step();
+        }
+        assert_eq!(ant.rule.index, at(3, 3));
+        assert_live(&ant, &[(2, 3, 1), (3, 3, 1), (2, 4, 1), (3, 4, 1)]);
+        // back on a visited cell, it turns left and clears it
+        ant.step();
+        assert_eq!(ant.rule.index, at(3, 2));
+        assert_live(&ant, &[(2, 3, 1), (2, 4, 1), (3, 4, 1)]);
+    }
+
+    #[test]
+    fn hex_ant_first_moves() {
+        let mut ant = Automaton::<_, _, CELLS>::new(&GRID, Ant::hex(), 1, 100);
+        ant.seed();
+        let start = GRID.position(ant.rule.index).unwrap();
+        assert_eq!(start, (7, 3));
+        // L2 to the upper left, L2 to the lower left, L2 back to the start
+        let path = [(6, 2), (5, 3), (7, 3), (9, 3)];
+        for &(x, y) in path.iter() {
+            ant.step();
+            assert_eq!(GRID.position(ant.rule.index), Some((x, y)));
+        }
+        // the start cell was visited twice, the N went straight on
+        assert_eq!(ant.cells()[GRID.index(7, 3).unwrap()], 2);
+        assert_eq!(ant.cells()[GRID.index(6, 2).unwrap()], 1);
+        assert_eq!(ant.cells()[GRID.index(5, 3).unwrap()], 1);
+        assert_eq!(ant.population(), 3);
+    }
+
+    /// The panel with `pattern` in the middle.
+    fn panel_with(pattern: &Pattern) -> Life<291> {
+        let mut life = Life::life(&hex::PANEL, 1);
+        let center = hex::PANEL.index(17, 10).unwrap();
+        for offset in pattern.cells.iter() {
+            let cell = hex::PANEL.offset(center, *offset).unwrap();
+            life.cells_mut()[cell] = 1;
+        }
+        life
+    }
+
+    fn alive(cells: &[u8]) -> impl Iterator<Item = bool> + '_ {
+        cells.iter().map(|c| *c != 0)
+    }
+
+    #[test]
+    fn patterns_repeat_after_their_period() {
+        for pattern in HEX_LIFE_PATTERNS.iter() {
+            let mut life = panel_with(pattern);
+            let start = life.cells;
+            for generation in 1..=pattern.period {
+                life.step();
+                let repeated = alive(life.cells()).eq(alive(&start));
+                assert_eq!(
+                    repeated,
+                    generation == pattern.period,
+                    "{} in generation {}",
+                    pattern.name,
+                    generation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn patterns_are_detected_as_stagnant() {
+        for pattern in HEX_LIFE_PATTERNS.iter() {
+            let mut life = panel_with(pattern);
+            let mut detected = false;
+            for _ in 0..2 * pattern.period {
+                life.step();
+                detected |= life.stagnant();
+            }
+            assert!(detected, "{}", pattern.name);
+        }
+    }
+
+    #[test]
+    fn oscillators_are_shown_before_they_count_as_stagnant() {
+        let mut life = panel_with(&HEX_LIFE_PATTERNS[0]);
+        let params = Params::default();
+        let mut frame = [color::BLACK; 291];
+        let mut t = 0;
+        while life.generations < MIN_GENERATIONS {
+            assert_eq!(life.transition, None, "generation {}", life.generations);
+            t += LIFE_GENERATION_MS;
+            life.render(t, &params, &mut frame);
+        }
+        assert_eq!(life.transition, Some(Transition::FadeOut { start: t }));
+
+        // the next board is made of patterns again
+        let mut life = Life::<291>::life_patterns(&hex::PANEL, 1);
+        life.reset();
+        assert!(life.population() > 0);
+        assert!(life.population() <= LIFE_PATTERN_COUNT as usize * 6);
+    }
+
+    #[test]
+    fn block_is_a_still_life() {
+        let mut life = Automaton::<_, _, CELLS>::new(SquareNeighbors::moore(&GRID), CONWAY, 1, 100);
+        for &(col, row) in [(2, 2), (3, 2), (2, 3), (3, 3)].iter() {
+            life.cells_mut()[at(col, row)] = 1;
+        }
+        assert!(!life.stagnant());
+        life.step();
+        // the cells get older, but the board is the same
+        assert_live(&life, &[(2, 2, 2), (3, 2, 2), (2, 3, 2), (3, 3, 2)]);
+        assert!(life.stagnant());
+    }
+}
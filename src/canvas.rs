@@ -0,0 +1,234 @@
+//! Drawing onto the hex panel with `embedded_graphics`.
+//!
+//! A [`HexCanvas`] is a `W` × `H` pixel `DrawTarget<Rgb888>`, so text,
+//! primitives and images can be drawn exactly like on the OLED. The pixel
+//! grid is stretched over the panel and [`HexCanvas::render`] samples it at
+//! the LED positions:
+//!
+//! ```ignore
+//! static mut CANVAS: HexCanvas<34, 21> = HexCanvas::new(&hex::PANEL, Sampling::Average);
+//!
+//! canvas.clear(Rgb888::BLACK).unwrap();
+//! Text::new("Hi", Point::new(2, 6))
+//!     .into_styled(TextStyle::new(Font6x8, Rgb888::RED))
+//!     .draw(canvas)
+//!     .unwrap();
+//! canvas.render(&mut frame);
+//! ```
+
+use crate::{color::BLACK, hex::Layout};
+use embedded_graphics::{
+    drawable::Pixel,
+    geometry::Size,
+    pixelcolor::{Rgb888, RgbColor},
+    DrawTarget,
+};
+use smart_leds::RGB8;
+
+/// How the color of an LED is taken from the pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    /// the pixel under the center of the LED, sharp but thin lines can be missed
+    Nearest,
+    /// the average of all pixels covered by the LED, smoother
+    Average,
+}
+
+/// A pixel grid that is rendered onto the LEDs of a [`Layout`].
+///
+/// Every LED covers two units of the doubled x coordinate and one row, so
+/// with `W = layout.width() / 2` and `H = layout.height()` there is about one
+/// pixel per LED. Larger grids give more room for detail, e.g. text.
+pub struct HexCanvas<const W: usize, const H: usize> {
+    layout: &'static Layout,
+    pixels: [[RGB8; W]; H],
+    pub sampling: Sampling,
+}
+
+impl<const W: usize, const H: usize> HexCanvas<W, H> {
+    pub const fn new(layout: &'static Layout, sampling: Sampling) -> Self {
+        HexCanvas {
+            layout,
+            pixels: [[BLACK; W]; H],
+            sampling,
+        }
+    }
+
+    /// Pixels outside of the canvas are black, like the canvas was
+    /// surrounded by an unlit area.
+    pub fn pixel(&self, x: usize, y: usize) -> RGB8 {
+        match self.pixels.get(y).and_then(|row| row.get(x)) {
+            Some(c) => *c,
+            None => BLACK,
+        }
+    }
+
+    /// Pixels outside of the canvas are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, c: RGB8) {
+        if x < W && y < H {
+            self.pixels[y][x] = c;
+        }
+    }
+
+    pub fn fill(&mut self, c: RGB8) {
+        for row in self.pixels.iter_mut() {
+            row.fill(c);
+        }
+    }
+
+    /// Pixel columns and rows covered by the LED at `(x, y)` (doubled
+    /// coordinates), at least one pixel in each direction.
+    fn area(&self, x: i16, y: i16) -> (usize, usize, usize, usize) {
+        // the rightmost LED reaches one unit past the width
+        let extent = (self.layout.width() + 1) as usize;
+        let rows = self.layout.height() as usize;
+        let (x, y) = (x as usize, y as usize);
+
+        let x0 = (x * W / extent).min(W - 1);
+        let x1 = ((x + 2) * W / extent).max(x0 + 1).min(W);
+        let y0 = (y * H / rows).min(H - 1);
+        let y1 = ((y + 1) * H / rows).max(y0 + 1).min(H);
+        (x0, x1, y0, y1)
+    }
+
+    /// Samples the pixels at the LED positions into `frame`.
+    pub fn render(&self, frame: &mut [RGB8]) {
+        if W == 0 || H == 0 {
+            return;
+        }
+        let extent = (self.layout.width() + 1) as usize;
+        let rows = self.layout.height() as usize;
+
+        for (i, c) in frame.iter_mut().enumerate() {
+            let (x, y) = match self.layout.position(i) {
+                Some(p) => p,
+                None => break,
+            };
+            *c = match self.sampling {
+                Sampling::Nearest => {
+                    let px = ((x as usize + 1) * W / extent).min(W - 1);
+                    let py = ((2 * y as usize + 1) * H / (2 * rows)).min(H - 1);
+                    self.pixels[py][px]
+                }
+                Sampling::Average => {
+                    let (x0, x1, y0, y1) = self.area(x, y);
+                    let mut sum = [0u32; 3];
+                    for row in self.pixels[y0..y1].iter() {
+                        for p in row[x0..x1].iter() {
+                            sum[0] += p.r as u32;
+                            sum[1] += p.g as u32;
+                            sum[2] += p.b as u32;
+                        }
+                    }
+                    let n = ((x1 - x0) * (y1 - y0)) as u32;
+                    RGB8 {
+                        r: (sum[0] / n) as u8,
+                        g: (sum[1] / n) as u8,
+                        b: (sum[2] / n) as u8,
+                    }
+                }
+            };
+        }
+    }
+}
+
+impl<const W: usize, const H: usize> DrawTarget<Rgb888> for HexCanvas<W, H> {
+    type Error = core::convert::Infallible;
+
+    fn draw_pixel(&mut self, pixel: Pixel<Rgb888>) -> Result<(), Self::Error> {
+        let Pixel(p, c) = pixel;
+        if p.x >= 0 && p.y >= 0 {
+            self.set_pixel(
+                p.x as usize,
+                p.y as usize,
+                RGB8 {
+                    r: c.r(),
+                    g: c.g(),
+                    b: c.b(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Size {
+        Size::new(W as u32, H as u32)
+    }
+
+    fn clear(&mut self, c: Rgb888) -> Result<(), Self::Error> {
+        self.fill(RGB8 {
+            r: c.r(),
+            g: c.g(),
+            b: c.b(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{BLUE, CYAN, GREEN, MAGENTA, RED};
+    use crate::hex::{self, Row};
+
+    /// Two rows of two LEDs, at (0, 0), (2, 0), (1, 1) and (3, 1). With five
+    /// units of width, a 10 × 4 canvas has 2 × 2 pixels per unit and row.
+    static PAIRS: Layout = Layout {
+        rows: &[Row::new(2, 0), Row::new(2, 1)],
+        serpentine: false,
+    };
+
+    #[test]
+    fn pixels_outside_are_black() {
+        let mut canvas = HexCanvas::<4, 3>::new(&hex::PANEL, Sampling::Nearest);
+        canvas.fill(crate::color::RED);
+        canvas.set_pixel(4, 0, crate::color::GREEN);
+        canvas.set_pixel(0, 3, crate::color::GREEN);
+        assert_eq!(canvas.pixel(3, 2), crate::color::RED);
+        assert_eq!(canvas.pixel(4, 0), BLACK);
+        assert_eq!(canvas.pixel(0, 3), BLACK);
+        assert_eq!(canvas.pixel(usize::MAX, usize::MAX), BLACK);
+    }
+
+    #[test]
+    fn nearest_takes_the_pixel_under_the_center() {
+        let mut canvas = HexCanvas::<10, 4>::new(&PAIRS, Sampling::Nearest);
+        canvas.fill(BLUE);
+        canvas.set_pixel(2, 1, RED);
+        canvas.set_pixel(6, 1, GREEN);
+        canvas.set_pixel(4, 3, CYAN);
+        canvas.set_pixel(8, 3, BLACK);
+        let mut frame = [BLACK; 4];
+        canvas.render(&mut frame);
+        assert_eq!(frame, [RED, GREEN, CYAN, BLACK]);
+    }
+
+    #[test]
+    fn average_takes_all_covered_pixels() {
+        let mut canvas = HexCanvas::<10, 4>::new(&PAIRS, Sampling::Average);
+        // half of the 4 × 2 pixels of the first LED
+        for x in 0..4 {
+            canvas.set_pixel(
+                x,
+                0,
+                RGB8 {
+                    r: 200,
+                    g: 100,
+                    b: 40,
+                },
+            );
+        }
+        // one pixel at the right edge of the last LED
+        canvas.set_pixel(9, 3, CYAN);
+        let mut frame = [MAGENTA; 5];
+        canvas.render(&mut frame);
+        let dim = RGB8 { r: 0, g: 31, b: 31 };
+        let half = RGB8 {
+            r: 100,
+            g: 50,
+            b: 20,
+        };
+        // LEDs past the layout are left alone
+        assert_eq!(frame, [half, BLACK, BLACK, dim, MAGENTA]);
+    }
+}
@@ -0,0 +1,230 @@
+//! Animation time base that is independent of the frame rate.
+//!
+//! The clock follows the instants of the RTIC monotonic timer (typically
+//! `cx.scheduled`), so the animation speed does not change with the refresh
+//! period of the task that drives it. It can be paused and run faster or
+//! slower than real time.
+//!
+//! The [`WallClock`] follows the same timer at real time, in between the
+//! syncs with a real time clock.
+
+/// Instants of a monotonic timer that an [`AnimationClock`] can follow.
+pub trait Ticks: Copy {
+    /// Timer ticks elapsed since `earlier`.
+    fn ticks_since(self, earlier: Self) -> u32;
+}
+
+#[cfg(feature = "device")]
+impl Ticks for rtic::cyccnt::Instant {
+    fn ticks_since(self, earlier: Self) -> u32 {
+        self.duration_since(earlier).as_cycles()
+    }
+}
+
+/// Speed multiplier of real time, in 8.8 fixed point.
+pub const REAL_TIME: u16 = 256;
+
+pub struct AnimationClock<I> {
+    ticks_per_ms: u32,
+    last: Option<I>,
+    /// scaled ticks that did not make up a full ms yet
+    remainder: u64,
+    elapsed_ms: u32,
+    speed: u16,
+    paused: bool,
+}
+
+impl<I: Ticks> AnimationClock<I> {
+    pub fn new(ticks_per_ms: u32) -> Self {
+        AnimationClock {
+            ticks_per_ms,
+            last: None,
+            remainder: 0,
+            elapsed_ms: 0,
+            speed: REAL_TIME,
+            paused: false,
+        }
+    }
+
+    /// Advances the clock to `now` and returns the animation time in ms.
+    ///
+    /// Must be called more often than the underlying timer wraps around.
+    pub fn tick(&mut self, now: I) -> u32 {
+        if let Some(last) = self.last {
+            if !self.paused {
+                self.advance(now.ticks_since(last));
+            }
+        }
+        self.last = Some(now);
+        self.elapsed_ms
+    }
+
+    fn advance(&mut self, ticks: u32) {
+        let scaled = self.remainder + ticks as u64 * self.speed as u64;
+        let per_ms = self.ticks_per_ms as u64 * REAL_TIME as u64;
+        self.elapsed_ms = self.elapsed_ms.wrapping_add((scaled / per_ms) as u32);
+        self.remainder = scaled % per_ms;
+    }
+
+    /// Animation time in ms as of the last [`AnimationClock::tick`].
+    pub fn now_ms(&self) -> u32 {
+        self.elapsed_ms
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Sets the speed multiplier in 8.8 fixed point, e.g. 64 for quarter
+    /// speed slow motion or 512 for double speed.
+    pub fn set_speed(&mut self, speed: u16) {
+        self.speed = speed;
+    }
+
+    pub fn speed(&self) -> u16 {
+        self.speed
+    }
+
+    /// Moves the animation time forward by `ms`, also while paused.
+    pub fn step(&mut self, ms: u32) {
+        self.elapsed_ms = self.elapsed_ms.wrapping_add(ms);
+    }
+}
+
+const MS_PER_DAY: u32 = 86_400_000;
+
+/// Time of day that runs on with the monotonic timer in between the syncs
+/// with a real time clock, unaffected by pausing or the animation speed.
+pub struct WallClock<I> {
+    ticks_per_ms: u32,
+    /// ms since midnight and the instant they were valid at
+    synced: Option<(u32, I)>,
+}
+
+impl<I: Ticks> WallClock<I> {
+    pub fn new(ticks_per_ms: u32) -> Self {
+        WallClock {
+            ticks_per_ms,
+            synced: None,
+        }
+    }
+
+    /// Syncs with the `seconds` since midnight of a real time clock.
+    ///
+    /// The seconds are truncated, so the clock is only set back when it is
+    /// off by a full second, otherwise the clock would stutter at each sync.
+    /// Must be called more often than the underlying timer wraps around.
+    pub fn sync(&mut self, now: I, seconds: u32) {
+        let ms = seconds % 86_400 * 1000;
+        if let Some(current) = self.ms_of_day(now) {
+            let ahead = (current + MS_PER_DAY - ms) % MS_PER_DAY;
+            if ahead < 1000 {
+                return;
+            }
+        }
+        self.synced = Some((ms, now));
+    }
+
+    /// ms since midnight at `now`, `None` until the first sync.
+    pub fn ms_of_day(&self, now: I) -> Option<u32> {
+        self.synced.map(|(ms, at)| {
+            let elapsed = now.ticks_since(at) / self.ticks_per_ms;
+            ((ms as u64 + elapsed as u64) % MS_PER_DAY as u64) as u32
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Ticks for u32 {
+        fn ticks_since(self, earlier: Self) -> u32 {
+            self.wrapping_sub(earlier)
+        }
+    }
+
+    #[test]
+    fn carries_the_remainder() {
+        // 3 ticks per ms, the clock only moves on every third tick
+        let mut clock = AnimationClock::new(3);
+        assert_eq!(clock.tick(0u32), 0);
+        assert_eq!(clock.tick(1), 0);
+        assert_eq!(clock.tick(2), 0);
+        assert_eq!(clock.tick(3), 1);
+        assert_eq!(clock.tick(3_001), 1_000);
+
+        // quarter speed, a frame of 25 ms advances 6.25 ms
+        let mut clock = AnimationClock::new(1_000);
+        clock.set_speed(REAL_TIME / 4);
+        clock.tick(0u32);
+        for frame in 1..=4u32 {
+            clock.tick(frame * 25_000);
+        }
+        assert_eq!(clock.now_ms(), 25);
+    }
+
+    #[test]
+    fn wraps_with_the_timer() {
+        let mut clock = AnimationClock::new(1);
+        clock.tick(u32::MAX - 9);
+        assert_eq!(clock.tick(10), 20);
+    }
+
+    #[test]
+    fn pause_and_step() {
+        let mut clock = AnimationClock::new(1);
+        clock.tick(0u32);
+        assert_eq!(clock.tick(100), 100);
+        clock.pause();
+        assert!(clock.is_paused());
+        assert_eq!(clock.tick(200), 100);
+        clock.step(25);
+        assert_eq!(clock.now_ms(), 125);
+        assert_eq!(clock.tick(300), 125);
+        // the time spent paused is skipped
+        clock.resume();
+        assert_eq!(clock.tick(350), 175);
+        clock.toggle_pause();
+        assert_eq!(clock.tick(400), 175);
+    }
+
+    #[test]
+    fn wall_clock_runs_at_real_time() {
+        let mut clock = WallClock::new(1_000);
+        assert_eq!(clock.ms_of_day(0u32), None);
+        clock.sync(0u32, 3_600);
+        assert_eq!(clock.ms_of_day(1_500_000), Some(3_601_500));
+
+        // a second that just started at the RTC keeps the clock running on
+        clock.sync(1_500_000, 3_601);
+        assert_eq!(clock.ms_of_day(1_600_000), Some(3_601_600));
+        // a clock that is too slow or too fast is set
+        clock.sync(1_600_000, 3_603);
+        assert_eq!(clock.ms_of_day(1_600_000), Some(3_603_000));
+        clock.sync(1_700_000, 3_601);
+        assert_eq!(clock.ms_of_day(1_700_000), Some(3_601_000));
+    }
+
+    #[test]
+    fn wall_clock_wraps_at_midnight() {
+        let mut clock = WallClock::new(1);
+        clock.sync(0u32, 86_399);
+        assert_eq!(clock.ms_of_day(1_500), Some(500));
+        // just after midnight at the RTC, the clock is in time
+        clock.sync(1_500, 0);
+        assert_eq!(clock.ms_of_day(1_500), Some(500));
+    }
+}
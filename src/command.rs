@@ -0,0 +1,601 @@
+//! Text commands to control the installation at runtime, e.g. over a serial
+//! port.
+//!
+//! Bytes are collected by a [`LineBuffer`] and every complete line is parsed
+//! into a [`Command`]:
+//!
+//! ```ignore
+//! if let Some(line) = line_buffer.push(byte) {
+//!     match Command::parse(line) {
+//!         Ok(cmd) => execute(cmd),
+//!         Err(e) => writeln!(replies, "error: {}", e).ok(),
+//!     }
+//! }
+//! ```
+//!
+//! Answers go through [`Replies`], so they can be sent in the background.
+
+use crate::game::Input;
+use core::fmt;
+use core::ops::Range;
+use core::str::FromStr;
+
+/// Collects bytes until the end of a line.
+pub struct LineBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// the line lost bytes, it is dropped when it ends
+    broken: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        LineBuffer {
+            buf: [0; N],
+            len: 0,
+            broken: false,
+        }
+    }
+
+    /// Adds a byte and returns the line when it is complete.
+    ///
+    /// Lines end at `\n` or `\r`, empty lines, lines that are longer than the
+    /// buffer or not valid UTF-8 and [discarded](Self::discard) lines are
+    /// dropped.
+    pub fn push(&mut self, byte: u8) -> Option<&str> {
+        if byte != b'\n' && byte != b'\r' {
+            if self.len < N {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.broken = true;
+            }
+            return None;
+        }
+
+        let len = core::mem::replace(&mut self.len, 0);
+        let broken = core::mem::replace(&mut self.broken, false);
+        if broken || len == 0 {
+            return None;
+        }
+        core::str::from_utf8(&self.buf[..len]).ok()
+    }
+
+    /// Drops the current line, up to its end, e.g. after an overrun or a
+    /// framing error lost some of its bytes.
+    pub fn discard(&mut self) {
+        self.broken = true;
+    }
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Replies waiting to be sent.
+///
+/// The command handler writes its answer with `core::fmt::Write` and the
+/// bytes are taken out one by one as the port is ready, e.g. from the
+/// transmit interrupt, so a long answer doesn't block the handler. Text that
+/// does not fit is dropped.
+pub struct Replies<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> Replies<N> {
+    pub const fn new() -> Self {
+        Replies {
+            buf: [0; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The next byte to send, without taking it out.
+    pub fn peek(&self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.buf[self.start])
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.start = (self.start + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+impl<const N: usize> Default for Replies<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for Replies<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len == N {
+                return Err(fmt::Error);
+            }
+            self.buf[(self.start + self.len) % N] = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// switch to the named effect
+    Effect(&'a str),
+    /// switch to the next effect
+    Next,
+    Brightness(u8),
+    /// brightness of the ambient zones at the top and bottom, or the panel
+    /// alone (`None`)
+    Ambient(Option<u8>),
+    /// animation speed, 256 is normal
+    Speed(u16),
+    Palette(&'a str),
+    /// show a text on the marquee
+    Text(&'a str),
+    Pause,
+    Resume,
+    /// speed of the animation clock, 256 is real time
+    Rate(u16),
+    /// move the animation clock forward by the ms, or by a frame (`None`)
+    Step(Option<u32>),
+    /// reseed the effects, with a fixed seed or from the hardware RNG (`None`)
+    Seed(Option<u32>),
+    /// start the named game, or stop playing (`None`)
+    Play(Option<&'a str>),
+    /// start the named show, or stop it (`None`)
+    Show(Option<&'a str>),
+    /// game controls, like the button and an encoder
+    Input(Input),
+    /// set the clock, hours, minutes and seconds
+    Time(u8, u8, u8),
+    /// set the calendar, year, month and day
+    Date(u16, u8, u8),
+    /// add a time-of-day rule, or replace the one at the same time
+    Rule(RuleSpec<'a>),
+    /// list the rules
+    Rules,
+    /// remove a rule by its number in the list, or all of them (`None`)
+    Unrule(Option<usize>),
+    Help,
+}
+
+/// A time-of-day rule as typed, the names are looked up by the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleSpec<'a> {
+    /// minutes since midnight
+    pub at: u16,
+    pub effect: Option<&'a str>,
+    pub palette: Option<&'a str>,
+    pub brightness: Option<u8>,
+    pub fade_minutes: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    UnknownCommand,
+    MissingArgument,
+    InvalidArgument,
+    /// a table, like the time-of-day rules, is full
+    NoRoom,
+    /// the hardware behind the command does not work, e.g. the RTC
+    Unavailable,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::UnknownCommand => "unknown command, try 'help'",
+            Error::MissingArgument => "missing argument",
+            Error::InvalidArgument => "invalid argument",
+            Error::NoRoom => "no room left",
+            Error::Unavailable => "not available",
+        })
+    }
+}
+
+/// One line per command, for the `help` command.
+pub const HELP: &[&str] = &[
+    "effect <name>",
+    "next",
+    "brightness <0-255>",
+    "ambient <0-255|off>",
+    "speed <n> (256 is normal)",
+    "palette <rainbow|heat|ocean|forest>",
+    "text <text>",
+    "pause",
+    "resume",
+    "rate <n> (256 is real time, 64 is slow motion)",
+    "step [<ms>]",
+    "seed <n|random>",
+    "play <snake|pong|off>",
+    "show <name|off>",
+    "left, right, press",
+    "time <hh:mm[:ss]>",
+    "date <yyyy-mm-dd>",
+    "rule <hh:mm> [effect <name>] [palette <name>] [brightness <0-255>|off] [fade <min>]",
+    "rules",
+    "unrule <n|all>",
+];
+
+impl<'a> Command<'a> {
+    /// Parses a line of the form `<command> [<argument>]`.
+    pub fn parse(line: &'a str) -> Result<Self, Error> {
+        let line = line.trim();
+        let (name, arg) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i + 1..].trim()),
+            None => (line, ""),
+        };
+
+        let cmd = match name {
+            "effect" => Command::Effect(required(arg)?),
+            "next" => Command::Next,
+            "brightness" => Command::Brightness(number(arg)?),
+            "ambient" => match required(arg)? {
+                "off" => Command::Ambient(None),
+                b => Command::Ambient(Some(number(b)?)),
+            },
+            "speed" => Command::Speed(number(arg)?),
+            "palette" => Command::Palette(required(arg)?),
+            // the text is taken as is, including inner spaces
+            "text" => Command::Text(required(arg)?),
+            "pause" => Command::Pause,
+            "resume" => Command::Resume,
+            "rate" => Command::Rate(number(arg)?),
+            "step" => match arg {
+                "" => Command::Step(None),
+                ms => Command::Step(Some(number(ms)?)),
+            },
+            "seed" => match required(arg)? {
+                "random" => Command::Seed(None),
+                n => Command::Seed(Some(number(n)?)),
+            },
+            "play" => match required(arg)? {
+                "off" => Command::Play(None),
+                game => Command::Play(Some(game)),
+            },
+            "show" => match required(arg)? {
+                "off" => Command::Show(None),
+                show => Command::Show(Some(show)),
+            },
+            "left" => Command::Input(Input::Left),
+            "right" => Command::Input(Input::Right),
+            "press" => Command::Input(Input::Press),
+            "time" => {
+                let (hour, minute, second) = time_of_day(arg)?;
+                Command::Time(hour, minute, second)
+            }
+            "date" => {
+                let mut fields = required(arg)?.split('-');
+                let year = number_in(fields.next(), 2000..2100)?;
+                let month = number_in(fields.next(), 1..13)?;
+                let day = number_in(fields.next(), 1..days_in_month(year, month) + 1)?;
+                if fields.next().is_some() {
+                    return Err(Error::InvalidArgument);
+                }
+                Command::Date(year, month, day)
+            }
+            "rule" => Command::Rule(rule(arg)?),
+            "rules" => Command::Rules,
+            "unrule" => match required(arg)? {
+                "all" => Command::Unrule(None),
+                n => Command::Unrule(Some(number(n)?)),
+            },
+            "help" => Command::Help,
+            _ => return Err(Error::UnknownCommand),
+        };
+        Ok(cmd)
+    }
+}
+
+fn required(arg: &str) -> Result<&str, Error> {
+    if arg.is_empty() {
+        Err(Error::MissingArgument)
+    } else {
+        Ok(arg)
+    }
+}
+
+fn number<T: FromStr>(arg: &str) -> Result<T, Error> {
+    required(arg)?.parse().map_err(|_| Error::InvalidArgument)
+}
+
+/// Parses a field of a time or date, which is `None` if the argument has too
+/// few fields.
+fn number_in<T: FromStr + PartialOrd>(field: Option<&str>, range: Range<T>) -> Result<T, Error> {
+    let n = number(field.ok_or(Error::InvalidArgument)?)?;
+    if range.contains(&n) {
+        Ok(n)
+    } else {
+        Err(Error::InvalidArgument)
+    }
+}
+
+/// Like the RTC, only right from 2000 to 2099, where every fourth year is a
+/// leap year.
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year & 3 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parses `hh:mm[:ss]`.
+fn time_of_day(arg: &str) -> Result<(u8, u8, u8), Error> {
+    let mut fields = required(arg)?.split(':');
+    let hour = number_in(fields.next(), 0..24)?;
+    let minute = number_in(fields.next(), 0..60)?;
+    let second = match fields.next() {
+        Some(s) => number_in(Some(s), 0..60)?,
+        None => 0,
+    };
+    if fields.next().is_some() {
+        return Err(Error::InvalidArgument);
+    }
+    Ok((hour, minute, second))
+}
+
+/// Parses `<hh:mm>` followed by `<setting> <value>` pairs.
+fn rule(arg: &str) -> Result<RuleSpec<'_>, Error> {
+    let mut words = required(arg)?.split_whitespace();
+    let (hour, minute, second) = time_of_day(words.next().unwrap_or(""))?;
+    if second != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let mut spec = RuleSpec {
+        at: hour as u16 * 60 + minute as u16,
+        effect: None,
+        palette: None,
+        brightness: None,
+        fade_minutes: 0,
+    };
+    while let Some(setting) = words.next() {
+        if setting == "off" {
+            spec.brightness = Some(0);
+            continue;
+        }
+        let value = words.next().ok_or(Error::MissingArgument)?;
+        match setting {
+            "effect" => spec.effect = Some(value),
+            "palette" => spec.palette = Some(value),
+            "brightness" => spec.brightness = Some(number(value)?),
+            "fade" => spec.fade_minutes = number(value)?,
+            _ => return Err(Error::InvalidArgument),
+        }
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn replies_wrap_around() {
+        let mut replies = Replies::<8>::new();
+        write!(replies, "hello").unwrap();
+        assert_eq!(replies.pop(), Some(b'h'));
+        assert_eq!(replies.pop(), Some(b'e'));
+        write!(replies, " you").unwrap();
+        let mut sent = [0; 8];
+        let mut len = 0;
+        while let Some(byte) = replies.pop() {
+            sent[len] = byte;
+            len += 1;
+        }
+        assert_eq!(&sent[..len], b"llo you");
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn replies_drop_what_does_not_fit() {
+        let mut replies = Replies::<4>::new();
+        assert!(write!(replies, "hello").is_err());
+        assert_eq!(replies.peek(), Some(b'h'));
+        assert!(write!(replies, "!").is_err());
+        assert_eq!(replies.pop(), Some(b'h'));
+        write!(replies, "!").unwrap();
+    }
+
+    #[test]
+    fn lines_end_at_a_newline() {
+        let mut lines = LineBuffer::<8>::new();
+        for byte in b"next\r" {
+            if let Some(line) = lines.push(*byte) {
+                assert_eq!(line, "next");
+                return;
+            }
+        }
+        panic!("no line");
+    }
+
+    #[test]
+    fn discarded_lines_are_dropped_up_to_their_end() {
+        let mut lines = LineBuffer::<8>::new();
+        lines.push(b'n');
+        lines.discard();
+        for byte in b"ext\n" {
+            assert_eq!(lines.push(*byte), None);
+        }
+        // the next line is fine again
+        for byte in b"next" {
+            assert_eq!(lines.push(*byte), None);
+        }
+        assert_eq!(lines.push(b'\n'), Some("next"));
+    }
+
+    #[test]
+    fn long_lines_are_dropped() {
+        let mut lines = LineBuffer::<4>::new();
+        for byte in b"pause\n" {
+            assert_eq!(lines.push(*byte), None);
+        }
+        assert_eq!(lines.push(b'\n'), None);
+    }
+
+    #[test]
+    fn effects_are_named() {
+        assert_eq!(Command::parse("effect fire"), Ok(Command::Effect("fire")));
+        assert_eq!(
+            Command::parse("  effect fire \r"),
+            Ok(Command::Effect("fire"))
+        );
+        assert_eq!(Command::parse("effect"), Err(Error::MissingArgument));
+        assert_eq!(Command::parse("effects fire"), Err(Error::UnknownCommand));
+    }
+
+    #[test]
+    fn brightness_is_a_byte() {
+        assert_eq!(Command::parse("brightness 0"), Ok(Command::Brightness(0)));
+        assert_eq!(
+            Command::parse("brightness 255"),
+            Ok(Command::Brightness(255))
+        );
+        assert_eq!(Command::parse("brightness"), Err(Error::MissingArgument));
+        assert_eq!(
+            Command::parse("brightness 256"),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(Command::parse("brightness -1"), Err(Error::InvalidArgument));
+        assert_eq!(
+            Command::parse("brightness half"),
+            Err(Error::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn ambient_is_a_byte_or_off() {
+        assert_eq!(Command::parse("ambient 96"), Ok(Command::Ambient(Some(96))));
+        assert_eq!(Command::parse("ambient off"), Ok(Command::Ambient(None)));
+        assert_eq!(Command::parse("ambient"), Err(Error::MissingArgument));
+        assert_eq!(Command::parse("ambient on"), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn text_keeps_its_spaces() {
+        assert_eq!(
+            Command::parse("text  hello  world "),
+            Ok(Command::Text("hello  world"))
+        );
+        assert_eq!(Command::parse("text"), Err(Error::MissingArgument));
+        assert_eq!(Command::parse("text   "), Err(Error::MissingArgument));
+    }
+
+    #[test]
+    fn rules_take_settings_in_any_order() {
+        assert_eq!(
+            Command::parse("rule 07:30 palette ocean effect fire brightness 40 fade 15"),
+            Ok(Command::Rule(RuleSpec {
+                at: 7 * 60 + 30,
+                effect: Some("fire"),
+                palette: Some("ocean"),
+                brightness: Some(40),
+                fade_minutes: 15,
+            }))
+        );
+        assert_eq!(
+            Command::parse("rule 23:00 off"),
+            Ok(Command::Rule(RuleSpec {
+                at: 23 * 60,
+                effect: None,
+                palette: None,
+                brightness: Some(0),
+                fade_minutes: 0,
+            }))
+        );
+    }
+
+    #[test]
+    fn rules_need_a_valid_time_and_settings() {
+        assert_eq!(Command::parse("rule"), Err(Error::MissingArgument));
+        for line in &[
+            "rule 24:00",
+            "rule 7",
+            "rule 07:30:10",
+            "rule 07:30 colour red",
+            "rule 07:30 brightness 300",
+            "rule 07:30 fade soon",
+        ] {
+            assert_eq!(
+                Command::parse(line),
+                Err(Error::InvalidArgument),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            Command::parse("rule 07:30 effect"),
+            Err(Error::MissingArgument)
+        );
+    }
+
+    #[test]
+    fn unrule_takes_a_number_or_all() {
+        assert_eq!(Command::parse("unrule 2"), Ok(Command::Unrule(Some(2))));
+        assert_eq!(Command::parse("unrule all"), Ok(Command::Unrule(None)));
+        assert_eq!(Command::parse("unrule"), Err(Error::MissingArgument));
+        assert_eq!(Command::parse("unrule first"), Err(Error::InvalidArgument));
+        assert_eq!(Command::parse("unrule -1"), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn errors_are_replied_as_text() {
+        let mut replies = Replies::<64>::new();
+        if let Err(e) = Command::parse("frobnicate") {
+            write!(replies, "error: {}", e).unwrap();
+        }
+        let mut sent = [0; 64];
+        let mut len = 0;
+        while let Some(byte) = replies.pop() {
+            sent[len] = byte;
+            len += 1;
+        }
+        assert_eq!(&sent[..len], b"error: unknown command, try 'help'");
+    }
+
+    #[test]
+    fn dates_must_exist() {
+        assert_eq!(
+            Command::parse("date 2024-02-29"),
+            Ok(Command::Date(2024, 2, 29))
+        );
+        assert_eq!(
+            Command::parse("date 2025-12-31"),
+            Ok(Command::Date(2025, 12, 31))
+        );
+        for line in &[
+            "date 2025-02-29",
+            "date 2025-02-31",
+            "date 2025-04-31",
+            "date 2025-1-0",
+        ] {
+            assert_eq!(
+                Command::parse(line),
+                Err(Error::InvalidArgument),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(2025, 1), 31);
+    }
+}
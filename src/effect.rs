@@ -0,0 +1,470 @@
+//! Interchangeable LED animations.
+//!
+//! An [`Effect`] renders a complete frame for a point in time. Effects are
+//! collected in a [`Registry`] and played back by a [`Player`], which can
+//! switch between them at runtime with a timed crossfade. Palette changes can
+//! be crossfaded as well.
+
+use crate::color::{self, Palette, RAINBOW_PALETTE};
+use smart_leds::RGB8;
+
+/// Parameters shared by all effects.
+#[derive(Clone, Copy, Debug)]
+pub struct Params {
+    /// animation speed, 256 is the speed the effect was designed for
+    pub speed: u16,
+    /// global brightness, applied by the [`Player`] after rendering
+    pub brightness: u8,
+    pub palette: Palette,
+    /// rotates the palette, applied by the [`Player`] before rendering
+    pub palette_offset: u8,
+    /// turns the colors around the color wheel, applied by the [`Player`]
+    /// after rendering, see [`color::rotate_hue`]
+    pub hue_offset: u8,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            speed: 256,
+            brightness: 255,
+            palette: RAINBOW_PALETTE,
+            palette_offset: 0,
+            hue_offset: 0,
+        }
+    }
+}
+
+impl Params {
+    /// Scales a duration in ms, e.g. a step interval, by the inverse of `speed`.
+    pub fn scale_interval(&self, ms: u32) -> u32 {
+        ms * 256 / (self.speed as u32).max(1)
+    }
+
+    /// Scales a time in ms by `speed`, i.e. the effect-local animation time.
+    pub fn scale_time(&self, t: u32) -> u32 {
+        ((t as u64 * self.speed as u64) >> 8) as u32
+    }
+}
+
+/// A message to an effect, see [`Effect::control`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control<'a> {
+    /// the text shown by the effect
+    Text(&'a str),
+    /// restarts the random numbers used by the effect, see [`crate::rng`]
+    Reseed(u32),
+    /// user input, e.g. a button press
+    Trigger,
+    /// the wall clock time, in ms since midnight
+    Time(u32),
+}
+
+pub trait Effect: Send {
+    /// Renders the state `t` ms after the effect was started into `frame`.
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]);
+
+    /// Called when the effect is started (again).
+    fn reset(&mut self) {}
+
+    /// Passes a message to the effect, returns false if the effect ignores
+    /// it.
+    fn control(&mut self, _control: Control) -> bool {
+        false
+    }
+}
+
+/// A fixed set of named effects.
+pub struct Registry<'a, const N: usize> {
+    entries: [(&'static str, &'a mut dyn Effect); N],
+}
+
+impl<'a, const N: usize> Registry<'a, N> {
+    pub fn new(entries: [(&'static str, &'a mut dyn Effect); N]) -> Self {
+        Registry { entries }
+    }
+
+    pub fn len(&self) -> usize {
+        N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    pub fn name(&self, index: usize) -> &'static str {
+        self.entries[index].0
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|(n, _)| *n == name)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut dyn Effect {
+        &mut *self.entries[index].1
+    }
+
+    /// Gives every effect a new seed, in registry order.
+    pub fn reseed(&mut self, mut seed: impl FnMut() -> u32) {
+        for (_, effect) in self.entries.iter_mut() {
+            effect.control(Control::Reseed(seed()));
+        }
+    }
+
+    /// Mutable access to two different effects at once.
+    fn pair_mut(&mut self, a: usize, b: usize) -> (&mut dyn Effect, &mut dyn Effect) {
+        assert!(a != b);
+        if a < b {
+            let (lo, hi) = self.entries.split_at_mut(b);
+            (&mut *lo[a].1, &mut *hi[0].1)
+        } else {
+            let (lo, hi) = self.entries.split_at_mut(a);
+            (&mut *hi[0].1, &mut *lo[b].1)
+        }
+    }
+}
+
+struct Fade {
+    to: usize,
+    start: u32,
+    duration: u32,
+}
+
+/// Crossfade from `from` to the palette in the [`Params`].
+struct PaletteFade {
+    from: Palette,
+    start: u32,
+    duration: u32,
+}
+
+/// Plays the effects of a [`Registry`], one at a time.
+pub struct Player<'a, const N: usize> {
+    registry: Registry<'a, N>,
+    current: usize,
+    started: u32,
+    fade: Option<Fade>,
+    palette_fade: Option<PaletteFade>,
+    scratch: &'a mut [RGB8],
+    pub params: Params,
+}
+
+impl<'a, const N: usize> Player<'a, N> {
+    /// Evaluated by [`Player::new`], so a player without effects doesn't
+    /// build.
+    const HAS_EFFECTS: () = assert!(N > 0, "a player needs at least one effect");
+
+    /// `scratch` holds the second frame during crossfades and must be at
+    /// least as long as the frames passed to [`Player::render`].
+    ///
+    /// The registry needs at least one effect, the first one starts playing.
+    /// An empty one is a build error.
+    pub fn new(mut registry: Registry<'a, N>, scratch: &'a mut [RGB8]) -> Self {
+        let () = Self::HAS_EFFECTS;
+        registry.get_mut(0).reset();
+        Player {
+            registry,
+            current: 0,
+            started: 0,
+            fade: None,
+            palette_fade: None,
+            scratch,
+            params: Params::default(),
+        }
+    }
+
+    pub fn registry(&self) -> &Registry<'a, N> {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut Registry<'a, N> {
+        &mut self.registry
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn current_name(&self) -> &'static str {
+        self.registry.name(self.current)
+    }
+
+    /// Time at which the current effect was started.
+    pub fn started(&self) -> u32 {
+        self.started
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// Switches to effect `index`, crossfading over `fade_ms`.
+    ///
+    /// A switch requested during a crossfade replaces the pending target.
+    pub fn switch_to(&mut self, index: usize, now: u32, fade_ms: u32) {
+        if index >= N {
+            return;
+        }
+        if index == self.current {
+            self.fade = None;
+            return;
+        }
+        self.registry.get_mut(index).reset();
+        if fade_ms == 0 {
+            self.current = index;
+            self.started = now;
+            self.fade = None;
+        } else {
+            self.fade = Some(Fade {
+                to: index,
+                start: now,
+                duration: fade_ms,
+            });
+        }
+    }
+
+    /// Switches to the effect called `name`, returns false if there is none.
+    pub fn switch_by_name(&mut self, name: &str, now: u32, fade_ms: u32) -> bool {
+        match self.registry.find(name) {
+            Some(index) => {
+                self.switch_to(index, now, fade_ms);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes the palette, crossfading over `fade_ms`.
+    ///
+    /// Setting `params.palette` directly changes it at once.
+    pub fn fade_palette(&mut self, palette: &Palette, now: u32, fade_ms: u32) {
+        // a fade in progress continues from where it is
+        let from = self.palette_at(now);
+        self.params.palette = *palette;
+        self.palette_fade = Some(PaletteFade {
+            from,
+            start: now,
+            duration: fade_ms,
+        });
+    }
+
+    /// The palette at `now`, blended during a palette crossfade.
+    fn palette_at(&self, now: u32) -> Palette {
+        match &self.palette_fade {
+            Some(fade) if now.wrapping_sub(fade.start) < fade.duration => {
+                let elapsed = now.wrapping_sub(fade.start);
+                let amount = (elapsed as u64 * 255 / fade.duration as u64) as u8;
+                fade.from.blend(&self.params.palette, amount)
+            }
+            _ => self.params.palette,
+        }
+    }
+
+    /// Starts the current effect again, e.g. after reseeding it.
+    pub fn restart(&mut self, now: u32) {
+        let index = self.fade.take().map_or(self.current, |f| f.to);
+        self.registry.get_mut(index).reset();
+        self.current = index;
+        self.started = now;
+    }
+
+    /// Passes a message to the current effect, or the pending one during a
+    /// crossfade.
+    pub fn control(&mut self, control: Control) -> bool {
+        let index = self.fade.as_ref().map_or(self.current, |f| f.to);
+        self.registry.get_mut(index).control(control)
+    }
+
+    /// Switches to the effect after the current one (or the pending one).
+    pub fn next(&mut self, now: u32, fade_ms: u32) {
+        let from = self.fade.as_ref().map_or(self.current, |f| f.to);
+        self.switch_to((from + 1) % N, now, fade_ms);
+    }
+
+    pub fn render(&mut self, now: u32, frame: &mut [RGB8]) {
+        let mut params = self.params;
+        params.palette = self.palette_at(now).rotated(self.params.palette_offset);
+        if matches!(&self.palette_fade, Some(f) if now.wrapping_sub(f.start) >= f.duration) {
+            self.palette_fade = None;
+        }
+
+        match self.fade.take() {
+            Some(fade) if now.wrapping_sub(fade.start) < fade.duration => {
+                let elapsed = now.wrapping_sub(fade.start);
+                let amount = (elapsed as u64 * 255 / fade.duration as u64) as u8;
+                let scratch = &mut self.scratch[..frame.len()];
+
+                let (from, to) = self.registry.pair_mut(self.current, fade.to);
+                from.render(now.wrapping_sub(self.started), &params, frame);
+                to.render(elapsed, &params, scratch);
+                for (c, n) in frame.iter_mut().zip(scratch.iter()) {
+                    *c = color::blend(*c, *n, amount);
+                }
+                self.fade = Some(fade);
+            }
+            Some(fade) => {
+                // crossfade is over
+                self.current = fade.to;
+                self.started = fade.start;
+                self.registry.get_mut(self.current).render(
+                    now.wrapping_sub(self.started),
+                    &params,
+                    frame,
+                );
+            }
+            None => {
+                self.registry.get_mut(self.current).render(
+                    now.wrapping_sub(self.started),
+                    &params,
+                    frame,
+                );
+            }
+        }
+
+        if params.hue_offset != 0 {
+            for c in frame.iter_mut() {
+                *c = color::rotate_hue(*c, params.hue_offset);
+            }
+        }
+        if params.brightness != 255 {
+            for c in frame.iter_mut() {
+                *c = color::scale(*c, params.brightness);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{HEAT_PALETTE, OCEAN_PALETTE};
+
+    /// Shows its `id` in red, its resets in green and the time in tenths of
+    /// a second in blue.
+    struct Stub {
+        id: u8,
+        resets: u8,
+    }
+
+    impl Stub {
+        fn new(id: u8) -> Self {
+            Stub { id, resets: 0 }
+        }
+    }
+
+    impl Effect for Stub {
+        fn render(&mut self, t: u32, _params: &Params, frame: &mut [RGB8]) {
+            frame.fill(RGB8 {
+                r: self.id,
+                g: self.resets,
+                b: (t / 100) as u8,
+            });
+        }
+
+        fn reset(&mut self) {
+            self.resets += 1;
+        }
+    }
+
+    fn fading_to<const N: usize>(player: &Player<'_, N>) -> Option<(usize, u32)> {
+        player.fade.as_ref().map(|f| (f.to, f.start))
+    }
+
+    #[test]
+    fn crossfade_ends_in_the_new_effect() {
+        let (mut a, mut b) = (Stub::new(0), Stub::new(100));
+        let registry = Registry::new([("a", &mut a as &mut dyn Effect), ("b", &mut b)]);
+        let mut scratch = [RGB8::default(); 1];
+        let mut player = Player::new(registry, &mut scratch);
+        let mut frame = [RGB8::default(); 1];
+
+        player.switch_to(1, 100, 1_000);
+        player.render(600, &mut frame);
+        assert_eq!(player.current(), 0);
+        assert!(player.is_fading());
+        // half way between a at 0.6 s and b at 0.5 s
+        assert_eq!(frame[0].r, 49);
+        assert_eq!(frame[0].b, 5);
+
+        player.render(1_100, &mut frame);
+        assert_eq!(player.current(), 1);
+        // b keeps its time from the start of the crossfade
+        assert_eq!(player.started(), 100);
+        assert!(!player.is_fading());
+        assert_eq!(
+            frame[0],
+            RGB8 {
+                r: 100,
+                g: 1,
+                b: 10
+            }
+        );
+    }
+
+    #[test]
+    fn switch_during_a_crossfade_replaces_the_target() {
+        let (mut a, mut b, mut c) = (Stub::new(0), Stub::new(100), Stub::new(200));
+        let registry = Registry::new([
+            ("a", &mut a as &mut dyn Effect),
+            ("b", &mut b),
+            ("c", &mut c),
+        ]);
+        let mut scratch = [RGB8::default(); 1];
+        let mut player = Player::new(registry, &mut scratch);
+        let mut frame = [RGB8::default(); 1];
+
+        player.switch_to(1, 0, 1_000);
+        player.switch_to(2, 500, 1_000);
+        assert_eq!(fading_to(&player), Some((2, 500)));
+        // the next one after the pending one is the current one again,
+        // which cancels the crossfade
+        player.next(600, 1_000);
+        assert_eq!(fading_to(&player), None);
+        player.render(700, &mut frame);
+        assert_eq!(frame[0].r, 0);
+
+        player.switch_to(2, 800, 1_000);
+        player.render(1_800, &mut frame);
+        assert_eq!(player.current_name(), "c");
+        assert_eq!(frame[0].r, 200);
+        // reset on both switches to it
+        assert_eq!(frame[0].g, 2);
+    }
+
+    #[test]
+    fn restart_during_a_crossfade_starts_the_target() {
+        let (mut a, mut b) = (Stub::new(0), Stub::new(100));
+        let registry = Registry::new([("a", &mut a as &mut dyn Effect), ("b", &mut b)]);
+        let mut scratch = [RGB8::default(); 1];
+        let mut player = Player::new(registry, &mut scratch);
+        let mut frame = [RGB8::default(); 1];
+
+        player.switch_to(1, 0, 1_000);
+        player.restart(300);
+        assert!(!player.is_fading());
+        assert_eq!(player.current(), 1);
+        assert_eq!(player.started(), 300);
+        player.render(500, &mut frame);
+        assert_eq!(frame[0], RGB8 { r: 100, g: 2, b: 2 });
+    }
+
+    #[test]
+    fn palette_fade_continues_from_where_it_is() {
+        let mut a = Stub::new(0);
+        let registry = Registry::new([("a", &mut a as &mut dyn Effect)]);
+        let mut scratch = [RGB8::default(); 1];
+        let mut player = Player::new(registry, &mut scratch);
+        let mut frame = [RGB8::default(); 1];
+        let rainbow = player.params.palette;
+
+        player.fade_palette(&HEAT_PALETTE, 0, 1_000);
+        let halfway = rainbow.blend(&HEAT_PALETTE, 127);
+        assert_eq!(player.palette_at(500), halfway);
+
+        player.fade_palette(&OCEAN_PALETTE, 500, 1_000);
+        assert_eq!(player.palette_at(500), halfway);
+        assert_eq!(player.palette_at(1_000), halfway.blend(&OCEAN_PALETTE, 127));
+        player.render(1_500, &mut frame);
+        assert!(player.palette_fade.is_none());
+        assert_eq!(player.palette_at(1_500), OCEAN_PALETTE);
+    }
+}
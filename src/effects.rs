@@ -0,0 +1,574 @@
+//! Effect implementations.
+
+use crate::{
+    canvas::{HexCanvas, Sampling},
+    color::{self, wheel, HEAT_PALETTE},
+    effect::{Control, Effect, Params},
+    hex::{self, Layout},
+    math::{cos8, isqrt, sin8},
+    rng::XorShift32,
+};
+use embedded_graphics::{
+    fonts::{Font6x8, Text},
+    pixelcolor::Rgb888,
+    prelude::*,
+    style::TextStyle,
+};
+use smart_leds::RGB8;
+
+/// The classic color wheel, running along the strip.
+pub struct RainbowEffect {
+    /// hue difference between neighboring LEDs
+    spread: u8,
+}
+
+impl RainbowEffect {
+    pub const fn new(spread: u8) -> Self {
+        RainbowEffect { spread }
+    }
+}
+
+impl Effect for RainbowEffect {
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        // one turn of the wheel every 2.56 s
+        let pos = (params.scale_time(t) / 10) as u8;
+        for (i, c) in frame.iter_mut().enumerate() {
+            *c = wheel(pos.wrapping_add((i as u8).wrapping_mul(self.spread)));
+        }
+    }
+}
+
+/// Longest text shown by the [`Marquee`], in bytes.
+pub const MARQUEE_TEXT_LEN: usize = 64;
+
+/// How the text of the [`Marquee`] is colored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextColor {
+    Fixed(RGB8),
+    /// the color wheel, running along the text
+    Rainbow,
+    /// the palette from the effect parameters, running along the text
+    Palette,
+}
+
+/// Text scrolling from right to left through a `W` × `H` pixel canvas.
+pub struct Marquee<const W: usize, const H: usize> {
+    canvas: HexCanvas<W, H>,
+    text: [u8; MARQUEE_TEXT_LEN],
+    text_len: usize,
+    /// time at which the text entered at the right edge
+    text_start: Option<u32>,
+    /// scroll speed at normal speed, in pixels per second
+    pub pixels_per_s: u32,
+    pub color: TextColor,
+}
+
+impl<const W: usize, const H: usize> Marquee<W, H> {
+    pub const fn new(layout: &'static Layout, pixels_per_s: u32, color: TextColor) -> Self {
+        Marquee {
+            canvas: HexCanvas::new(layout, Sampling::Average),
+            text: [0; MARQUEE_TEXT_LEN],
+            text_len: 0,
+            text_start: None,
+            pixels_per_s,
+            color,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        // only ever set from a str, cut at a char boundary
+        core::str::from_utf8(&self.text[..self.text_len]).unwrap_or("")
+    }
+
+    fn text_width(&self) -> u32 {
+        self.text().chars().count() as u32 * 6
+    }
+}
+
+impl<const W: usize, const H: usize> Effect for Marquee<W, H> {
+    fn reset(&mut self) {
+        self.text_start = None;
+    }
+
+    /// A text replaces the current one, longer texts are cut off. The new
+    /// text starts at the right edge.
+    fn control(&mut self, control: Control) -> bool {
+        let text = match control {
+            Control::Text(text) => text,
+            _ => return false,
+        };
+        let mut len = text.len().min(MARQUEE_TEXT_LEN);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.text[..len].copy_from_slice(&text.as_bytes()[..len]);
+        self.text_len = len;
+        self.text_start = None;
+        true
+    }
+
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        let start = match self.text_start {
+            Some(start) if start <= t => start,
+            _ => {
+                self.text_start = Some(t);
+                t
+            }
+        };
+        let scrolled = params.scale_time(t - start) as u64 * self.pixels_per_s as u64 / 1000;
+        let offset = (scrolled % (W as u64 + self.text_width() as u64)) as i32;
+
+        // draw in white first, the pixels are colored afterwards
+        let canvas = &mut self.canvas;
+        canvas.fill(color::BLACK);
+        let text = core::str::from_utf8(&self.text[..self.text_len]).unwrap_or("");
+        Text::new(text, Point::new(W as i32 - offset, (H as i32 - 8) / 2))
+            .into_styled(TextStyle::new(Font6x8, Rgb888::WHITE))
+            .draw(canvas)
+            .ok();
+
+        let phase = params.scale_time(t) / 10;
+        for y in 0..H {
+            for x in 0..W {
+                let brightness = canvas.pixel(x, y).r;
+                if brightness == 0 {
+                    continue;
+                }
+                let c = match self.color {
+                    TextColor::Fixed(c) => c,
+                    TextColor::Rainbow => {
+                        wheel((phase as u8).wrapping_add((x as u8).wrapping_mul(8)))
+                    }
+                    TextColor::Palette => params
+                        .palette
+                        .lookup((phase as u8).wrapping_add((x * 256 / W) as u8)),
+                };
+                canvas.set_pixel(x, y, color::scale(c, brightness));
+            }
+        }
+        canvas.render(frame);
+    }
+}
+
+/// Time between two simulation steps of the [`Fire`] at normal speed, in ms.
+const FIRE_STEP_MS: u32 = 25;
+const MAX_FIRE_STEPS_PER_FRAME: u32 = 4;
+
+/// Fire2012 by Mark Kriegsman, with the heat rising through the hex rows.
+///
+/// Sparks are lit in the two bottom rows and every cell above takes its heat
+/// from the three cells below it, cooling down on the way up.
+pub struct Fire<const N: usize> {
+    layout: &'static Layout,
+    heat: [u8; N],
+    rng: XorShift32,
+    step_time: u32,
+    /// how fast the flames cool down, 20 to 100 gives tall to short flames
+    pub cooling: u8,
+    /// chance of a new spark per step, in 1/256
+    pub sparking: u8,
+}
+
+impl<const N: usize> Fire<N> {
+    pub const fn new(layout: &'static Layout, seed: u32) -> Self {
+        Fire {
+            layout,
+            heat: [0; N],
+            rng: XorShift32::new(seed),
+            step_time: 0,
+            cooling: 55,
+            sparking: 120,
+        }
+    }
+
+    fn heat_at(&self, x: i16, y: i16) -> u16 {
+        match self.layout.index(x, y) {
+            Some(i) if i < N => self.heat[i] as u16,
+            _ => 0,
+        }
+    }
+
+    pub fn step(&mut self) {
+        let height = self.layout.height();
+        // Fire2012 divides by the strip length, the rows of the panel are
+        // about three times as close
+        let max_cooling = self.cooling as u32 * 10 / (3 * height.max(1) as u32) + 2;
+        for h in self.heat.iter_mut() {
+            *h = h.saturating_sub(self.rng.below(max_cooling) as u8);
+        }
+
+        // rows are stored top to bottom, so the rows below are not updated yet
+        for i in 0..N {
+            let (x, y) = match self.layout.position(i) {
+                Some(p) => p,
+                None => break,
+            };
+            if y >= height - 2 {
+                break;
+            }
+            let below = self.heat_at(x - 1, y + 1)
+                + self.heat_at(x + 1, y + 1)
+                + 2 * self.heat_at(x, y + 2);
+            self.heat[i] = (below / 4) as u8;
+        }
+
+        if self.rng.next_u8() < self.sparking {
+            let bottom = self.layout.rows[self.layout.rows.len().saturating_sub(2)..]
+                .iter()
+                .map(|r| r.len as usize)
+                .sum::<usize>();
+            let len = self.layout.len().min(N);
+            if bottom > 0 && len >= bottom {
+                let i = len - 1 - self.rng.below(bottom as u32) as usize;
+                let spark = 160 + self.rng.below(96) as u8;
+                self.heat[i] = self.heat[i].saturating_add(spark);
+            }
+        }
+    }
+}
+
+impl<const N: usize> Effect for Fire<N> {
+    fn reset(&mut self) {
+        self.heat = [0; N];
+        self.step_time = 0;
+    }
+
+    fn control(&mut self, control: Control) -> bool {
+        match control {
+            Control::Reseed(seed) => self.rng = XorShift32::new(seed),
+            _ => return false,
+        }
+        true
+    }
+
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        if t < self.step_time {
+            // restarted
+            self.step_time = 0;
+        }
+        let interval = params.scale_interval(FIRE_STEP_MS).max(1);
+        let behind = (t - self.step_time) / interval;
+        for _ in 0..behind.min(MAX_FIRE_STEPS_PER_FRAME) {
+            self.step();
+        }
+        self.step_time += behind * interval;
+
+        for (c, heat) in frame.iter_mut().zip(self.heat.iter()) {
+            // the heat palette wraps around to black after its last entry
+            *c = HEAT_PALETTE.lookup((*heat as u16 * 240 / 255) as u8);
+        }
+    }
+}
+
+/// Plasma from summed sine waves over the cell positions, colored through
+/// the palette.
+pub struct Plasma {
+    layout: &'static Layout,
+}
+
+impl Plasma {
+    pub const fn new(layout: &'static Layout) -> Self {
+        Plasma { layout }
+    }
+}
+
+impl Effect for Plasma {
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        let t = params.scale_time(t);
+        // a center wandering around the panel
+        let (w, h) = hex::cartesian(self.layout.width(), self.layout.height());
+        let cx = w / 2 + (sin8((t / 37) as u8) as i32 - 128) * w / 512;
+        let cy = h / 2 + (cos8((t / 43) as u8) as i32 - 128) * h / 512;
+
+        for (i, c) in frame.iter_mut().enumerate() {
+            let (x, y) = match self.layout.position(i) {
+                Some(p) => hex::cartesian(p.0, p.1),
+                None => break,
+            };
+            let dist = isqrt(((x - cx) * (x - cx) + (y - cy) * (y - cy)) as u32);
+            let v = sin8((x as u32 + t / 8) as u8) as u32
+                + sin8((y as u32 + t / 11) as u8) as u32
+                + sin8(((x + y) as u32 / 2 + t / 7) as u8) as u32
+                + sin8((dist + t / 5) as u8) as u32;
+            *c = params.palette.lookup((v / 4 + t / 20) as u8);
+        }
+    }
+}
+
+const MAX_RIPPLES: usize = 4;
+/// Average time between two new ripples at normal speed, in ms.
+const RIPPLE_INTERVAL_MS: u32 = 800;
+const RIPPLE_LIFETIME_MS: u32 = 3_000;
+const RIPPLE_CELLS_PER_S: u32 = 6;
+/// Width of a ring, in 1/16 cell spacing.
+const RIPPLE_WIDTH: u32 = 24;
+
+#[derive(Clone, Copy)]
+struct Drop {
+    /// center, see [`hex::cartesian`]
+    x: i32,
+    y: i32,
+    start: u32,
+    color: u8,
+}
+
+/// Rings expanding from random cells, like drops falling into water.
+pub struct Ripples {
+    layout: &'static Layout,
+    drops: [Option<Drop>; MAX_RIPPLES],
+    rng: XorShift32,
+    next_drop: u32,
+}
+
+impl Ripples {
+    pub const fn new(layout: &'static Layout, seed: u32) -> Self {
+        Ripples {
+            layout,
+            drops: [None; MAX_RIPPLES],
+            rng: XorShift32::new(seed),
+            next_drop: 0,
+        }
+    }
+
+    fn add_drop(&mut self, t: u32) {
+        let index = self.rng.below(self.layout.len() as u32) as usize;
+        let (x, y) = match self.layout.position(index) {
+            Some(p) => hex::cartesian(p.0, p.1),
+            None => return,
+        };
+        let drop = Drop {
+            x,
+            y,
+            start: t,
+            color: self.rng.next_u8(),
+        };
+        // replace the oldest drop if all are in use
+        let slot = self
+            .drops
+            .iter_mut()
+            .min_by_key(|d| d.map_or(0, |d| d.start as u64 + 1));
+        if let Some(slot) = slot {
+            *slot = Some(drop);
+        }
+    }
+}
+
+impl Effect for Ripples {
+    fn reset(&mut self) {
+        self.drops = [None; MAX_RIPPLES];
+        self.next_drop = 0;
+    }
+
+    fn control(&mut self, control: Control) -> bool {
+        match control {
+            Control::Reseed(seed) => self.rng = XorShift32::new(seed),
+            _ => return false,
+        }
+        true
+    }
+
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        let lifetime = params.scale_interval(RIPPLE_LIFETIME_MS).max(1);
+        for d in self.drops.iter_mut() {
+            if matches!(d, Some(drop) if drop.start > t || t - drop.start >= lifetime) {
+                *d = None;
+            }
+        }
+        if t >= self.next_drop {
+            self.add_drop(t);
+            let interval = params.scale_interval(RIPPLE_INTERVAL_MS).max(2);
+            self.next_drop = t + interval / 2 + self.rng.below(interval);
+        }
+
+        for (i, c) in frame.iter_mut().enumerate() {
+            let (x, y) = match self.layout.position(i) {
+                Some(p) => hex::cartesian(p.0, p.1),
+                None => break,
+            };
+            *c = color::BLACK;
+            for drop in self.drops.iter().flatten() {
+                let age = t - drop.start;
+                let radius = params.scale_time(age) * RIPPLE_CELLS_PER_S * 16 / 1000;
+                let dist =
+                    isqrt(((x - drop.x) * (x - drop.x) + (y - drop.y) * (y - drop.y)) as u32);
+                let off = dist.abs_diff(radius);
+                if off >= RIPPLE_WIDTH {
+                    continue;
+                }
+                let ring = (RIPPLE_WIDTH - off) * 255 / RIPPLE_WIDTH;
+                let fade = 255 - age * 255 / lifetime;
+                let v = color::scale(params.palette.lookup(drop.color), (ring * fade / 255) as u8);
+                c.r = c.r.saturating_add(v.r);
+                c.g = c.g.saturating_add(v.g);
+                c.b = c.b.saturating_add(v.b);
+            }
+        }
+    }
+}
+
+/// How the [`ClockFace`] shows the time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockStyle {
+    /// hour, minute and second hands in red, green and blue
+    Hands,
+    /// `HH:MM` in the palette colors, the colon blinks with the seconds
+    Digits,
+}
+
+/// Hour marks and hands are drawn in steps of this length, in 1/16 cell
+/// spacing, so no cell along a hand is skipped.
+const HAND_STEP: i32 = 4;
+
+/// The wall clock time, as last set with [`Control::Time`].
+///
+/// The time does not advance on its own, the effect time pauses and runs at
+/// the animation speed. Send the time before each frame, e.g. from a
+/// [`WallClock`](crate::clock::WallClock).
+pub struct ClockFace<const W: usize, const H: usize> {
+    layout: &'static Layout,
+    canvas: HexCanvas<W, H>,
+    /// ms since midnight
+    time_ms: u32,
+    pub style: ClockStyle,
+}
+
+impl<const W: usize, const H: usize> ClockFace<W, H> {
+    pub const fn new(layout: &'static Layout, style: ClockStyle) -> Self {
+        ClockFace {
+            layout,
+            canvas: HexCanvas::new(layout, Sampling::Average),
+            time_ms: 0,
+            style,
+        }
+    }
+
+    /// Adds `c` to the cell at the cartesian position `(x, y)`.
+    fn plot(&self, frame: &mut [RGB8], x: i32, y: i32, c: RGB8) {
+        let cell = self.layout.cell_at(x, y);
+        if let Some(p) = cell
+            .and_then(|(x, y)| self.layout.index(x, y))
+            .and_then(|i| frame.get_mut(i))
+        {
+            p.r = p.r.saturating_add(c.r);
+            p.g = p.g.saturating_add(c.g);
+            p.b = p.b.saturating_add(c.b);
+        }
+    }
+
+    /// Draws a hand from the center, `angle` in 1/256 turns clockwise from 12.
+    fn hand(&self, frame: &mut [RGB8], center: (i32, i32), angle: u8, len: i32, c: RGB8) {
+        let dx = sin8(angle) as i32 - 128;
+        let dy = cos8(angle) as i32 - 128;
+        // the center cell is shared by all hands
+        for r in (HAND_STEP..=len).step_by(HAND_STEP as usize) {
+            self.plot(frame, center.0 + dx * r / 127, center.1 - dy * r / 127, c);
+        }
+    }
+
+    fn render_hands(&self, ms: u32, frame: &mut [RGB8]) {
+        let (w, h) = hex::cartesian(self.layout.width() - 1, self.layout.height() - 1);
+        let center = (w / 2, h / 2);
+        let radius = center.0.min(center.1);
+
+        frame.fill(color::BLACK);
+        let mark = RGB8 {
+            r: 24,
+            g: 24,
+            b: 24,
+        };
+        for hour in 0..12 {
+            let angle = (hour * 256 / 12) as u8;
+            let dx = sin8(angle) as i32 - 128;
+            let dy = cos8(angle) as i32 - 128;
+            self.plot(
+                frame,
+                center.0 + dx * radius / 127,
+                center.1 - dy * radius / 127,
+                mark,
+            );
+        }
+
+        let angle = |period: u32| ((ms % period) as u64 * 256 / period as u64) as u8;
+        self.hand(frame, center, angle(43_200_000), radius / 2, color::RED);
+        self.hand(
+            frame,
+            center,
+            angle(3_600_000),
+            radius * 4 / 5,
+            color::GREEN,
+        );
+        self.hand(frame, center, angle(60_000), radius, color::BLUE);
+    }
+
+    fn render_digits(&mut self, ms: u32, t: u32, params: &Params, frame: &mut [RGB8]) {
+        let minutes = ms / 60_000;
+        let colon = if ms % 1000 < 500 { ':' } else { ' ' };
+        let mut text = [0u8; 5];
+        text[0] = b'0' + (minutes / 600 % 10) as u8;
+        text[1] = b'0' + (minutes / 60 % 10) as u8;
+        text[2] = colon as u8;
+        text[3] = b'0' + (minutes % 60 / 10) as u8;
+        text[4] = b'0' + (minutes % 10) as u8;
+        let text = core::str::from_utf8(&text).unwrap_or("");
+
+        let canvas = &mut self.canvas;
+        canvas.fill(color::BLACK);
+        Text::new(text, Point::new((W as i32 - 30) / 2, (H as i32 - 8) / 2))
+            .into_styled(TextStyle::new(Font6x8, Rgb888::WHITE))
+            .draw(canvas)
+            .ok();
+
+        let phase = params.scale_time(t) / 20;
+        for y in 0..H {
+            for x in 0..W {
+                let brightness = canvas.pixel(x, y).r;
+                if brightness != 0 {
+                    let c = params
+                        .palette
+                        .lookup((phase as u8).wrapping_add((x * 256 / W) as u8));
+                    canvas.set_pixel(x, y, color::scale(c, brightness));
+                }
+            }
+        }
+        canvas.render(frame);
+    }
+}
+
+impl<const W: usize, const H: usize> Effect for ClockFace<W, H> {
+    fn control(&mut self, control: Control) -> bool {
+        match control {
+            Control::Time(ms) => {
+                self.time_ms = ms % 86_400_000;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        let ms = self.time_ms;
+        match self.style {
+            ClockStyle::Hands => self.render_hands(ms, frame),
+            ClockStyle::Digits => self.render_digits(ms, t, params, frame),
+        }
+    }
+}
+
+/// A still image with a color per LED, e.g. from [`assets`](crate::assets).
+pub struct Picture {
+    image: &'static [RGB8],
+}
+
+impl Picture {
+    pub const fn new(image: &'static [RGB8]) -> Self {
+        Picture { image }
+    }
+}
+
+impl Effect for Picture {
+    fn render(&mut self, _t: u32, _params: &Params, frame: &mut [RGB8]) {
+        let len = frame.len().min(self.image.len());
+        frame[..len].copy_from_slice(&self.image[..len]);
+        frame[len..].fill(color::BLACK);
+    }
+}
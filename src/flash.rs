@@ -0,0 +1,143 @@
+//! Settings that survive power loss, in a page of the internal flash.
+//!
+//! The page must be kept free of code, see `memory.x`. It is erased and
+//! written as a whole, in double words:
+//!
+//! ```ignore
+//! let mut page = FlashPage::new(flash.keyr, flash.sr, flash.cr, SETTINGS_PAGE);
+//! scheduler.load(page.words());
+//! page.write(&words)?;
+//! ```
+//!
+//! Code can't be fetched from flash while a page is erased or written, which
+//! stalls the CPU (and all interrupts) for up to about 25 ms.
+
+use core::ptr::{read_volatile, write_volatile};
+use stm32l4xx_hal::{
+    flash::{CR, KEYR, SR},
+    stm32::FLASH,
+};
+
+pub const PAGE_SIZE: usize = 2048;
+const FLASH_START: usize = 0x0800_0000;
+
+// register offsets
+const FLASH_KEYR: usize = 0x08;
+const FLASH_SR: usize = 0x10;
+const FLASH_CR: usize = 0x14;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+const SR_EOP: u32 = 1 << 0;
+/// OPERR, PROGERR, WRPERR, PGAERR, SIZERR, PGSERR, MISERR and FASTERR
+const SR_ERRORS: u32 = 0b11_1111_1010;
+const SR_BSY: u32 = 1 << 16;
+const CR_PG: u32 = 1 << 0;
+const CR_PER: u32 = 1 << 1;
+const CR_PNB_SHIFT: u32 = 3;
+const CR_PNB_MASK: u32 = 0xff << CR_PNB_SHIFT;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// more data than fits into the page
+    TooLong,
+    /// the error flags of the status register
+    Flash(u32),
+}
+
+pub struct FlashPage {
+    _keyr: KEYR,
+    _sr: SR,
+    _cr: CR,
+    page: u8,
+}
+
+impl FlashPage {
+    /// Page `page` of the flash, counted from its start.
+    ///
+    /// The registers are only taken to prove that nobody else is writing
+    /// the flash.
+    pub fn new(keyr: KEYR, sr: SR, cr: CR, page: u8) -> Self {
+        FlashPage {
+            _keyr: keyr,
+            _sr: sr,
+            _cr: cr,
+            page,
+        }
+    }
+
+    fn address(&self) -> usize {
+        FLASH_START + self.page as usize * PAGE_SIZE
+    }
+
+    /// The contents of the page, all ones where it is erased.
+    pub fn words(&self) -> &'static [u64] {
+        unsafe { core::slice::from_raw_parts(self.address() as *const u64, PAGE_SIZE / 8) }
+    }
+
+    /// Erases the page and writes `words` to its start.
+    pub fn write(&mut self, words: &[u64]) -> Result<(), Error> {
+        if words.len() > PAGE_SIZE / 8 {
+            return Err(Error::TooLong);
+        }
+        let base = FLASH::ptr() as usize;
+        unsafe {
+            if read_volatile((base + FLASH_CR) as *const u32) & CR_LOCK != 0 {
+                write_volatile((base + FLASH_KEYR) as *mut u32, KEY1);
+                write_volatile((base + FLASH_KEYR) as *mut u32, KEY2);
+            }
+            let result = self.erase(base).and_then(|_| self.program(base, words));
+            modify(base + FLASH_CR, |r| r | CR_LOCK);
+            result
+        }
+    }
+
+    unsafe fn erase(&mut self, base: usize) -> Result<(), Error> {
+        wait(base)?;
+        modify(base + FLASH_CR, |r| {
+            r & !CR_PNB_MASK | CR_PER | (self.page as u32) << CR_PNB_SHIFT
+        });
+        modify(base + FLASH_CR, |r| r | CR_STRT);
+        let result = wait(base);
+        modify(base + FLASH_CR, |r| r & !CR_PER);
+        result
+    }
+
+    unsafe fn program(&mut self, base: usize, words: &[u64]) -> Result<(), Error> {
+        modify(base + FLASH_CR, |r| r | CR_PG);
+        let mut result = Ok(());
+        for (i, word) in words.iter().enumerate() {
+            // a double word is written as two words, low word first
+            let addr = (self.address() + i * 8) as *mut u32;
+            write_volatile(addr, *word as u32);
+            write_volatile(addr.add(1), (*word >> 32) as u32);
+            result = wait(base);
+            if result.is_err() {
+                break;
+            }
+        }
+        modify(base + FLASH_CR, |r| r & !CR_PG);
+        result
+    }
+}
+
+/// Waits for the current operation and clears its flags.
+unsafe fn wait(base: usize) -> Result<(), Error> {
+    let sr = (base + FLASH_SR) as *mut u32;
+    while read_volatile(sr) & SR_BSY != 0 {}
+    let flags = read_volatile(sr);
+    // the flags are cleared by writing ones
+    write_volatile(sr, flags & (SR_EOP | SR_ERRORS));
+    match flags & SR_ERRORS {
+        0 => Ok(()),
+        errors => Err(Error::Flash(errors)),
+    }
+}
+
+unsafe fn modify(addr: usize, f: impl FnOnce(u32) -> u32) {
+    let reg = addr as *mut u32;
+    write_volatile(reg, f(read_volatile(reg)));
+}
@@ -0,0 +1,292 @@
+//! Small games on the LED panel.
+//!
+//! A [`Game`] is advanced in fixed steps and controlled by [`Input`] events
+//! from the button (or an encoder). The [`Arcade`] holds the available games,
+//! runs the current one and keeps the high scores:
+//!
+//! ```ignore
+//! // periodic task
+//! arcade.update(now);
+//! // button task
+//! arcade.input(Input::Press);
+//! // LED task
+//! arcade.render(now, &mut frame);
+//! ```
+
+use crate::{color, rng::XorShift32};
+use smart_leds::RGB8;
+
+/// Duration of the game over screen, in ms. The game starts again after it.
+pub const GAME_OVER_MS: u32 = 3_000;
+
+/// Blink period of the game over screen, in ms.
+const GAME_OVER_BLINK_MS: u32 = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// the button
+    Press,
+    /// an encoder step to the left (counterclockwise)
+    Left,
+    /// an encoder step to the right (clockwise)
+    Right,
+}
+
+pub trait Game: Send {
+    /// Starts a new round.
+    fn reset(&mut self, rng: &mut XorShift32);
+
+    fn input(&mut self, input: Input);
+
+    /// Advances the game by one step, returns false when the game is over.
+    fn step(&mut self, rng: &mut XorShift32) -> bool;
+
+    /// Time until the next step, in ms. Games can speed up as they go.
+    fn step_ms(&self) -> u32;
+
+    fn score(&self) -> u32;
+
+    fn render(&self, frame: &mut [RGB8]);
+}
+
+/// Runs one of a fixed set of named games.
+pub struct Arcade<'a, const N: usize> {
+    games: [(&'static str, &'a mut dyn Game); N],
+    current: Option<usize>,
+    high_scores: [u32; N],
+    last_step: u32,
+    /// time at which the current game ended
+    over_since: Option<u32>,
+    rng: XorShift32,
+}
+
+impl<'a, const N: usize> Arcade<'a, N> {
+    pub fn new(games: [(&'static str, &'a mut dyn Game); N], seed: u32) -> Self {
+        Arcade {
+            games,
+            current: None,
+            high_scores: [0; N],
+            last_step: 0,
+            over_since: None,
+            rng: XorShift32::new(seed),
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u32) {
+        self.rng = XorShift32::new(seed);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn current_name(&self) -> Option<&'static str> {
+        self.current.map(|i| self.games[i].0)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.over_since.is_some()
+    }
+
+    pub fn score(&self) -> u32 {
+        self.current.map_or(0, |i| self.games[i].1.score())
+    }
+
+    pub fn high_score(&self) -> u32 {
+        self.current.map_or(0, |i| self.high_scores[i])
+    }
+
+    /// Starts game `index`, from the beginning.
+    pub fn start(&mut self, index: usize, now: u32) {
+        if index >= N {
+            return;
+        }
+        self.games[index].1.reset(&mut self.rng);
+        self.current = Some(index);
+        self.last_step = now;
+        self.over_since = None;
+    }
+
+    /// Starts the game called `name`, returns false if there is none.
+    pub fn start_by_name(&mut self, name: &str, now: u32) -> bool {
+        match self.games.iter().position(|(n, _)| *n == name) {
+            Some(index) => {
+                self.start(index, now);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.over_since = None;
+    }
+
+    /// Starts the next game, stops after the last one.
+    pub fn next(&mut self, now: u32) {
+        match self.current.map_or(0, |i| i + 1) {
+            i if i < N => self.start(i, now),
+            _ => self.stop(),
+        }
+    }
+
+    /// Ignored during the game over screen, so a late press doesn't start
+    /// the next round.
+    pub fn input(&mut self, input: Input) {
+        if let (Some(i), None) = (self.current, self.over_since) {
+            self.games[i].1.input(input);
+        }
+    }
+
+    /// Steps the current game if it is due, call this at least as often as
+    /// the fastest game steps.
+    pub fn update(&mut self, now: u32) {
+        let index = match self.current {
+            Some(i) => i,
+            None => return,
+        };
+        if let Some(since) = self.over_since {
+            if now.wrapping_sub(since) >= GAME_OVER_MS {
+                self.start(index, now);
+            }
+            return;
+        }
+
+        let game = &mut *self.games[index].1;
+        if now.wrapping_sub(self.last_step) < game.step_ms() {
+            return;
+        }
+        // a late update doesn't catch up, the game just runs slower
+        self.last_step = now;
+        if !game.step(&mut self.rng) {
+            self.high_scores[index] = self.high_scores[index].max(game.score());
+            self.over_since = Some(now);
+        }
+    }
+
+    /// Renders the current game, the last state blinks when it is over.
+    pub fn render(&self, now: u32, frame: &mut [RGB8]) {
+        let index = match self.current {
+            Some(i) => i,
+            None => {
+                frame.fill(color::BLACK);
+                return;
+            }
+        };
+        self.games[index].1.render(frame);
+        if let Some(since) = self.over_since {
+            if now.wrapping_sub(since) / (GAME_OVER_BLINK_MS / 2) % 2 == 1 {
+                for c in frame.iter_mut() {
+                    *c = color::scale(*c, 48);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scores a point per step, over after `rounds` steps.
+    struct Stub {
+        rounds: u32,
+        steps: u32,
+        inputs: u32,
+        resets: u32,
+    }
+
+    impl Stub {
+        fn new(rounds: u32) -> Self {
+            Stub {
+                rounds,
+                steps: 0,
+                inputs: 0,
+                resets: 0,
+            }
+        }
+    }
+
+    impl Game for Stub {
+        fn reset(&mut self, _rng: &mut XorShift32) {
+            self.steps = 0;
+            self.resets += 1;
+        }
+
+        fn input(&mut self, _input: Input) {
+            self.inputs += 1;
+        }
+
+        fn step(&mut self, _rng: &mut XorShift32) -> bool {
+            self.steps += 1;
+            self.steps < self.rounds
+        }
+
+        fn step_ms(&self) -> u32 {
+            100
+        }
+
+        fn score(&self) -> u32 {
+            self.steps
+        }
+
+        fn render(&self, frame: &mut [RGB8]) {
+            frame.fill(color::RED);
+        }
+    }
+
+    #[test]
+    fn game_over_keeps_the_high_score_and_restarts() {
+        let mut stub = Stub::new(3);
+        let mut arcade = Arcade::new([("stub", &mut stub as &mut dyn Game)], 1);
+        arcade.start(0, 0);
+        // not due yet
+        arcade.update(99);
+        assert_eq!(arcade.score(), 0);
+        for now in [100, 200, 300].iter() {
+            assert!(!arcade.is_over());
+            arcade.update(*now);
+        }
+        assert!(arcade.is_over());
+        assert_eq!(arcade.score(), 3);
+        assert_eq!(arcade.high_score(), 3);
+
+        arcade.update(300 + GAME_OVER_MS - 1);
+        assert!(arcade.is_over());
+        arcade.update(300 + GAME_OVER_MS);
+        assert!(!arcade.is_over());
+        assert_eq!(arcade.score(), 0);
+        assert_eq!(arcade.high_score(), 3);
+        assert_eq!(stub.resets, 2);
+    }
+
+    #[test]
+    fn game_over_screen_ignores_input() {
+        let mut stub = Stub::new(1);
+        let mut arcade = Arcade::new([("stub", &mut stub as &mut dyn Game)], 1);
+        arcade.start(0, 0);
+        arcade.input(Input::Left);
+        arcade.update(100);
+        assert!(arcade.is_over());
+        arcade.input(Input::Press);
+        arcade.input(Input::Right);
+        assert_eq!(stub.inputs, 1);
+    }
+
+    #[test]
+    fn game_over_screen_blinks() {
+        let mut stub = Stub::new(1);
+        let mut arcade = Arcade::new([("stub", &mut stub as &mut dyn Game)], 1);
+        let mut frame = [color::BLACK; 1];
+        arcade.render(0, &mut frame);
+        assert_eq!(frame[0], color::BLACK);
+
+        arcade.start(0, 0);
+        arcade.update(100);
+        arcade.render(100, &mut frame);
+        assert_eq!(frame[0], color::RED);
+        arcade.render(100 + GAME_OVER_BLINK_MS / 2, &mut frame);
+        assert_eq!(frame[0], color::scale(color::RED, 48));
+    }
+}
@@ -0,0 +1,443 @@
+//! Game implementations.
+
+use crate::{
+    color,
+    game::{Game, Input},
+    hex::{cartesian, Layout, NEIGHBOR_OFFSETS},
+    rng::XorShift32,
+};
+use smart_leds::RGB8;
+
+const SNAKE_START_LEN: usize = 3;
+const SNAKE_STEP_MS: u32 = 300;
+const SNAKE_MIN_STEP_MS: u32 = 100;
+/// Step time saved per eaten food, in ms.
+const SNAKE_SPEEDUP_MS: u32 = 10;
+
+/// Snake on the hex cells of a layout with `N` cells.
+///
+/// The snake moves in one of the six hex directions. The button turns it
+/// clockwise, an encoder turns it either way.
+pub struct Snake<const N: usize> {
+    layout: &'static Layout,
+    /// LED indices of the body, a ring buffer with the head at `tail + len - 1`
+    body: [u16; N],
+    tail: usize,
+    len: usize,
+    occupied: [bool; N],
+    /// index into [`NEIGHBOR_OFFSETS`]
+    dir: usize,
+    /// turn to apply on the next step, at most one per step
+    turn: usize,
+    food: Option<usize>,
+    score: u32,
+}
+
+impl<const N: usize> Snake<N> {
+    pub const fn new(layout: &'static Layout) -> Self {
+        Snake {
+            layout,
+            body: [0; N],
+            tail: 0,
+            len: 0,
+            occupied: [false; N],
+            dir: 0,
+            turn: 0,
+            food: None,
+            score: 0,
+        }
+    }
+
+    fn cells(&self) -> usize {
+        self.layout.len().min(N)
+    }
+
+    fn head(&self) -> usize {
+        self.body[(self.tail + self.len - 1) % N] as usize
+    }
+
+    fn push_head(&mut self, index: usize) {
+        self.body[(self.tail + self.len) % N] = index as u16;
+        self.len += 1;
+        self.occupied[index] = true;
+    }
+
+    fn pop_tail(&mut self) {
+        self.occupied[self.body[self.tail] as usize] = false;
+        self.tail = (self.tail + 1) % N;
+        self.len -= 1;
+    }
+
+    /// Puts the food on a random free cell, none if the snake fills the layout.
+    fn place_food(&mut self, rng: &mut XorShift32) {
+        let free = self.cells() - self.len;
+        self.food = if free == 0 {
+            None
+        } else {
+            let nth = rng.below(free as u32) as usize;
+            (0..self.cells()).filter(|i| !self.occupied[*i]).nth(nth)
+        };
+    }
+}
+
+impl<const N: usize> Game for Snake<N> {
+    /// Starts in the center, heading right.
+    fn reset(&mut self, rng: &mut XorShift32) {
+        self.occupied = [false; N];
+        self.tail = 0;
+        self.len = 0;
+        self.dir = 0;
+        self.turn = 0;
+        self.score = 0;
+
+        let center = self.cells() / 2;
+        if let Some((x, y)) = self.layout.position(center) {
+            for i in (0..SNAKE_START_LEN as i16).rev() {
+                if let Some(index) = self.layout.index(x - 2 * i, y).filter(|i| *i < N) {
+                    self.push_head(index);
+                }
+            }
+        }
+        self.place_food(rng);
+    }
+
+    fn input(&mut self, input: Input) {
+        self.turn = match input {
+            Input::Press | Input::Right => 1,
+            Input::Left => 5,
+        };
+    }
+
+    fn step(&mut self, rng: &mut XorShift32) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+        self.dir = (self.dir + self.turn) % 6;
+        self.turn = 0;
+
+        let (x, y) = match self.layout.position(self.head()) {
+            Some(p) => p,
+            None => return false,
+        };
+        let (dx, dy) = NEIGHBOR_OFFSETS[self.dir];
+        let next = match self.layout.index(x + dx, y + dy).filter(|i| *i < N) {
+            Some(next) => next,
+            // hit the edge
+            None => return false,
+        };
+
+        if Some(next) == self.food {
+            self.push_head(next);
+            self.score += 1;
+            self.place_food(rng);
+            return self.food.is_some();
+        }
+        // the tail moves out of the way
+        self.pop_tail();
+        if self.occupied[next] {
+            return false;
+        }
+        self.push_head(next);
+        true
+    }
+
+    fn step_ms(&self) -> u32 {
+        SNAKE_STEP_MS
+            .saturating_sub(self.score * SNAKE_SPEEDUP_MS)
+            .max(SNAKE_MIN_STEP_MS)
+    }
+
+    fn score(&self) -> u32 {
+        self.score
+    }
+
+    fn render(&self, frame: &mut [RGB8]) {
+        frame.fill(color::BLACK);
+        for k in 0..self.len {
+            let index = self.body[(self.tail + k) % N] as usize;
+            // brighter towards the head
+            let brightness = 64 + (k * 191 / self.len.max(1)) as u8;
+            if let Some(c) = frame.get_mut(index) {
+                *c = color::scale(color::GREEN, brightness);
+            }
+        }
+        if let Some(c) = self.food.and_then(|f| frame.get_mut(f)) {
+            *c = color::RED;
+        }
+    }
+}
+
+/// Positions are [`cartesian`] coordinates with this many fractional bits.
+const PONG_SUBPIXEL_BITS: u32 = 4;
+const PONG_STEP_MS: u32 = 40;
+/// Cells covered by the paddle.
+const PADDLE_LEN: i16 = 3;
+/// The paddle moves one cell every this many steps.
+const PADDLE_INTERVAL: u8 = 2;
+/// Initial speed of the ball, in 1/256 cells per step.
+const BALL_SPEED: i32 = 64;
+const MAX_BALL_SPEED: i32 = 160;
+/// Largest random change of the horizontal speed when bouncing off a wall.
+const BALL_SPIN: i32 = 12;
+
+/// Pong against the walls of the panel, with the paddle in the bottom row.
+///
+/// The paddle keeps moving and turns around at the ends of the row or when
+/// the button is pressed. An encoder moves it step by step. Every return of
+/// the ball scores a point and makes it faster.
+pub struct Pong {
+    layout: &'static Layout,
+    ball: (i32, i32),
+    velocity: (i32, i32),
+    /// column of the leftmost paddle cell
+    paddle: i16,
+    paddle_dir: i16,
+    steps: u8,
+    score: u32,
+}
+
+impl Pong {
+    pub const fn new(layout: &'static Layout) -> Self {
+        Pong {
+            layout,
+            ball: (0, 0),
+            velocity: (0, 0),
+            paddle: 0,
+            paddle_dir: 1,
+            steps: 0,
+            score: 0,
+        }
+    }
+
+    fn cell(&self, x: i32, y: i32) -> Option<(i16, i16)> {
+        self.layout
+            .cell_at(x >> PONG_SUBPIXEL_BITS, y >> PONG_SUBPIXEL_BITS)
+    }
+
+    /// Columns in the bottom row the paddle can move along.
+    fn paddle_range(&self) -> i16 {
+        let bottom = self.layout.rows[self.layout.rows.len() - 1];
+        (bottom.len as i16 - PADDLE_LEN).max(0)
+    }
+
+    fn move_paddle(&mut self, dir: i16) {
+        self.paddle = (self.paddle + dir).max(0).min(self.paddle_range());
+    }
+}
+
+impl Game for Pong {
+    /// Serves the ball from the top, in a random direction.
+    fn reset(&mut self, rng: &mut XorShift32) {
+        let top = self.layout.rows[0];
+        let (x, y) = cartesian(top.offset as i16 + top.len as i16, 1);
+        self.ball = (x << PONG_SUBPIXEL_BITS, y << PONG_SUBPIXEL_BITS);
+        let vx = rng.below(2 * BALL_SPEED as u32 + 1) as i32 - BALL_SPEED;
+        self.velocity = (vx, BALL_SPEED);
+        self.paddle = self.paddle_range() / 2;
+        self.paddle_dir = 1;
+        self.steps = 0;
+        self.score = 0;
+    }
+
+    fn input(&mut self, input: Input) {
+        match input {
+            Input::Press => self.paddle_dir = -self.paddle_dir,
+            Input::Left => self.move_paddle(-1),
+            Input::Right => self.move_paddle(1),
+        }
+    }
+
+    fn step(&mut self, rng: &mut XorShift32) -> bool {
+        self.steps = self.steps.wrapping_add(1);
+        if self.steps.is_multiple_of(PADDLE_INTERVAL) {
+            if self.paddle + self.paddle_dir < 0
+                || self.paddle + self.paddle_dir > self.paddle_range()
+            {
+                self.paddle_dir = -self.paddle_dir;
+            }
+            self.move_paddle(self.paddle_dir);
+        }
+
+        let (x, y) = self.ball;
+        let (vx, vy) = self.velocity;
+        let (nx, ny) = (x + vx, y + vy);
+        let bottom = self.layout.height() - 1;
+        match self.cell(nx, ny) {
+            Some((cx, cy)) if cy == bottom && vy > 0 => {
+                let row = self.layout.rows[bottom as usize];
+                let hit = (cx - row.offset as i16) / 2 - self.paddle;
+                if !(0..PADDLE_LEN).contains(&hit) {
+                    return false;
+                }
+                // the edges of the paddle send the ball off at an angle
+                let speed = (vy + 8).min(MAX_BALL_SPEED);
+                let vx = vx + (hit - PADDLE_LEN / 2) as i32 * speed / 2;
+                self.velocity = (vx.clamp(-MAX_BALL_SPEED, MAX_BALL_SPEED), -speed);
+                self.score += 1;
+            }
+            Some(_) => self.ball = (nx, ny),
+            None => {
+                // bounce off the blocked direction, a corner blocks both
+                let x_free = self.cell(nx, y).is_some();
+                let y_free = self.cell(x, ny).is_some();
+                if x_free && !y_free {
+                    self.ball.0 = nx;
+                    self.velocity.1 = -vy;
+                } else if y_free && !x_free {
+                    self.ball.1 = ny;
+                    self.velocity.0 = -vx;
+                } else {
+                    self.velocity = (-vx, -vy);
+                }
+                // a little spin, so the ball can't get caught in a loop
+                // between the slanted walls
+                self.velocity.0 += rng.below(2 * BALL_SPIN as u32 + 1) as i32 - BALL_SPIN;
+            }
+        }
+        true
+    }
+
+    fn step_ms(&self) -> u32 {
+        PONG_STEP_MS
+    }
+
+    fn score(&self) -> u32 {
+        self.score
+    }
+
+    fn render(&self, frame: &mut [RGB8]) {
+        frame.fill(color::BLACK);
+        let bottom = self.layout.height() - 1;
+        let row = self.layout.rows[bottom as usize];
+        for col in self.paddle..self.paddle + PADDLE_LEN {
+            let x = row.offset as i16 + 2 * col;
+            if let Some(c) = self.layout.index(x, bottom).and_then(|i| frame.get_mut(i)) {
+                *c = color::CYAN;
+            }
+        }
+        if let Some((x, y)) = self.cell(self.ball.0, self.ball.1) {
+            if let Some(c) = self.layout.index(x, y).and_then(|i| frame.get_mut(i)) {
+                *c = RGB8 {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex::Row;
+
+    /// 7 × 7 cells, wired row by row from the top left.
+    static GRID: Layout = Layout {
+        rows: &[
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+        ],
+        serpentine: false,
+    };
+    const CELLS: usize = 49;
+
+    /// The cell the snake moves into on its next step.
+    fn ahead(snake: &Snake<CELLS>) -> Option<usize> {
+        let (x, y) = GRID.position(snake.head()).unwrap();
+        let (dx, dy) = NEIGHBOR_OFFSETS[(snake.dir + snake.turn) % 6];
+        GRID.index(x + dx, y + dy)
+    }
+
+    #[test]
+    fn snake_grows_and_dies_at_the_edge() {
+        let mut rng = XorShift32::new(1);
+        let mut snake = Snake::<CELLS>::new(&GRID);
+        snake.reset(&mut rng);
+        assert_eq!(snake.len, SNAKE_START_LEN);
+
+        snake.food = ahead(&snake);
+        assert!(snake.step(&mut rng));
+        assert_eq!(snake.len, SNAKE_START_LEN + 1);
+        assert_eq!(snake.score(), 1);
+        assert_eq!(snake.step_ms(), SNAKE_STEP_MS - SNAKE_SPEEDUP_MS);
+
+        snake.food = None;
+        while ahead(&snake).is_some() {
+            assert!(snake.step(&mut rng));
+            assert_eq!(snake.len, SNAKE_START_LEN + 1);
+        }
+        assert!(!snake.step(&mut rng));
+    }
+
+    #[test]
+    fn snake_dies_on_itself() {
+        let mut rng = XorShift32::new(1);
+        let mut snake = Snake::<CELLS>::new(&GRID);
+        snake.reset(&mut rng);
+        // turning on every step goes round in a ring of six cells, which
+        // a snake of seven doesn't fit into
+        while snake.len < 7 {
+            snake.input(Input::Press);
+            snake.food = ahead(&snake);
+            assert!(snake.step(&mut rng));
+        }
+        snake.food = None;
+        loop {
+            snake.input(Input::Press);
+            let next = ahead(&snake).unwrap();
+            if snake.occupied[next] && next != snake.body[snake.tail] as usize {
+                break;
+            }
+            assert!(snake.step(&mut rng));
+        }
+        assert!(!snake.step(&mut rng));
+    }
+
+    /// Pong with the ball in cell `(x, y)`, falling straight down, and a
+    /// paddle that doesn't move by itself.
+    fn pong(x: i16, y: i16, paddle: i16) -> Pong {
+        let mut pong = Pong::new(&GRID);
+        pong.reset(&mut XorShift32::new(1));
+        let (cx, cy) = cartesian(x, y);
+        pong.ball = (cx << PONG_SUBPIXEL_BITS, cy << PONG_SUBPIXEL_BITS);
+        pong.velocity = (0, BALL_SPEED);
+        pong.paddle = paddle;
+        pong.paddle_dir = 0;
+        pong
+    }
+
+    #[test]
+    fn pong_scores_on_the_paddle() {
+        let mut rng = XorShift32::new(1);
+        // the paddle covers the columns 2 to 4 of the bottom row
+        let mut pong = pong(6, 5, 2);
+        while pong.score() == 0 {
+            assert!(pong.step(&mut rng));
+        }
+        assert_eq!(pong.score(), 1);
+        // returned faster, straight up from the middle of the paddle
+        assert_eq!(pong.velocity, (0, -(BALL_SPEED + 8)));
+        for _ in 0..10 {
+            assert!(pong.step(&mut rng));
+        }
+        assert_eq!(pong.score(), 1);
+    }
+
+    #[test]
+    fn pong_ends_on_a_miss() {
+        let mut rng = XorShift32::new(1);
+        let mut pong = pong(12, 5, 0);
+        let mut steps = 0;
+        while pong.step(&mut rng) {
+            steps += 1;
+            assert!(steps < 20, "never missed");
+        }
+        assert_eq!(pong.score(), 0);
+    }
+}
@@ -0,0 +1,202 @@
+//! Geometry of the hexagonal LED panel.
+//!
+//! Cells are addressed in "doubled" coordinates: `y` is the row and `x`
+//! counts half cell widths, so the cells of a row are two units apart and
+//! neighboring rows are shifted by one unit against each other. The six
+//! neighbors of `(x, y)` are `(x ± 2, y)` and `(x ± 1, y ± 1)`.
+//!
+//! `build.rs` compiles this file as a module of its own to sample the LED
+//! images, so it must only use `core`, nothing from the rest of the crate
+//! (`crate::...`) or from the dependencies.
+
+use core::ops::Range;
+
+/// A single row of LEDs.
+#[derive(Clone, Copy, Debug)]
+pub struct Row {
+    /// number of LEDs in the row
+    pub len: u8,
+    /// x coordinate of the leftmost cell
+    pub offset: u8,
+}
+
+impl Row {
+    pub const fn new(len: u8, offset: u8) -> Self {
+        Row { len, offset }
+    }
+}
+
+/// Maps LED indices to hex cells and back.
+#[derive(Debug)]
+pub struct Layout {
+    pub rows: &'static [Row],
+    /// every other row is wired right to left
+    pub serpentine: bool,
+}
+
+/// The panel of the installation, 291 LEDs in 21 rows, wired from the top row.
+pub const PANEL: Layout = Layout {
+    rows: &[
+        Row::new(8, 9),
+        Row::new(9, 8),
+        Row::new(10, 7),
+        Row::new(11, 6),
+        Row::new(15, 3),
+        Row::new(16, 2),
+        Row::new(17, 1),
+        Row::new(17, 0),
+        Row::new(17, 1),
+        Row::new(17, 0),
+        Row::new(17, 1),
+        Row::new(17, 0),
+        Row::new(17, 1),
+        Row::new(17, 0),
+        Row::new(17, 1),
+        Row::new(16, 2),
+        Row::new(15, 3),
+        Row::new(11, 6),
+        Row::new(10, 7),
+        Row::new(9, 8),
+        Row::new(8, 9),
+    ],
+    serpentine: true,
+};
+
+/// Offsets of the six neighbors in doubled coordinates, clockwise from the right.
+pub const NEIGHBOR_OFFSETS: [(i16, i16); 6] = [(2, 0), (1, 1), (-1, 1), (-2, 0), (-1, -1), (1, -1)];
+
+impl Layout {
+    /// Total number of LEDs.
+    pub fn len(&self) -> usize {
+        self.rows.iter().map(|r| r.len as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn height(&self) -> i16 {
+        self.rows.len() as i16
+    }
+
+    /// Width in doubled coordinates, i.e. one more than the largest x.
+    pub fn width(&self) -> i16 {
+        self.rows
+            .iter()
+            .map(|r| r.offset as i16 + 2 * r.len as i16 - 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Index of the first LED of row `y`.
+    fn row_start(&self, y: usize) -> usize {
+        self.rows[..y].iter().map(|r| r.len as usize).sum()
+    }
+
+    /// Cell of the LED with the given index.
+    pub fn position(&self, index: usize) -> Option<(i16, i16)> {
+        let mut start = 0;
+        for (y, row) in self.rows.iter().enumerate() {
+            let len = row.len as usize;
+            if index < start + len {
+                let mut col = index - start;
+                if self.serpentine && y % 2 == 1 {
+                    col = len - 1 - col;
+                }
+                return Some((row.offset as i16 + 2 * col as i16, y as i16));
+            }
+            start += len;
+        }
+        None
+    }
+
+    /// LED index of the cell at `(x, y)`, if there is one.
+    pub fn index(&self, x: i16, y: i16) -> Option<usize> {
+        if y < 0 || y >= self.height() {
+            return None;
+        }
+        let row = self.rows[y as usize];
+        let dx = x - row.offset as i16;
+        if dx < 0 || dx % 2 != 0 || dx / 2 >= row.len as i16 {
+            return None;
+        }
+        let mut col = (dx / 2) as usize;
+        if self.serpentine && y % 2 == 1 {
+            col = row.len as usize - 1 - col;
+        }
+        Some(self.row_start(y as usize) + col)
+    }
+
+    /// The cell nearest to a [`cartesian`] position, if that is on the
+    /// layout. The row is picked first, so positions between rows snap to the
+    /// closer row.
+    pub fn cell_at(&self, cx: i32, cy: i32) -> Option<(i16, i16)> {
+        let y = (cy + 7).div_euclid(14);
+        if y < 0 || y >= self.height() as i32 {
+            return None;
+        }
+        let row = self.rows[y as usize];
+        // the cells of the row are 16 units apart
+        let col = (cx - row.offset as i32 * 8 + 8).div_euclid(16);
+        if col < 0 || col >= row.len as i32 {
+            return None;
+        }
+        Some((row.offset as i16 + 2 * col as i16, y as i16))
+    }
+
+    /// The pixels covered by the LED with the given index when an image of
+    /// `width` by `height` pixels is stretched over the layout, as the ranges
+    /// of x and y.
+    pub fn image_area(
+        &self,
+        index: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<(Range<usize>, Range<usize>)> {
+        // a cell covers 16 by 14 in cartesian coordinates
+        let (w, h) = cartesian(self.width() - 1, self.height() - 1);
+        let (w, h) = ((w + 16) as usize, (h + 14) as usize);
+        let (x, y) = self.position(index)?;
+        let (cx, cy) = cartesian(x, y);
+        let (cx, cy) = (cx as usize, cy as usize);
+        Some((
+            cx * width / w..(cx + 16) * width / w,
+            cy * height / h..(cy + 14) * height / h,
+        ))
+    }
+
+    /// LED indices of the (up to six) neighbors of `index`.
+    pub fn neighbors(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let pos = self.position(index);
+        NEIGHBOR_OFFSETS.iter().filter_map(move |(dx, dy)| {
+            let (x, y) = pos?;
+            self.index(x + dx, y + dy)
+        })
+    }
+}
+
+/// Cartesian position of the cell at `(x, y)` in 1/16 of the cell spacing.
+///
+/// Rows are sqrt(3)/2 cell spacings apart, so distances and angles computed
+/// from these are (nearly) undistorted.
+pub fn cartesian(x: i16, y: i16) -> (i32, i32) {
+    (x as i32 * 8, y as i32 * 14)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_areas_are_the_cells() {
+        // one pixel per cartesian unit
+        let (w, h) = cartesian(PANEL.width() - 1, PANEL.height() - 1);
+        let (w, h) = ((w + 16) as usize, (h + 14) as usize);
+        let first = PANEL.image_area(0, w, h).unwrap();
+        assert_eq!(first, (72..88, 0..14));
+        // the last LED is in the bottom row
+        let last = PANEL.image_area(PANEL.len() - 1, w, h).unwrap();
+        assert_eq!(last.1, h - 14..h);
+        assert_eq!(PANEL.image_area(PANEL.len(), w, h), None);
+    }
+}
@@ -0,0 +1,278 @@
+//! The effects and the frame pipeline of the installation, shared by the
+//! firmware (`examples/hexlife.rs`) and the simulator in `host/`, so the
+//! simulator shows what the panel shows.
+//!
+//! ```ignore
+//! static mut EFFECTS: MaybeUninit<Effects> = MaybeUninit::uninit();
+//! let effects: &'static mut Effects = EFFECTS.write(Effects::new());
+//! let mut player = Player::new(effects.registry(), FADE_BUFFER);
+//! let mut pipeline = Pipeline::new();
+//! // the panel alone, unless the ambient zones are turned on
+//! pipeline.set_ambient(Some(96));
+//! // every frame, with the player or a game on the panel
+//! let currents = pipeline.render(&mut player, now, &mut frame);
+//! ```
+
+use crate::{
+    anim::{self, Animation},
+    assets,
+    automaton::{Ant, Automaton, BriansBrain, Life, SquareNeighbors, Wireworld},
+    color,
+    effect::{Control, Effect, Params, Registry},
+    effects::{
+        ClockFace, ClockStyle, Fire, Marquee, Picture, Plasma, RainbowEffect, Ripples, TextColor,
+    },
+    hex,
+    particles::{Particles, Sand},
+    power::{PowerLimiter, PowerModel, Zone, ZoneReport},
+    segment::{self, Segment, Source},
+};
+use smart_leds::RGB8;
+
+/// LEDs of [`hex::PANEL`].
+pub const NUM_LEDS: usize = 291;
+/// Number of [`Effects`].
+pub const NUM_EFFECTS: usize = 16;
+
+// generation times of the automata, in ms
+const BRAIN_GENERATION_MS: u32 = 150;
+const WIREWORLD_GENERATION_MS: u32 = 100;
+const ANT_GENERATION_MS: u32 = 20;
+
+const MAX_PARTICLES: usize = 32;
+
+const MARQUEE_PIXELS_PER_S: u32 = 12;
+const MARQUEE_TEXT: &str = "hexlife";
+
+// supply of a single zone and rating of its injection wires, in mA
+const ZONE_SUPPLY_MA: u32 = 2_000;
+const ZONE_INJECTION_MA: u32 = 3_000;
+
+// the top and bottom zones can show an ambient effect over the main effect
+const AMBIENT_SPEED: u16 = 128;
+
+// sources of the segments
+const PANEL_SOURCE: usize = 0;
+const TOP_SOURCE: usize = 1;
+const BOTTOM_SOURCE: usize = 2;
+
+/// The effects of the panel.
+///
+/// About 12K, build them at run time. A `static` initialized with
+/// [`Effects::new`] lands in `.data`, stored in flash and copied into RAM.
+pub struct Effects {
+    rainbow: RainbowEffect,
+    life: Life<NUM_LEDS>,
+    brain: Automaton<&'static hex::Layout, BriansBrain, NUM_LEDS>,
+    wireworld: Automaton<&'static hex::Layout, Wireworld, NUM_LEDS>,
+    langton: Automaton<SquareNeighbors, Ant, NUM_LEDS>,
+    hex_ant: Automaton<&'static hex::Layout, Ant, NUM_LEDS>,
+    fire: Fire<NUM_LEDS>,
+    plasma: Plasma,
+    ripples: Ripples,
+    particles: Particles<MAX_PARTICLES, NUM_LEDS>,
+    sand: Sand<NUM_LEDS>,
+    marquee: Marquee<34, 21>,
+    clock: ClockFace<34, 21>,
+    heart: Picture,
+    oscillators: Life<NUM_LEDS>,
+    rings: Animation<NUM_LEDS>,
+}
+
+impl Effects {
+    pub const fn new() -> Self {
+        let mut wireworld =
+            Automaton::new(&hex::PANEL, Wireworld, 0x3456_789a, WIREWORLD_GENERATION_MS);
+        // the circuits are meant to oscillate
+        wireworld.reseed_stagnant = false;
+        Effects {
+            rainbow: RainbowEffect::new(1),
+            life: Life::life(&hex::PANEL, 0x1234_5678),
+            brain: Automaton::new(&hex::PANEL, BriansBrain, 0x2345_6789, BRAIN_GENERATION_MS),
+            wireworld,
+            langton: Automaton::new(
+                SquareNeighbors::von_neumann(&hex::PANEL),
+                Ant::langton(),
+                0,
+                ANT_GENERATION_MS,
+            ),
+            hex_ant: Automaton::new(&hex::PANEL, Ant::hex(), 0, ANT_GENERATION_MS),
+            fire: Fire::new(&hex::PANEL, 0x0bad_f00d),
+            plasma: Plasma::new(&hex::PANEL),
+            ripples: Ripples::new(&hex::PANEL, 0xdead_beef),
+            particles: Particles::new(&hex::PANEL, 0x4567_89ab),
+            sand: Sand::new(&hex::PANEL, 0x5678_9abc),
+            marquee: Marquee::new(&hex::PANEL, MARQUEE_PIXELS_PER_S, TextColor::Rainbow),
+            clock: ClockFace::new(&hex::PANEL, ClockStyle::Hands),
+            heart: Picture::new(&assets::HEART),
+            oscillators: Life::life_patterns(&hex::PANEL, 0x6789_abcd),
+            // anim-encode --fps 10 assets/anim/rings.hxa assets/anim/rings/*.png
+            rings: Animation::new(
+                include_bytes!("../assets/anim/rings.hxa"),
+                anim::LAYOUT_HEX_PANEL,
+            ),
+        }
+    }
+
+    /// All effects, with the marquee showing its default text.
+    pub fn registry(&mut self) -> Registry<'_, NUM_EFFECTS> {
+        self.marquee.control(Control::Text(MARQUEE_TEXT));
+        Registry::new([
+            ("rainbow", &mut self.rainbow),
+            ("life", &mut self.life),
+            ("brain", &mut self.brain),
+            ("wireworld", &mut self.wireworld),
+            ("langton", &mut self.langton),
+            ("hexant", &mut self.hex_ant),
+            ("fire", &mut self.fire),
+            ("plasma", &mut self.plasma),
+            ("ripples", &mut self.ripples),
+            ("particles", &mut self.particles),
+            ("sand", &mut self.sand),
+            ("marquee", &mut self.marquee),
+            ("clock", &mut self.clock),
+            ("heart", &mut self.heart),
+            // appended, the saved rules refer to the effects by index
+            ("oscillators", &mut self.oscillators),
+            ("rings", &mut self.rings),
+        ])
+    }
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The power zones, the panel is fed at four points from the top to the
+/// bottom.
+pub fn power_zones() -> [Zone; 4] {
+    let start0 = 0;
+    let size0 = 8 + 9 + 10 + 11 + 15 + 16 + 17;
+    let start1 = size0;
+    let size1 = 17 + 17 + 17 + 17;
+    let start2 = start1 + size1;
+    let size2 = 17 + 17 + 17 + 17;
+    let start3 = start2 + size2;
+    let size3 = 16 + 15 + 11 + 10 + 9 + 8;
+    let end3 = start3 + size3;
+    assert!(size0 + size1 + size2 + size3 == NUM_LEDS);
+
+    [
+        Zone::new(start0..start1, ZONE_SUPPLY_MA, ZONE_INJECTION_MA),
+        Zone::new(start1..start2, ZONE_SUPPLY_MA, ZONE_INJECTION_MA),
+        Zone::new(start2..start3, ZONE_SUPPLY_MA, ZONE_INJECTION_MA),
+        Zone::new(start3..end3, ZONE_SUPPLY_MA, ZONE_INJECTION_MA),
+    ]
+}
+
+/// A single effect that is never switched, so it needs no crossfades.
+struct Ambient {
+    effect: RainbowEffect,
+    params: Params,
+}
+
+impl Ambient {
+    fn new() -> Self {
+        let mut effect = RainbowEffect::new(3);
+        effect.reset();
+        Ambient {
+            effect,
+            params: Params {
+                speed: AMBIENT_SPEED,
+                ..Params::default()
+            },
+        }
+    }
+}
+
+impl Source for Ambient {
+    fn render(&mut self, now: u32, leds: &mut [RGB8]) {
+        self.effect.render(now, &self.params, leds);
+        for c in leds.iter_mut() {
+            *c = color::scale(*c, self.params.brightness);
+        }
+    }
+}
+
+/// Turns what the panel shows into the frame that is sent to the LEDs.
+pub struct Pipeline {
+    /// top and bottom
+    ambient: [Ambient; 2],
+    /// brightness of the ambient zones, `None` while they are off
+    ambient_brightness: Option<u8>,
+    /// the panel, then the ambient zones
+    segments: [Segment; 3],
+    scratch: [RGB8; NUM_LEDS],
+    power_limiter: PowerLimiter<4>,
+    /// scales all LEDs, set by the time-of-day rules
+    pub brightness: u8,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        let zones = power_zones();
+        Pipeline {
+            ambient: [Ambient::new(), Ambient::new()],
+            ambient_brightness: None,
+            segments: [
+                Segment::new("panel", PANEL_SOURCE, 0..NUM_LEDS),
+                Segment::zone("top", TOP_SOURCE, &zones[0]),
+                // mirrored, so both ends of the panel run towards the center
+                Segment::reversed("bottom", BOTTOM_SOURCE, zones[3].leds.clone()),
+            ],
+            scratch: [color::BLACK; NUM_LEDS],
+            power_limiter: PowerLimiter::new(PowerModel::default(), zones),
+            brightness: 255,
+        }
+    }
+
+    /// Shows a rainbow at `brightness` in the top and bottom zones, over the
+    /// panel. `None` shows the panel alone, which is the default.
+    pub fn set_ambient(&mut self, brightness: Option<u8>) {
+        self.ambient_brightness = brightness;
+        for ambient in self.ambient.iter_mut() {
+            ambient.params.brightness = brightness.unwrap_or(0);
+        }
+    }
+
+    pub fn ambient(&self) -> Option<u8> {
+        self.ambient_brightness
+    }
+
+    /// Renders `panel` and the ambient zones into `frame`, scales them by
+    /// the brightness and limits the current. Returns the current per zone.
+    pub fn render(
+        &mut self,
+        panel: &mut dyn Source,
+        now: u32,
+        frame: &mut [RGB8],
+    ) -> [ZoneReport; 4] {
+        let segments = match self.ambient_brightness {
+            Some(_) => &self.segments[..],
+            None => &self.segments[..1],
+        };
+        let [top, bottom] = &mut self.ambient;
+        segment::render_all(
+            segments,
+            // in the order of the *_SOURCE indices
+            &mut [panel, top, bottom],
+            now,
+            frame,
+            &mut self.scratch,
+        );
+        if self.brightness < 255 {
+            for c in frame.iter_mut() {
+                *c = color::scale(*c, self.brightness);
+            }
+        }
+        self.power_limiter.apply(frame)
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
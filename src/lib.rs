@@ -2,11 +2,55 @@
 #![feature(min_const_generics)]
 #![feature(slice_fill)]
 
-use embedded_graphics::{fonts, pixelcolor, prelude::*, primitives, style};
+#[cfg(feature = "device")]
+use embedded_graphics::{fonts, image, pixelcolor, prelude::*, primitives, style};
+#[cfg(feature = "device")]
 use ssd1306::{displaysize::DisplaySize, mode::GraphicsMode, prelude::WriteOnlyDataCommand};
 
+pub mod anim;
+pub mod apa102;
+pub mod assets;
+pub mod automaton;
+pub mod canvas;
+pub mod clock;
+pub mod command;
+pub mod effect;
+pub mod effects;
+#[cfg(feature = "device")]
+pub mod flash;
+pub mod game;
+pub mod games;
+pub mod hex;
+pub mod installation;
+pub mod math;
+#[cfg(feature = "device")]
+pub mod monotonic;
+pub mod output;
+pub mod particles;
+pub mod power;
+pub mod preview;
+pub mod rng;
+#[cfg(feature = "device")]
+pub mod rtc;
+pub mod schedule;
+pub mod segment;
+pub mod stats;
+#[cfg(feature = "device")]
+pub mod time;
+pub mod timeline;
+#[cfg(feature = "device")]
+pub mod ws2812_dma;
+
 pub trait Console {
-    fn write(&mut self, t: &str, line: Option<i32>);
+    fn write(&mut self, t: &str, line: Option<i32>) {
+        self.write_at(t, 0, line.unwrap_or(0));
+    }
+    /// Writes `t` on `line` from pixel column `x`, the rest of the line is
+    /// cleared from there.
+    fn write_at(&mut self, t: &str, x: i32, line: i32);
+    /// Draws `bitmap` with its top left corner at `(x, y)`, pixels that are
+    /// off are cleared.
+    fn bitmap(&mut self, bitmap: &assets::Bitmap, x: i32, y: i32);
 }
 
 impl core::fmt::Write for &mut dyn Console {
@@ -16,12 +60,13 @@ impl core::fmt::Write for &mut dyn Console {
     }
 }
 
+#[cfg(feature = "device")]
 impl<DI, DSIZE> Console for GraphicsMode<DI, DSIZE>
 where
     DSIZE: DisplaySize,
     DI: WriteOnlyDataCommand,
 {
-    fn write(&mut self, t: &str, line: Option<i32>) {
+    fn write_at(&mut self, t: &str, x: i32, line: i32) {
         // self.clear();
         let style = style::PrimitiveStyleBuilder::new()
             .stroke_width(1)
@@ -29,16 +74,13 @@ where
             .fill_color(pixelcolor::BinaryColor::Off)
             .build();
 
-        let y = match line {
-            Some(l) => l * 8,
-            None => 0,
-        };
+        let y = line * 8;
 
-        primitives::Rectangle::new(Point::new(0, y), Point::new(127, y + 7))
+        primitives::Rectangle::new(Point::new(x, y), Point::new(127, y + 7))
             .into_styled(style)
             .draw(self)
             .unwrap();
-        fonts::Text::new(t, Point::new(0, y))
+        fonts::Text::new(t, Point::new(x, y))
             .into_styled(style::TextStyle::new(
                 fonts::Font6x8,
                 pixelcolor::BinaryColor::On,
@@ -47,6 +89,12 @@ where
             .unwrap();
         // self.flush().unwrap();
     }
+
+    fn bitmap(&mut self, bitmap: &assets::Bitmap, x: i32, y: i32) {
+        image::Image::new(&bitmap.raw(), Point::new(x, y))
+            .draw(self)
+            .unwrap();
+    }
 }
 
 pub mod color {
@@ -96,6 +144,186 @@ pub mod color {
         (wheel_pos * 3, 255 - wheel_pos * 3, 0).into()
     }
 
+    /// Scales all channels of `c` by `scale / 255`.
+    pub fn scale(c: RGB8, scale: u8) -> RGB8 {
+        let s = scale as u16;
+        RGB8 {
+            r: (c.r as u16 * s / 255) as u8,
+            g: (c.g as u16 * s / 255) as u8,
+            b: (c.b as u16 * s / 255) as u8,
+        }
+    }
+
+    /// Moves `c` around the color wheel by `offset`, 256 being a full turn.
+    /// Keeps the brightest and the darkest channel, so grays stay gray.
+    pub fn rotate_hue(c: RGB8, offset: u8) -> RGB8 {
+        let (r, g, b) = (c.r as i32, c.g as i32, c.b as i32);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let chroma = max - min;
+        if chroma == 0 || offset == 0 {
+            return c;
+        }
+        // hue in 256ths of the six sectors between red, yellow, green, cyan,
+        // blue and magenta
+        let hue = if max == r {
+            (g - b) * 256 / chroma
+        } else if max == g {
+            512 + (b - r) * 256 / chroma
+        } else {
+            1024 + (r - g) * 256 / chroma
+        };
+        let hue = (hue + offset as i32 * 6).rem_euclid(6 * 256);
+        let f = chroma * (hue & 0xff) / 256;
+        let (rising, falling) = ((min + f) as u8, (max - f) as u8);
+        let (max, min) = (max as u8, min as u8);
+        match hue >> 8 {
+            0 => (max, rising, min),
+            1 => (falling, max, min),
+            2 => (min, max, rising),
+            3 => (min, falling, max),
+            4 => (rising, min, max),
+            _ => (max, min, falling),
+        }
+        .into()
+    }
+
+    /// Linear blend from `a` (amount 0) to `b` (amount 255).
+    pub fn blend(a: RGB8, b: RGB8, amount: u8) -> RGB8 {
+        let mix = |x: u8, y: u8| {
+            ((x as u16 * (255 - amount as u16) + y as u16 * amount as u16) / 255) as u8
+        };
+        RGB8 {
+            r: mix(a.r, b.r),
+            g: mix(a.g, b.g),
+            b: mix(a.b, b.b),
+        }
+    }
+
+    /// A color gradient of 16 evenly spaced entries.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Palette(pub [RGB8; 16]);
+
+    impl Palette {
+        /// Color at `index`, interpolated between neighboring entries. The gradient wraps around.
+        pub fn lookup(&self, index: u8) -> RGB8 {
+            let entry = (index >> 4) as usize;
+            let frac = (index & 0xf) << 4;
+            blend(self.0[entry], self.0[(entry + 1) % 16], frac)
+        }
+
+        /// Entry by entry blend, `amount` 0 is `self` and 255 is `other`.
+        pub fn blend(&self, other: &Palette, amount: u8) -> Palette {
+            let mut blended = *self;
+            for (c, o) in blended.0.iter_mut().zip(other.0.iter()) {
+                *c = blend(*c, *o, amount);
+            }
+            blended
+        }
+
+        /// The gradient shifted by `offset`, looking up `index` in the result
+        /// gives the color at `index + offset`.
+        pub fn rotated(&self, offset: u8) -> Palette {
+            let mut rotated = *self;
+            for (i, c) in rotated.0.iter_mut().enumerate() {
+                *c = self.lookup((i as u8 * 16).wrapping_add(offset));
+            }
+            rotated
+        }
+    }
+
+    const fn rgb(r: u8, g: u8, b: u8) -> RGB8 {
+        RGB8 { r, g, b }
+    }
+
+    pub const RAINBOW_PALETTE: Palette = Palette([
+        rgb(255, 0, 0),
+        rgb(213, 42, 0),
+        rgb(171, 85, 0),
+        rgb(171, 127, 0),
+        rgb(171, 171, 0),
+        rgb(86, 213, 0),
+        rgb(0, 255, 0),
+        rgb(0, 213, 42),
+        rgb(0, 171, 85),
+        rgb(0, 86, 170),
+        rgb(0, 0, 255),
+        rgb(42, 0, 213),
+        rgb(85, 0, 171),
+        rgb(127, 0, 129),
+        rgb(171, 0, 85),
+        rgb(213, 0, 43),
+    ]);
+
+    pub const HEAT_PALETTE: Palette = Palette([
+        rgb(0, 0, 0),
+        rgb(51, 0, 0),
+        rgb(102, 0, 0),
+        rgb(153, 0, 0),
+        rgb(204, 0, 0),
+        rgb(255, 0, 0),
+        rgb(255, 51, 0),
+        rgb(255, 102, 0),
+        rgb(255, 153, 0),
+        rgb(255, 204, 0),
+        rgb(255, 255, 0),
+        rgb(255, 255, 51),
+        rgb(255, 255, 102),
+        rgb(255, 255, 153),
+        rgb(255, 255, 204),
+        rgb(255, 255, 255),
+    ]);
+
+    pub const OCEAN_PALETTE: Palette = Palette([
+        rgb(25, 25, 112),
+        rgb(0, 0, 139),
+        rgb(25, 25, 112),
+        rgb(0, 0, 128),
+        rgb(0, 0, 139),
+        rgb(0, 0, 205),
+        rgb(46, 139, 87),
+        rgb(0, 128, 128),
+        rgb(95, 158, 160),
+        rgb(0, 0, 255),
+        rgb(0, 139, 139),
+        rgb(100, 149, 237),
+        rgb(127, 255, 212),
+        rgb(46, 139, 87),
+        rgb(0, 255, 255),
+        rgb(135, 206, 250),
+    ]);
+
+    pub const FOREST_PALETTE: Palette = Palette([
+        rgb(0, 100, 0),
+        rgb(0, 100, 0),
+        rgb(85, 107, 47),
+        rgb(0, 100, 0),
+        rgb(0, 128, 0),
+        rgb(34, 139, 34),
+        rgb(107, 142, 35),
+        rgb(0, 128, 0),
+        rgb(46, 139, 87),
+        rgb(102, 205, 170),
+        rgb(50, 205, 50),
+        rgb(154, 205, 50),
+        rgb(144, 238, 144),
+        rgb(124, 252, 0),
+        rgb(102, 205, 170),
+        rgb(34, 139, 34),
+    ]);
+
+    /// The built-in palettes by name.
+    pub const PALETTES: [(&str, &Palette); 4] = [
+        ("rainbow", &RAINBOW_PALETTE),
+        ("heat", &HEAT_PALETTE),
+        ("ocean", &OCEAN_PALETTE),
+        ("forest", &FOREST_PALETTE),
+    ];
+
+    pub fn find_palette(name: &str) -> Option<&'static Palette> {
+        PALETTES.iter().find(|(n, _)| *n == name).map(|(_, p)| *p)
+    }
+
     pub const BLACK: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
     pub const RED: RGB8 = RGB8 { r: 255, g: 0, b: 0 };
     pub const GREEN: RGB8 = RGB8 { r: 0, g: 255, b: 0 };
@@ -119,7 +347,8 @@ pub mod color {
 
 pub mod prelude {
     pub use super::{
-        color::{wheel, Rainbow},
+        color::{wheel, Palette, Rainbow},
+        effect::Effect,
         Console,
     };
 }
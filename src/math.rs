@@ -0,0 +1,54 @@
+//! Integer math helpers for effects.
+
+// sin(x) * 127 for the first quarter of the wave, 0 to 64 inclusive
+const SIN_QUARTER: [u8; 65] = [
+    0, 3, 6, 9, 12, 16, 19, 22, 25, 28, 31, 34, 37, 40, 43, 46, 49, 51, 54, 57, 60, 63, 65, 68, 71,
+    73, 76, 78, 81, 83, 85, 88, 90, 92, 94, 96, 98, 100, 102, 104, 106, 107, 109, 111, 112, 113,
+    115, 116, 117, 118, 120, 121, 122, 122, 123, 124, 125, 125, 126, 126, 126, 127, 127, 127, 127,
+];
+
+/// Sine of `x / 256` turns, mapped to 1..=255 with 128 at zero.
+pub fn sin8(x: u8) -> u8 {
+    let i = (x & 63) as usize;
+    match x >> 6 {
+        0 => 128 + SIN_QUARTER[i],
+        1 => 128 + SIN_QUARTER[64 - i],
+        2 => 128 - SIN_QUARTER[i],
+        _ => 128 - SIN_QUARTER[64 - i],
+    }
+}
+
+/// Cosine, see [`sin8`].
+pub fn cos8(x: u8) -> u8 {
+    sin8(x.wrapping_add(64))
+}
+
+/// Integer square root, rounded down.
+pub fn isqrt(n: u32) -> u32 {
+    if n < 2 {
+        return n;
+    }
+    // Newton iteration from above, the first step is (n + n / n) / 2
+    let mut x = n;
+    let mut y = n / 2 + n % 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isqrt_rounds_down() {
+        for n in 0..=16u32 {
+            let r = isqrt(n);
+            assert!(r * r <= n && (r + 1) * (r + 1) > n, "isqrt({}) = {}", n, r);
+        }
+        assert_eq!(isqrt(u32::MAX), 65_535);
+        assert_eq!(isqrt(65_536 * 65_535), 65_535);
+    }
+}
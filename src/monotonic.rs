@@ -0,0 +1,237 @@
+//! 64-bit monotonic timer for RTIC, built from TIM2 and an overflow counter.
+//!
+//! `CYCCNT` wraps after 2³² core cycles (67 s at 64 MHz), which rules out
+//! long schedules. [`Tim2Monotonic`] runs the 32-bit TIM2 at a selectable
+//! resolution and extends it to 64 bits in the update interrupt:
+//!
+//! ```ignore
+//! #[rtic::app(device = hal::stm32, peripherals = true, monotonic = rtic_stm32::monotonic::Tim2Monotonic)]
+//! const APP: () = {
+//!     #[init]
+//!     fn init(cx: init::Context) {
+//!         // ...
+//!         Tim2Monotonic::start(cx.device.TIM2, 1_000_000, clocks, &mut rcc.apb1r1);
+//!     }
+//!
+//!     #[task(binds = TIM2, priority = 3)]
+//!     fn tim2(_: tim2::Context) {
+//!         Tim2Monotonic::on_interrupt();
+//!     }
+//! };
+//! ```
+
+use core::{
+    convert::TryFrom,
+    num::TryFromIntError,
+    ops::{Add, Sub},
+    sync::atomic::{AtomicU32, Ordering},
+};
+use rtic::{Fraction, Monotonic};
+use stm32l4xx_hal::{
+    rcc::{Clocks, APB1R1},
+    stm32::{RCC, TIM2},
+};
+
+static OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+static TICK_HZ: AtomicU32 = AtomicU32::new(1);
+static RATIO_NUMERATOR: AtomicU32 = AtomicU32::new(1);
+static RATIO_DENOMINATOR: AtomicU32 = AtomicU32::new(1);
+
+/// A point in time, in ticks of [`Tim2Monotonic`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+/// A span of time, in ticks of [`Tim2Monotonic`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Duration(u64);
+
+impl Instant {
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        *self - earlier
+    }
+
+    /// Time since start in ms.
+    pub fn as_millis(&self) -> u64 {
+        self.0 * 1_000 / Tim2Monotonic::hz() as u64
+    }
+}
+
+impl Duration {
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Duration(ticks)
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0 + rhs.0)
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    /// Saturates at zero if `rhs` is later than `self`.
+    fn sub(self, rhs: Instant) -> Duration {
+        Duration(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration(self.0 + rhs.0)
+    }
+}
+
+/// Needed by the RTIC timer queue to program SysTick.
+impl TryFrom<Duration> for u32 {
+    type Error = TryFromIntError;
+
+    fn try_from(d: Duration) -> Result<u32, Self::Error> {
+        u32::try_from(d.0)
+    }
+}
+
+impl crate::clock::Ticks for Instant {
+    fn ticks_since(self, earlier: Self) -> u32 {
+        u32::try_from((self - earlier).0).unwrap_or(u32::MAX)
+    }
+}
+
+/// TIM2 extended to 64 bits, see the [module documentation](self).
+pub struct Tim2Monotonic;
+
+impl Tim2Monotonic {
+    /// Starts TIM2 counting at `resolution_hz`.
+    ///
+    /// `resolution_hz` must divide the timer clock by at most 65536, e.g.
+    /// 1 kHz to 64 MHz for a 64 MHz timer clock.
+    pub fn start(tim: TIM2, resolution_hz: u32, clocks: Clocks, _apb1r1: &mut APB1R1) {
+        // APB1R1 is only taken to prove that nobody else is configuring it
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.apb1enr1.modify(|_, w| w.tim2en().set_bit());
+        rcc.apb1rstr1.modify(|_, w| w.tim2rst().set_bit());
+        rcc.apb1rstr1.modify(|_, w| w.tim2rst().clear_bit());
+
+        // timers run at twice the bus clock if the bus is divided
+        let timclk = clocks.pclk1().0 * if clocks.ppre1() == 1 { 1 } else { 2 };
+        let psc = (timclk / resolution_hz).max(1) - 1;
+        assert!(psc <= 0xffff);
+        let hz = timclk / (psc + 1);
+
+        let gcd = gcd(clocks.sysclk().0, hz);
+        RATIO_NUMERATOR.store(clocks.sysclk().0 / gcd, Ordering::Relaxed);
+        RATIO_DENOMINATOR.store(hz / gcd, Ordering::Relaxed);
+        TICK_HZ.store(hz, Ordering::Relaxed);
+
+        tim.psc.write(|w| w.psc().bits(psc as u16));
+        tim.arr.write(|w| unsafe { w.bits(u32::MAX) });
+        // load the prescaler, this also raises the update flag
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.modify(|_, w| w.uif().clear_bit());
+        tim.dier.modify(|_, w| w.uie().set_bit());
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+    }
+
+    /// Counts an overflow, must be called from the TIM2 interrupt.
+    pub fn on_interrupt() {
+        let tim = unsafe { &*TIM2::ptr() };
+        if tim.sr.read().uif().bit_is_set() {
+            tim.sr.modify(|_, w| w.uif().clear_bit());
+            OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Tick frequency selected in [`Tim2Monotonic::start`].
+    pub fn hz() -> u32 {
+        TICK_HZ.load(Ordering::Relaxed)
+    }
+
+    pub fn ticks_per_ms() -> u32 {
+        Self::hz() / 1_000
+    }
+
+    pub fn millis(ms: u32) -> Duration {
+        Duration(Self::hz() as u64 * ms as u64 / 1_000)
+    }
+
+    pub fn micros(us: u32) -> Duration {
+        Duration(Self::hz() as u64 * us as u64 / 1_000_000)
+    }
+
+    pub fn secs(s: u32) -> Duration {
+        Duration(Self::hz() as u64 * s as u64)
+    }
+
+    /// Period of a task that runs `freq` times per second.
+    pub fn period(freq: u32) -> Duration {
+        assert!(freq > 0, "a period needs a frequency of at least 1 Hz");
+        Duration((Self::hz() / freq) as u64)
+    }
+}
+
+impl Monotonic for Tim2Monotonic {
+    type Instant = Instant;
+
+    fn ratio() -> Fraction {
+        Fraction {
+            numerator: RATIO_NUMERATOR.load(Ordering::Relaxed),
+            denominator: RATIO_DENOMINATOR.load(Ordering::Relaxed),
+        }
+    }
+
+    fn now() -> Instant {
+        let tim = unsafe { &*TIM2::ptr() };
+        cortex_m::interrupt::free(|_| {
+            let mut hi = OVERFLOWS.load(Ordering::Relaxed);
+            let mut lo = tim.cnt.read().bits();
+            if tim.sr.read().uif().bit_is_set() {
+                // the counter wrapped, but the interrupt did not run yet.
+                // Read again, the first read might have been before the wrap.
+                hi = hi.wrapping_add(1);
+                lo = tim.cnt.read().bits();
+            }
+            Instant((hi as u64) << 32 | lo as u64)
+        })
+    }
+
+    unsafe fn reset() {
+        let tim = &*TIM2::ptr();
+        tim.cnt.write(|w| w.bits(0));
+        tim.sr.modify(|_, w| w.uif().clear_bit());
+        OVERFLOWS.store(0, Ordering::Relaxed);
+    }
+
+    fn zero() -> Instant {
+        Instant(0)
+    }
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
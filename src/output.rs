@@ -0,0 +1,287 @@
+//! Driving several LED strips from one logical frame buffer.
+//!
+//! A single chain can only be refreshed so often, so larger installations are
+//! split over several outputs. A list of [`Route`]s decides which LEDs of the
+//! frame go to which output, and [`MultiOutput`] starts all outputs together
+//! so they update in sync:
+//!
+//! ```ignore
+//! // LEDs 0..150 on SPI1, 150..291 on SPI3, the second chain is wired backwards
+//! static ROUTES: [Route; 2] = [Route::new(0, 0..150), Route::reversed(1, 150..291)];
+//!
+//! let outputs = MultiOutput::new((strip_spi1, strip_spi3), &ROUTES);
+//! // in the render task
+//! outputs.lock(|o| o.write(frame)).ok();
+//! // in the DMA interrupt of the second output
+//! outputs.lock(|o| o.on_interrupt(1));
+//! ```
+
+use core::ops::Range;
+use smart_leds::RGB8;
+
+/// A physical strip output that sends frames in the background.
+pub trait StripOutput {
+    /// Encodes the LEDs for the next frame without sending them.
+    fn prepare(&mut self, leds: &mut dyn Iterator<Item = RGB8>);
+
+    /// Starts sending the prepared frame, unless the output is busy.
+    fn start(&mut self);
+
+    fn is_busy(&self) -> bool;
+
+    /// Must be called from the completion interrupt of the output.
+    fn on_interrupt(&mut self);
+}
+
+/// A fixed set of outputs, implemented for arrays and tuples of up to four outputs.
+pub trait Outputs {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&mut self, index: usize) -> &mut dyn StripOutput;
+}
+
+impl<O: StripOutput, const N: usize> Outputs for [O; N] {
+    fn len(&self) -> usize {
+        N
+    }
+
+    fn get(&mut self, index: usize) -> &mut dyn StripOutput {
+        &mut self[index]
+    }
+}
+
+macro_rules! tuple_outputs {
+    ($len:expr, $($name:ident: $index:tt),+) => {
+        impl<$($name: StripOutput),+> Outputs for ($($name,)+) {
+            fn len(&self) -> usize {
+                $len
+            }
+
+            fn get(&mut self, index: usize) -> &mut dyn StripOutput {
+                match index {
+                    $($index => &mut self.$index,)+
+                    _ => panic!("no output {}", index),
+                }
+            }
+        }
+    };
+}
+
+tuple_outputs!(1, A: 0);
+tuple_outputs!(2, A: 0, B: 1);
+tuple_outputs!(3, A: 0, B: 1, C: 2);
+tuple_outputs!(4, A: 0, B: 1, C: 2, D: 3);
+
+/// Sends a range of the frame to an output.
+///
+/// The ranges routed to the same output are sent one after the other, in the
+/// order of the route list.
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub output: usize,
+    pub leds: Range<usize>,
+    /// send the range last LED first
+    pub reversed: bool,
+}
+
+impl Route {
+    pub const fn new(output: usize, leds: Range<usize>) -> Self {
+        Route {
+            output,
+            leds,
+            reversed: false,
+        }
+    }
+
+    pub const fn reversed(output: usize, leds: Range<usize>) -> Self {
+        Route {
+            output,
+            leds,
+            reversed: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// an output is still sending the last frame, the frame was dropped
+    Busy,
+}
+
+/// Splits frames over several outputs, see the [module documentation](self).
+pub struct MultiOutput<O> {
+    outputs: O,
+    routes: &'static [Route],
+}
+
+impl<O: Outputs> MultiOutput<O> {
+    pub fn new(outputs: O, routes: &'static [Route]) -> Self {
+        MultiOutput { outputs, routes }
+    }
+
+    pub fn outputs_mut(&mut self) -> &mut O {
+        &mut self.outputs
+    }
+
+    /// Any of the outputs is still sending.
+    pub fn is_busy(&mut self) -> bool {
+        let outputs = &mut self.outputs;
+        (0..outputs.len()).any(|i| outputs.get(i).is_busy())
+    }
+
+    /// Sends `frame` to all outputs at once.
+    ///
+    /// Frames are only started together, so if any output is still busy the
+    /// frame is dropped. Routes that reach past the end of `frame` are cut off.
+    pub fn write(&mut self, frame: &[RGB8]) -> Result<(), Error> {
+        if self.is_busy() {
+            return Err(Error::Busy);
+        }
+
+        let n = self.outputs.len();
+        for i in 0..n {
+            let mut leds = self
+                .routes
+                .iter()
+                .filter(|r| r.output == i)
+                .flat_map(|r| route_leds(r, frame));
+            self.outputs.get(i).prepare(&mut leds);
+        }
+        for i in 0..n {
+            self.outputs.get(i).start();
+        }
+        Ok(())
+    }
+
+    /// Must be called from the completion interrupt of output `index`.
+    pub fn on_interrupt(&mut self, index: usize) {
+        self.outputs.get(index).on_interrupt();
+    }
+}
+
+fn route_leds<'a>(route: &Route, frame: &'a [RGB8]) -> impl Iterator<Item = RGB8> + 'a {
+    let end = route.leds.end.min(frame.len());
+    let start = route.leds.start.min(end);
+    let leds = &frame[start..end];
+    let reversed = route.reversed;
+    (0..leds.len()).map(move |i| {
+        if reversed {
+            leds[leds.len() - 1 - i]
+        } else {
+            leds[i]
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps the red channel of the last prepared frame, the test frames carry the LED index there.
+    struct Recorder {
+        leds: [u8; 8],
+        len: usize,
+        starts: u32,
+        busy: bool,
+    }
+
+    impl Recorder {
+        fn new() -> Self {
+            Recorder {
+                leds: [0; 8],
+                len: 0,
+                starts: 0,
+                busy: false,
+            }
+        }
+
+        fn sent(&self) -> &[u8] {
+            &self.leds[..self.len]
+        }
+    }
+
+    impl StripOutput for Recorder {
+        fn prepare(&mut self, leds: &mut dyn Iterator<Item = RGB8>) {
+            self.len = 0;
+            for c in leds {
+                self.leds[self.len] = c.r;
+                self.len += 1;
+            }
+        }
+
+        fn start(&mut self) {
+            self.starts += 1;
+            self.busy = true;
+        }
+
+        fn is_busy(&self) -> bool {
+            self.busy
+        }
+
+        fn on_interrupt(&mut self) {
+            self.busy = false;
+        }
+    }
+
+    fn frame() -> [RGB8; 6] {
+        let mut frame = [RGB8::default(); 6];
+        for (i, c) in frame.iter_mut().enumerate() {
+            c.r = i as u8;
+        }
+        frame
+    }
+
+    #[test]
+    fn routes_split_the_frame() {
+        static ROUTES: [Route; 3] = [
+            Route::new(0, 0..2),
+            Route::reversed(1, 2..5),
+            Route::new(0, 5..6),
+        ];
+        let mut outputs = MultiOutput::new((Recorder::new(), Recorder::new()), &ROUTES);
+        outputs.write(&frame()).unwrap();
+        let (a, b) = outputs.outputs_mut();
+        assert_eq!(a.sent(), &[0, 1, 5]);
+        assert_eq!(b.sent(), &[4, 3, 2]);
+        assert_eq!((a.starts, b.starts), (1, 1));
+    }
+
+    #[test]
+    fn routes_are_cut_off_at_the_end_of_the_frame() {
+        static ROUTES: [Route; 3] = [
+            Route::new(0, 4..10),
+            Route::reversed(1, 3..8),
+            Route::new(1, 8..10),
+        ];
+        let mut outputs = MultiOutput::new([Recorder::new(), Recorder::new()], &ROUTES);
+        outputs.write(&frame()).unwrap();
+        let [a, b] = outputs.outputs_mut();
+        assert_eq!(a.sent(), &[4, 5]);
+        assert_eq!(b.sent(), &[5, 4, 3]);
+    }
+
+    #[test]
+    fn frames_start_together() {
+        static ROUTES: [Route; 2] = [Route::new(0, 0..3), Route::new(1, 3..6)];
+        let mut outputs = MultiOutput::new((Recorder::new(), Recorder::new()), &ROUTES);
+        outputs.write(&frame()).unwrap();
+        // the first output finished, the second is still sending
+        outputs.on_interrupt(0);
+        assert!(outputs.is_busy());
+        assert_eq!(outputs.write(&[RGB8::default(); 6]), Err(Error::Busy));
+        let (a, b) = outputs.outputs_mut();
+        assert_eq!((a.starts, b.starts), (1, 1));
+        assert_eq!(a.sent(), &[0, 1, 2]);
+
+        outputs.on_interrupt(1);
+        assert!(!outputs.is_busy());
+        outputs.write(&frame()).unwrap();
+        let (a, b) = outputs.outputs_mut();
+        assert_eq!((a.starts, b.starts), (2, 2));
+        assert_eq!(b.sent(), &[3, 4, 5]);
+    }
+}
@@ -0,0 +1,563 @@
+//! Particle physics on the hex panel.
+//!
+//! Two effects share the same integer-only approach, a fixed simulation step
+//! with catch-up like the [`crate::automaton`]:
+//!
+//! * [`Particles`]: free particles with velocity and gravity, bouncing off the
+//!   edges of the panel and off each other. A fountain at the bottom keeps
+//!   them coming.
+//! * [`Sand`]: grains falling through the hex rows. Every cell rests on the
+//!   two cells below it, so grains pile up in 60° slopes. When the panel is
+//!   full, the floor opens and the sand runs out.
+//!
+//! Both add particles on [`Control::Trigger`], e.g. when the button is pressed.
+
+use crate::{
+    color,
+    effect::{Control, Effect, Params},
+    hex::{cartesian, Layout},
+    math::isqrt,
+    rng::XorShift32,
+};
+use smart_leds::RGB8;
+
+/// Time between two simulation steps at normal speed, in ms.
+const PARTICLE_STEP_MS: u32 = 20;
+const MAX_PARTICLE_STEPS_PER_FRAME: u32 = 4;
+
+/// Positions are [`cartesian`] coordinates with this many fractional bits,
+/// i.e. in 1/256 of the cell spacing.
+const SUBPIXEL_BITS: u32 = 4;
+
+/// Velocities are clamped below one cell per step, so particles can't pass
+/// through walls or each other.
+const MAX_SPEED: i32 = 200;
+
+/// Lifetime of a particle in steps, it fades out during the last quarter.
+const PARTICLE_LIFETIME: u16 = 200;
+
+/// Steps between two particles of the fountain.
+const FOUNTAIN_INTERVAL: u8 = 6;
+
+/// Particles added by [`Control::Trigger`].
+const PARTICLE_BURST: usize = 8;
+
+/// Brightness lost by the trail of a particle per step.
+const TRAIL_DECAY: u8 = 24;
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    x: i32,
+    y: i32,
+    vx: i32,
+    vy: i32,
+    color: u8,
+    age: u16,
+}
+
+/// Up to `P` particles on a layout with `N` cells.
+pub struct Particles<const P: usize, const N: usize> {
+    layout: &'static Layout,
+    particles: [Option<Particle>; P],
+    /// index + 1 of the particle in each cell, 0 if empty
+    occupied: [u8; N],
+    trail: [u8; N],
+    trail_color: [u8; N],
+    rng: XorShift32,
+    step_time: u32,
+    steps: u8,
+    next_color: u8,
+    /// added to the downwards velocity every step
+    pub gravity: i32,
+    /// fraction of the speed kept when bouncing, in 1/256
+    pub elasticity: u8,
+    pub fountain: bool,
+}
+
+impl<const P: usize, const N: usize> Particles<P, N> {
+    pub const fn new(layout: &'static Layout, seed: u32) -> Self {
+        Particles {
+            layout,
+            particles: [None; P],
+            occupied: [0; N],
+            trail: [0; N],
+            trail_color: [0; N],
+            rng: XorShift32::new(seed),
+            step_time: 0,
+            steps: 0,
+            next_color: 0,
+            gravity: 1,
+            elasticity: 192,
+            fountain: true,
+        }
+    }
+
+    /// Number of live particles.
+    pub fn count(&self) -> usize {
+        self.particles.iter().filter(|p| p.is_some()).count()
+    }
+
+    fn cell(&self, x: i32, y: i32) -> Option<usize> {
+        let (hx, hy) = self
+            .layout
+            .cell_at(x >> SUBPIXEL_BITS, y >> SUBPIXEL_BITS)?;
+        self.layout.index(hx, hy).filter(|i| *i < N)
+    }
+
+    /// Adds a particle at cell `(x, y)`, replacing the oldest one if all
+    /// slots are taken.
+    pub fn spawn(&mut self, x: i16, y: i16, vx: i32, vy: i32) {
+        let (cx, cy) = cartesian(x, y);
+        let particle = Particle {
+            x: cx << SUBPIXEL_BITS,
+            y: cy << SUBPIXEL_BITS,
+            vx: vx.clamp(-MAX_SPEED, MAX_SPEED),
+            vy: vy.clamp(-MAX_SPEED, MAX_SPEED),
+            color: self.next_color,
+            age: 0,
+        };
+        self.next_color = self.next_color.wrapping_add(7);
+
+        let slot = match self.particles.iter().position(|p| p.is_none()) {
+            Some(slot) => slot,
+            None => match (0..P).max_by_key(|i| self.particles[*i].map_or(0, |p| p.age)) {
+                Some(slot) => slot,
+                None => return,
+            },
+        };
+        self.particles[slot] = Some(particle);
+    }
+
+    /// Shoots a particle up from the bottom center.
+    fn fountain(&mut self) {
+        let y = self.layout.height() - 1;
+        let row = self.layout.rows[y as usize];
+        let x = row.offset as i16 + 2 * (row.len as i16 / 2);
+        // enough to reach about three quarters of the height
+        let height = (y as i32 * 14) << SUBPIXEL_BITS;
+        let v = isqrt((2 * self.gravity.max(1) * height * 3 / 4) as u32) as i32;
+        let vx = self.rng.below(49) as i32 - 24;
+        let vy = -(v - 8 + self.rng.below(16) as i32);
+        self.spawn(x, y, vx, vy);
+    }
+
+    /// Moves particle `i`, bouncing off walls and other particles.
+    fn move_particle(&mut self, i: usize) {
+        let mut p = match self.particles[i] {
+            Some(p) => p,
+            None => return,
+        };
+        p.age += 1;
+        if p.age >= PARTICLE_LIFETIME {
+            if let Some(cell) = self.cell(p.x, p.y) {
+                self.occupied[cell] = 0;
+            }
+            self.particles[i] = None;
+            return;
+        }
+        p.vy = (p.vy + self.gravity).min(MAX_SPEED);
+
+        let from = self.cell(p.x, p.y);
+        let (nx, ny) = (p.x + p.vx, p.y + p.vy);
+        let bounce = |v: i32| -v * self.elasticity as i32 / 256;
+        match self.cell(nx, ny) {
+            None => {
+                // find the blocked direction, a corner blocks both
+                let x_free = self.cell(nx, p.y).is_some();
+                let y_free = self.cell(p.x, ny).is_some();
+                if x_free {
+                    p.x = nx;
+                } else {
+                    p.vx = bounce(p.vx);
+                }
+                if y_free && !x_free {
+                    p.y = ny;
+                } else {
+                    p.vy = bounce(p.vy);
+                }
+            }
+            Some(to) if Some(to) != from && self.occupied[to] != 0 => {
+                // equal masses, the particles trade velocities
+                let other = self.occupied[to] as usize - 1;
+                if let Some(o) = self.particles[other].as_mut() {
+                    core::mem::swap(&mut p.vx, &mut o.vx);
+                    core::mem::swap(&mut p.vy, &mut o.vy);
+                }
+            }
+            Some(_) => {
+                p.x = nx;
+                p.y = ny;
+            }
+        }
+
+        let to = self.cell(p.x, p.y);
+        if to != from {
+            if let Some(cell) = from {
+                self.occupied[cell] = 0;
+            }
+        }
+        if let Some(cell) = to {
+            self.occupied[cell] = i as u8 + 1;
+        }
+        self.particles[i] = Some(p);
+    }
+
+    pub fn step(&mut self) {
+        for t in self.trail.iter_mut() {
+            *t = t.saturating_sub(TRAIL_DECAY);
+        }
+
+        self.steps = self.steps.wrapping_add(1);
+        if self.fountain && self.steps.is_multiple_of(FOUNTAIN_INTERVAL) {
+            self.fountain();
+        }
+
+        // rebuilt every step, spawning doesn't keep track
+        self.occupied = [0; N];
+        for i in 0..P.min(255) {
+            if let Some(p) = self.particles[i] {
+                if let Some(cell) = self.cell(p.x, p.y) {
+                    self.occupied[cell] = i as u8 + 1;
+                }
+            }
+        }
+        for i in 0..P.min(255) {
+            self.move_particle(i);
+        }
+
+        let particles = self.particles;
+        for p in particles.iter().flatten() {
+            if let Some(cell) = self.cell(p.x, p.y) {
+                let remaining = (PARTICLE_LIFETIME - p.age) as u32;
+                let brightness = (remaining * 4 * 255 / PARTICLE_LIFETIME as u32).min(255);
+                self.trail[cell] = self.trail[cell].max(brightness as u8);
+                self.trail_color[cell] = p.color;
+            }
+        }
+    }
+}
+
+impl<const P: usize, const N: usize> Effect for Particles<P, N> {
+    fn reset(&mut self) {
+        self.particles = [None; P];
+        self.trail = [0; N];
+        self.step_time = 0;
+    }
+
+    /// A trigger throws a handful of particles in from the top.
+    fn control(&mut self, control: Control) -> bool {
+        match control {
+            Control::Reseed(seed) => self.rng = XorShift32::new(seed),
+            Control::Trigger => {
+                let row = self.layout.rows[0];
+                for _ in 0..PARTICLE_BURST {
+                    let x = row.offset as i16 + 2 * self.rng.below(row.len as u32) as i16;
+                    let vx = self.rng.below(129) as i32 - 64;
+                    let vy = self.rng.below(64) as i32;
+                    self.spawn(x, 0, vx, vy);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        if t < self.step_time {
+            // restarted
+            self.step_time = 0;
+        }
+        let interval = params.scale_interval(PARTICLE_STEP_MS).max(1);
+        let behind = (t - self.step_time) / interval;
+        for _ in 0..behind.min(MAX_PARTICLE_STEPS_PER_FRAME) {
+            self.step();
+        }
+        self.step_time += behind * interval;
+
+        for (c, (trail, hue)) in frame
+            .iter_mut()
+            .zip(self.trail.iter().zip(self.trail_color.iter()))
+        {
+            *c = color::scale(params.palette.lookup(*hue), *trail);
+        }
+    }
+}
+
+/// Steps between two grains dropped at the top.
+const SAND_INTERVAL: u8 = 3;
+
+/// Grains added by [`Control::Trigger`].
+const SAND_BURST: usize = 12;
+
+/// Falling sand on a layout with `N` cells.
+pub struct Sand<const N: usize> {
+    layout: &'static Layout,
+    /// palette index + 1 of the grain in each cell, 0 if empty
+    grains: [u8; N],
+    rng: XorShift32,
+    step_time: u32,
+    steps: u8,
+    next_color: u8,
+    /// the bottom row lets the grains out until the panel is empty
+    draining: bool,
+}
+
+impl<const N: usize> Sand<N> {
+    pub const fn new(layout: &'static Layout, seed: u32) -> Self {
+        Sand {
+            layout,
+            grains: [0; N],
+            rng: XorShift32::new(seed),
+            step_time: 0,
+            steps: 0,
+            next_color: 0,
+            draining: false,
+        }
+    }
+
+    pub fn grains(&self) -> usize {
+        self.grains.iter().filter(|g| **g != 0).count()
+    }
+
+    fn index(&self, x: i16, y: i16) -> Option<usize> {
+        self.layout.index(x, y).filter(|i| *i < N)
+    }
+
+    /// Drops a grain into cell `(x, y)`, returns false if that is taken.
+    pub fn drop_grain(&mut self, x: i16, y: i16) -> bool {
+        match self.index(x, y) {
+            Some(i) if self.grains[i] == 0 => {
+                // slowly running through the palette
+                self.grains[i] = (self.next_color >> 2).max(1);
+                self.next_color = self.next_color.wrapping_add(1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drops a grain into a random cell of the top row.
+    fn drop_random(&mut self) -> bool {
+        let row = self.layout.rows[0];
+        let x = row.offset as i16 + 2 * self.rng.below(row.len as u32) as i16;
+        self.drop_grain(x, 0)
+    }
+
+    pub fn step(&mut self) {
+        let height = self.layout.height();
+        if height == 0 {
+            return;
+        }
+
+        // bottom up, so every grain falls at most one row per step
+        for y in (0..height).rev() {
+            let row = self.layout.rows[y as usize];
+            for col in 0..row.len as i16 {
+                let x = row.offset as i16 + 2 * col;
+                let from = match self.index(x, y) {
+                    Some(i) if self.grains[i] != 0 => i,
+                    _ => continue,
+                };
+                let below = [self.index(x - 1, y + 1), self.index(x + 1, y + 1)];
+                if below == [None, None] {
+                    // on the floor or on a ledge, only open while draining
+                    if self.draining {
+                        self.grains[from] = 0;
+                    }
+                    continue;
+                }
+                // no preferred side, or the piles would lean
+                let first = self.rng.below(2) as usize;
+                for to in [below[first], below[1 - first]].iter().flatten() {
+                    if self.grains[*to] == 0 {
+                        self.grains[*to] = self.grains[from];
+                        self.grains[from] = 0;
+                        break;
+                    }
+                }
+            }
+        }
+        if self.draining && self.grains() == 0 {
+            self.draining = false;
+        }
+
+        self.steps = self.steps.wrapping_add(1);
+        if !self.draining && self.steps.is_multiple_of(SAND_INTERVAL) && !self.drop_random() {
+            self.draining = true;
+        }
+    }
+}
+
+impl<const N: usize> Effect for Sand<N> {
+    fn reset(&mut self) {
+        self.grains = [0; N];
+        self.step_time = 0;
+        self.draining = false;
+    }
+
+    fn control(&mut self, control: Control) -> bool {
+        match control {
+            Control::Reseed(seed) => self.rng = XorShift32::new(seed),
+            Control::Trigger if !self.draining => {
+                for _ in 0..SAND_BURST {
+                    self.drop_random();
+                }
+            }
+            Control::Trigger => {}
+            _ => return false,
+        }
+        true
+    }
+
+    fn render(&mut self, t: u32, params: &Params, frame: &mut [RGB8]) {
+        if t < self.step_time {
+            // restarted
+            self.step_time = 0;
+        }
+        let interval = params.scale_interval(PARTICLE_STEP_MS).max(1);
+        let behind = (t - self.step_time) / interval;
+        for _ in 0..behind.min(MAX_PARTICLE_STEPS_PER_FRAME) {
+            self.step();
+        }
+        self.step_time += behind * interval;
+
+        for (c, grain) in frame.iter_mut().zip(self.grains.iter()) {
+            *c = match grain {
+                0 => color::BLACK,
+                g => params.palette.lookup(g.wrapping_mul(4)),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex::Row;
+
+    /// 7 × 7 cells, wired row by row from the top left.
+    static GRID: Layout = Layout {
+        rows: &[
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+            Row::new(7, 1),
+            Row::new(7, 0),
+        ],
+        serpentine: false,
+    };
+    const CELLS: usize = 49;
+
+    fn assert_on_grid<const P: usize>(particles: &Particles<P, CELLS>) {
+        for p in particles.particles.iter().flatten() {
+            assert!(particles.cell(p.x, p.y).is_some(), "{:?} left the grid", p);
+        }
+    }
+
+    #[test]
+    fn particles_bounce_off_the_edges() {
+        let mut particles = Particles::<4, CELLS>::new(&GRID, 1);
+        particles.fountain = false;
+        particles.gravity = 0;
+        particles.spawn(6, 3, MAX_SPEED, 0);
+        let mut bounced = false;
+        for _ in 0..100 {
+            particles.step();
+            assert_on_grid(&particles);
+            bounced |= particles.particles[0].unwrap().vx < 0;
+        }
+        assert!(bounced);
+    }
+
+    #[test]
+    fn particles_stay_on_the_layout() {
+        let mut particles = Particles::<4, CELLS>::new(&GRID, 1);
+        particles.fountain = false;
+        particles.spawn(0, 0, -MAX_SPEED, -MAX_SPEED);
+        particles.spawn(12, 0, MAX_SPEED, -MAX_SPEED);
+        particles.spawn(1, 5, -MAX_SPEED, MAX_SPEED);
+        particles.spawn(6, 6, 1000, 1000);
+        for _ in 0..PARTICLE_LIFETIME - 1 {
+            particles.step();
+            assert_on_grid(&particles);
+            assert_eq!(particles.count(), 4);
+        }
+        // and are gone at the end of their lifetime
+        particles.step();
+        assert_eq!(particles.count(), 0);
+    }
+
+    #[test]
+    fn sand_piles_up_without_losing_grains() {
+        let mut sand = Sand::<CELLS>::new(&GRID, 1);
+        for x in [0, 4, 8, 12].iter() {
+            assert!(sand.drop_grain(*x, 0));
+        }
+        assert!(!sand.drop_grain(4, 0));
+        for _ in 0..60 {
+            let before = sand.grains();
+            sand.step();
+            let added = sand.grains() - before;
+            assert!(added <= 1, "{} grains appeared", added);
+        }
+        assert!(!sand.draining);
+        // one grain every third step
+        assert_eq!(sand.grains(), 4 + 60 / SAND_INTERVAL as usize);
+    }
+
+    #[test]
+    fn sand_drains_when_full_and_fills_again() {
+        let mut sand = Sand::<CELLS>::new(&GRID, 1);
+        let mut steps = 0;
+        while !sand.draining {
+            sand.step();
+            steps += 1;
+            assert!(steps < 1_000, "never full");
+        }
+        assert!(
+            sand.grains() > CELLS / 2,
+            "full at {} grains",
+            sand.grains()
+        );
+        // triggers don't add grains while it runs out
+        sand.control(Control::Trigger);
+        let mut last = sand.grains();
+        while sand.draining {
+            sand.step();
+            assert!(sand.grains() <= last);
+            last = sand.grains();
+            steps += 1;
+            assert!(steps < 2_000, "never empty");
+        }
+        // the step that empties it may already drop the first grain again
+        assert!(sand.grains() <= 1);
+        for _ in 0..SAND_INTERVAL {
+            if sand.grains() > 0 {
+                break;
+            }
+            sand.step();
+        }
+        assert_eq!(sand.grains(), 1);
+    }
+
+    #[test]
+    fn catching_up_is_limited() {
+        let params = Params::default();
+        let mut frame = [color::BLACK; CELLS];
+
+        let mut particles = Particles::<4, CELLS>::new(&GRID, 1);
+        particles.render(10_000, &params, &mut frame);
+        assert_eq!(particles.steps as u32, MAX_PARTICLE_STEPS_PER_FRAME);
+        // the steps that were skipped are not made up for later
+        particles.render(10_000 + PARTICLE_STEP_MS, &params, &mut frame);
+        assert_eq!(particles.steps as u32, MAX_PARTICLE_STEPS_PER_FRAME + 1);
+
+        let mut sand = Sand::<CELLS>::new(&GRID, 1);
+        sand.render(10_000, &params, &mut frame);
+        assert_eq!(sand.steps as u32, MAX_PARTICLE_STEPS_PER_FRAME);
+        sand.render(10_000 + PARTICLE_STEP_MS, &params, &mut frame);
+        assert_eq!(sand.steps as u32, MAX_PARTICLE_STEPS_PER_FRAME + 1);
+    }
+}
@@ -0,0 +1,369 @@
+//! Current estimation and limiting for WS2812-style LED strips.
+//!
+//! The model is linear in the channel values: every LED draws a quiescent
+//! current plus a current per channel that is proportional to the 8-bit
+//! channel value. The coefficients can be fitted from bench measurements with
+//! [`Calibration`]. Other pixel types, like the APA102 pixels with their
+//! global brightness, take part through the [`Led`] trait.
+
+use crate::color;
+use core::ops::Range;
+use smart_leds::RGB8;
+
+/// A pixel that can be estimated and dimmed by the [`PowerLimiter`].
+pub trait Led: Copy {
+    /// 8-bit color with the same light output.
+    fn rgb(&self) -> RGB8;
+
+    /// The pixel dimmed to `scale` / 255.
+    fn scaled(self, scale: u8) -> Self;
+}
+
+impl Led for RGB8 {
+    fn rgb(&self) -> RGB8 {
+        *self
+    }
+
+    fn scaled(self, scale: u8) -> Self {
+        color::scale(self, scale)
+    }
+}
+
+/// Per-channel current model of a single LED. All currents are in µA.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerModel {
+    /// current of the red channel at full scale (255)
+    pub red_ua: u32,
+    /// current of the green channel at full scale (255)
+    pub green_ua: u32,
+    /// current of the blue channel at full scale (255)
+    pub blue_ua: u32,
+    /// current of an LED that is switched off
+    pub quiescent_ua: u32,
+}
+
+impl Default for PowerModel {
+    /// Datasheet values of a typical WS2812B.
+    fn default() -> Self {
+        PowerModel {
+            red_ua: 12_000,
+            green_ua: 12_000,
+            blue_ua: 12_000,
+            quiescent_ua: 1_000,
+        }
+    }
+}
+
+impl PowerModel {
+    /// Estimated current of a single LED in µA.
+    pub fn led_ua(&self, c: &RGB8) -> u32 {
+        self.estimate_ua(core::slice::from_ref(c))
+    }
+
+    /// Estimated current of a run of LEDs in µA.
+    pub fn estimate_ua<L: Led>(&self, leds: &[L]) -> u32 {
+        // sum up the channels first, so that we only round once
+        let (mut r, mut g, mut b) = (0u64, 0u64, 0u64);
+        for c in leds.iter().map(Led::rgb) {
+            r += c.r as u64;
+            g += c.g as u64;
+            b += c.b as u64;
+        }
+        let dynamic =
+            (r * self.red_ua as u64 + g * self.green_ua as u64 + b * self.blue_ua as u64) / 255;
+        (leds.len() as u64 * self.quiescent_ua as u64 + dynamic) as u32
+    }
+}
+
+/// A section of the strip that is fed by its own power injection point.
+#[derive(Clone, Debug)]
+pub struct Zone {
+    /// LED indices belonging to the zone
+    pub leds: Range<usize>,
+    /// maximum current the supply of the zone can deliver, in mA
+    pub supply_ma: u32,
+    /// maximum current allowed through the injection wiring, in mA
+    pub injection_ma: u32,
+}
+
+impl Zone {
+    pub const fn new(leds: Range<usize>, supply_ma: u32, injection_ma: u32) -> Self {
+        Zone {
+            leds,
+            supply_ma,
+            injection_ma,
+        }
+    }
+
+    /// Current budget of the zone in mA.
+    pub fn limit_ma(&self) -> u32 {
+        self.supply_ma.min(self.injection_ma)
+    }
+}
+
+/// Result of limiting a single zone.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ZoneReport {
+    /// estimated current of the unmodified frame, in mA
+    pub requested_ma: u32,
+    /// estimated current after limiting, in mA
+    pub output_ma: u32,
+    /// scale that was applied to the zone (255 means unchanged)
+    pub scale: u8,
+}
+
+/// Estimates and limits the current of a frame zone by zone.
+pub struct PowerLimiter<const N: usize> {
+    pub model: PowerModel,
+    pub zones: [Zone; N],
+}
+
+impl<const N: usize> PowerLimiter<N> {
+    pub fn new(model: PowerModel, zones: [Zone; N]) -> Self {
+        PowerLimiter { model, zones }
+    }
+
+    /// Estimated current per zone in mA.
+    pub fn estimate<L: Led>(&self, frame: &[L]) -> [u32; N] {
+        let mut out = [0; N];
+        for (o, zone) in out.iter_mut().zip(self.zones.iter()) {
+            *o = self.model.estimate_ua(&frame[zone.leds.clone()]) / 1000;
+        }
+        out
+    }
+
+    /// Scales down every zone that would exceed its budget.
+    ///
+    /// Only the dynamic part of the current is scaled, the quiescent current
+    /// of the zone is always drawn.
+    pub fn apply<L: Led>(&self, frame: &mut [L]) -> [ZoneReport; N] {
+        let mut out = [ZoneReport::default(); N];
+        for (report, zone) in out.iter_mut().zip(self.zones.iter()) {
+            let leds = &mut frame[zone.leds.clone()];
+            let requested = self.model.estimate_ua(leds);
+            let limit = zone.limit_ma() * 1000;
+
+            let scale = if requested <= limit {
+                255
+            } else {
+                let quiescent = leds.len() as u32 * self.model.quiescent_ua;
+                let available = limit.saturating_sub(quiescent) as u64;
+                let dynamic = (requested - quiescent) as u64;
+                (available * 255 / dynamic) as u8
+            };
+
+            if scale != 255 {
+                for c in leds.iter_mut() {
+                    *c = c.scaled(scale);
+                }
+            }
+
+            *report = ZoneReport {
+                requested_ma: requested / 1000,
+                output_ma: self.model.estimate_ua(leds) / 1000,
+                scale,
+            };
+        }
+        out
+    }
+}
+
+/// Least-squares fit of a [`PowerModel`] from measured (frame, current) samples.
+///
+/// Meant to be driven from the host while the strip is hooked up to a bench
+/// supply: show a frame, read the supply current and pass both to
+/// [`Calibration::add_sample`]. At least four linearly independent frames are
+/// needed, e.g. all off, full red, full green and full blue. Only the
+/// accumulated normal equations are stored, so any number of samples can be
+/// added.
+#[derive(Default)]
+pub struct Calibration {
+    ata: [[f64; 4]; 4],
+    atb: [f64; 4],
+    samples: u32,
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a frame together with the current measured while it was shown, in mA.
+    pub fn add_sample(&mut self, frame: &[RGB8], measured_ma: f32) {
+        let mut x = [frame.len() as f64, 0.0, 0.0, 0.0];
+        for c in frame {
+            x[1] += c.r as f64 / 255.0;
+            x[2] += c.g as f64 / 255.0;
+            x[3] += c.b as f64 / 255.0;
+        }
+        let y = measured_ma as f64 * 1000.0;
+
+        for i in 0..4 {
+            for j in 0..4 {
+                self.ata[i][j] += x[i] * x[j];
+            }
+            self.atb[i] += x[i] * y;
+        }
+        self.samples += 1;
+    }
+
+    pub fn num_samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Solves for the model coefficients.
+    ///
+    /// Returns `None` if the samples do not determine all coefficients.
+    /// Negative coefficients (measurement noise) are clamped to zero.
+    pub fn fit(&self) -> Option<PowerModel> {
+        let mut a = self.ata;
+        let mut b = self.atb;
+
+        // gaussian elimination with partial pivoting
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| {
+                abs(a[i][col])
+                    .partial_cmp(&abs(a[j][col]))
+                    .unwrap_or(core::cmp::Ordering::Equal)
+            })?;
+            if abs(a[pivot][col]) < 1e-9 {
+                return None;
+            }
+            a.swap(col, pivot);
+            b.swap(col, pivot);
+
+            let pivot_row = a[col];
+            for row in col + 1..4 {
+                let f = a[row][col] / pivot_row[col];
+                for (v, p) in a[row][col..].iter_mut().zip(pivot_row[col..].iter()) {
+                    *v -= f * p;
+                }
+                b[row] -= f * b[col];
+            }
+        }
+
+        let mut x = [0f64; 4];
+        for row in (0..4).rev() {
+            let s: f64 = a[row][row + 1..]
+                .iter()
+                .zip(x[row + 1..].iter())
+                .fold(b[row], |s, (a, x)| s - a * x);
+            x[row] = s / a[row][row];
+        }
+
+        // `as` saturates, so negative values end up as 0
+        let to_ua = |v: f64| (v + 0.5) as u32;
+        Some(PowerModel {
+            quiescent_ua: to_ua(x[0]),
+            red_ua: to_ua(x[1]),
+            green_ua: to_ua(x[2]),
+            blue_ua: to_ua(x[3]),
+        })
+    }
+}
+
+fn abs(v: f64) -> f64 {
+    if v < 0.0 {
+        -v
+    } else {
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: PowerModel = PowerModel {
+        red_ua: 13_500,
+        green_ua: 11_200,
+        blue_ua: 10_800,
+        quiescent_ua: 900,
+    };
+
+    /// What a bench supply would show for `frame` with [`MODEL`], in mA.
+    fn measure(frame: &[RGB8]) -> f32 {
+        let ua: f64 = frame
+            .iter()
+            .map(|c| {
+                MODEL.quiescent_ua as f64
+                    + (c.r as f64 * MODEL.red_ua as f64
+                        + c.g as f64 * MODEL.green_ua as f64
+                        + c.b as f64 * MODEL.blue_ua as f64)
+                        / 255.0
+            })
+            .sum();
+        (ua / 1000.0) as f32
+    }
+
+    fn assert_close(fitted: &PowerModel) {
+        let close = |a: u32, b: u32| (a as i64 - b as i64).abs() <= 2;
+        assert!(close(fitted.red_ua, MODEL.red_ua), "{:?}", fitted);
+        assert!(close(fitted.green_ua, MODEL.green_ua), "{:?}", fitted);
+        assert!(close(fitted.blue_ua, MODEL.blue_ua), "{:?}", fitted);
+        assert!(close(fitted.quiescent_ua, MODEL.quiescent_ua), "{:?}", fitted);
+    }
+
+    #[test]
+    fn fit_recovers_the_channels() {
+        let mut calibration = Calibration::new();
+        for c in [
+            RGB8::new(0, 0, 0),
+            RGB8::new(255, 0, 0),
+            RGB8::new(0, 255, 0),
+            RGB8::new(0, 0, 255),
+        ]
+        .iter()
+        {
+            let frame = [*c; 20];
+            calibration.add_sample(&frame, measure(&frame));
+        }
+        assert_eq!(calibration.num_samples(), 4);
+        assert_close(&calibration.fit().unwrap());
+    }
+
+    #[test]
+    fn fit_from_mixed_frames() {
+        // partly lit strips in mixed colors, more samples than unknowns
+        let mut calibration = Calibration::new();
+        let colors = [
+            RGB8::new(255, 255, 255),
+            RGB8::new(128, 0, 64),
+            RGB8::new(10, 200, 30),
+            RGB8::new(0, 90, 255),
+            RGB8::new(255, 128, 0),
+        ];
+        for (i, c) in colors.iter().enumerate() {
+            let mut frame = [RGB8::default(); 30];
+            for led in frame.iter_mut().take(6 * (i + 1)) {
+                *led = *c;
+            }
+            calibration.add_sample(&frame, measure(&frame));
+        }
+        assert_close(&calibration.fit().unwrap());
+    }
+
+    #[test]
+    fn fit_needs_independent_frames() {
+        // white only, the channels can't be told apart
+        let mut calibration = Calibration::new();
+        for level in [0, 128, 255].iter() {
+            let frame = [RGB8::new(*level, *level, *level); 10];
+            calibration.add_sample(&frame, measure(&frame));
+        }
+        assert_eq!(calibration.fit(), None);
+        assert_eq!(Calibration::new().fit(), None);
+    }
+
+    #[test]
+    fn limiter_keeps_zones_within_budget() {
+        let zones = [Zone::new(0..10, 100, 200), Zone::new(10..20, 1_000, 1_000)];
+        let limiter = PowerLimiter::new(MODEL, zones);
+        let mut frame = [RGB8::new(255, 255, 255); 20];
+        let reports = limiter.apply(&mut frame);
+        assert!(reports[0].scale < 255);
+        assert!(reports[0].output_ma <= 100);
+        assert_eq!(reports[1].scale, 255);
+        assert_eq!(frame[10], RGB8::new(255, 255, 255));
+    }
+}
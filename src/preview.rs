@@ -0,0 +1,84 @@
+//! A monochrome picture of the LEDs, for the display.
+//!
+//! Every cell of the layout becomes a block of [`CELL_WIDTH`] × [`ROW_HEIGHT`]
+//! pixels, shifted by half a cell from row to row like the LEDs, so the
+//! panel takes 70 × 63 pixels. Brightness is rendered by ordered dithering:
+//!
+//! ```ignore
+//! let leds = cx.resources.led_strip_data.lock(|d| *d);
+//! Preview::new(&hex::PANEL, &leds, Point::zero()).draw(disp).unwrap();
+//! ```
+
+use crate::{hex::Layout, math::isqrt};
+use embedded_graphics::{
+    drawable::{Drawable, Pixel},
+    geometry::{Point, Size},
+    pixelcolor::BinaryColor,
+    DrawTarget,
+};
+use smart_leds::RGB8;
+
+/// Pixels per cell, neighboring cells of a row are this far apart.
+pub const CELL_WIDTH: i32 = 4;
+pub const ROW_HEIGHT: i32 = 3;
+
+/// 4 × 4 Bayer matrix, the order in which the pixels of a block turn on.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+pub struct Preview<'a> {
+    layout: &'a Layout,
+    leds: &'a [RGB8],
+    top_left: Point,
+}
+
+impl<'a> Preview<'a> {
+    /// The LEDs in `leds` (in layout order), drawn from `top_left`.
+    pub fn new(layout: &'a Layout, leds: &'a [RGB8], top_left: Point) -> Self {
+        Preview {
+            layout,
+            leds,
+            top_left,
+        }
+    }
+
+    /// Size of the picture of `layout` in pixels.
+    pub fn size(layout: &Layout) -> Size {
+        // doubled coordinates, a cell is two units wide
+        let width = (layout.width() as i32 + 1) * CELL_WIDTH / 2;
+        let height = layout.height() as i32 * ROW_HEIGHT;
+        Size::new(width as u32, height as u32)
+    }
+
+    /// The pixels of all cells, off pixels included. Pixels between the
+    /// cells (outside the layout) are left alone.
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel<BinaryColor>> + '_ {
+        self.leds
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, c)| Some((self.layout.position(i)?, level(c))))
+            .flat_map(move |((x, y), level)| {
+                let left = self.top_left.x + x as i32 * CELL_WIDTH / 2;
+                let top = self.top_left.y + y as i32 * ROW_HEIGHT;
+                (0..CELL_WIDTH * ROW_HEIGHT).map(move |k| {
+                    let p = Point::new(left + k % CELL_WIDTH, top + k / CELL_WIDTH);
+                    // the pattern follows the screen, so neighboring cells fit
+                    let rank = BAYER[p.y as usize % 4][p.x as usize % 4];
+                    let on = level > rank * 16 + 8;
+                    Pixel(p, BinaryColor::from(on))
+                })
+            })
+    }
+}
+
+/// Perceived brightness of an LED, 0 to 255. Dim LEDs look brighter than
+/// their values, so the luma is lifted by a square root.
+fn level(c: &RGB8) -> u8 {
+    let luma = (2 * c.r as u32 + 5 * c.g as u32 + c.b as u32) / 8;
+    isqrt(luma * 255) as u8
+}
+
+impl Drawable<BinaryColor> for Preview<'_> {
+    fn draw<D: DrawTarget<BinaryColor>>(self, display: &mut D) -> Result<(), D::Error> {
+        display.draw_iter(self.pixels())
+    }
+}
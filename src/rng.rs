@@ -0,0 +1,199 @@
+//! Small and fast pseudo random numbers for effects.
+//!
+//! The generators are seeded by a [`Seeder`], either from a hardware entropy
+//! source like the RNG peripheral or, to reproduce an animation, from a fixed
+//! seed:
+//!
+//! ```ignore
+//! let rng = cx.device.RNG.enable(&mut rcc.ahb2, clocks);
+//! let mut seeder = Seeder::new(rng, SeedMode::Entropy);
+//! player.registry_mut().reseed(|| seeder.next_seed());
+//! ```
+
+use embedded_hal::blocking::rng::Read;
+
+/// Marsaglia's xorshift32.
+#[derive(Clone, Debug)]
+pub struct XorShift32 {
+    state: u32,
+}
+
+impl XorShift32 {
+    /// A zero seed would get the generator stuck, it is replaced by a fixed value.
+    pub const fn new(seed: u32) -> Self {
+        XorShift32 {
+            state: if seed == 0 { 0x2545_f491 } else { seed },
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u32() >> 24) as u8
+    }
+
+    /// Uniform-ish value in `0..n`, `n` must not be 0.
+    pub fn below(&mut self, n: u32) -> u32 {
+        ((self.next_u32() as u64 * n as u64) >> 32) as u32
+    }
+
+    /// `true` with a probability of `p / 256`.
+    pub fn chance(&mut self, p: u8) -> bool {
+        self.next_u8() < p
+    }
+}
+
+/// Where the seeds handed out by a [`Seeder`] come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeedMode {
+    /// the entropy source, different on every run
+    Entropy,
+    /// derived from the given seed, the same on every run
+    Fixed(u32),
+}
+
+/// An entropy source that has no entropy, for [`SeedMode::Fixed`] without
+/// hardware, e.g. on the host.
+pub struct NoEntropy;
+
+impl Read for NoEntropy {
+    type Error = ();
+
+    fn read(&mut self, _buffer: &mut [u8]) -> Result<(), Self::Error> {
+        Err(())
+    }
+}
+
+/// Hands out seeds for the pseudo random generators.
+pub struct Seeder<E> {
+    entropy: E,
+    mode: SeedMode,
+    /// the sequence for [`SeedMode::Fixed`] and the fallback if the entropy
+    /// source fails
+    sequence: XorShift32,
+}
+
+impl<E: Read> Seeder<E> {
+    pub fn new(entropy: E, mode: SeedMode) -> Self {
+        let mut seeder = Seeder {
+            entropy,
+            mode,
+            sequence: XorShift32::new(0),
+        };
+        seeder.set_mode(mode);
+        seeder
+    }
+
+    pub fn mode(&self) -> SeedMode {
+        self.mode
+    }
+
+    /// Switches the mode, [`SeedMode::Fixed`] restarts its sequence.
+    pub fn set_mode(&mut self, mode: SeedMode) {
+        self.mode = mode;
+        if let SeedMode::Fixed(seed) = mode {
+            self.sequence = XorShift32::new(seed);
+        }
+    }
+
+    pub fn next_seed(&mut self) -> u32 {
+        if self.mode == SeedMode::Entropy {
+            let mut buf = [0; 4];
+            // on a seed or clock error the RNG has to be restarted, the
+            // sequence is good enough until then
+            if self.entropy.read(&mut buf).is_ok() {
+                return u32::from_le_bytes(buf);
+            }
+        }
+        self.sequence.next_u32()
+    }
+
+    /// A new generator for an effect.
+    pub fn rng(&mut self) -> XorShift32 {
+        XorShift32::new(self.next_seed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Delivers `bytes` the given number of times, then fails.
+    struct Flaky {
+        bytes: [u8; 4],
+        reads: u32,
+    }
+
+    impl Read for Flaky {
+        type Error = ();
+
+        fn read(&mut self, buffer: &mut [u8]) -> Result<(), Self::Error> {
+            if self.reads == 0 {
+                return Err(());
+            }
+            self.reads -= 1;
+            buffer.copy_from_slice(&self.bytes);
+            Ok(())
+        }
+    }
+
+    fn seeds<E: Read>(seeder: &mut Seeder<E>) -> [u32; 3] {
+        [seeder.next_seed(), seeder.next_seed(), seeder.next_seed()]
+    }
+
+    #[test]
+    fn fixed_seeds_repeat() {
+        let mut seeder = Seeder::new(NoEntropy, SeedMode::Fixed(42));
+        let first = seeds(&mut seeder);
+        assert_ne!(first[0], first[1]);
+        assert_ne!(first[1], first[2]);
+
+        seeder.set_mode(SeedMode::Fixed(42));
+        assert_eq!(seeds(&mut seeder), first);
+        seeder.set_mode(SeedMode::Fixed(43));
+        assert_ne!(seeds(&mut seeder), first);
+        // and the generators seeded with them as well
+        seeder.set_mode(SeedMode::Fixed(42));
+        assert_eq!(
+            seeder.rng().next_u32(),
+            XorShift32::new(first[0]).next_u32()
+        );
+    }
+
+    #[test]
+    fn fixed_mode_ignores_the_entropy() {
+        let flaky = Flaky {
+            bytes: [1, 2, 3, 4],
+            reads: 3,
+        };
+        let mut seeder = Seeder::new(flaky, SeedMode::Fixed(42));
+        assert_eq!(
+            seeds(&mut seeder),
+            seeds(&mut Seeder::new(NoEntropy, SeedMode::Fixed(42)))
+        );
+    }
+
+    #[test]
+    fn entropy_failures_fall_back_to_the_sequence() {
+        let mut fallback = XorShift32::new(0);
+        let mut seeder = Seeder::new(NoEntropy, SeedMode::Entropy);
+        assert_eq!(seeder.next_seed(), fallback.next_u32());
+        assert_eq!(seeder.next_seed(), fallback.next_u32());
+
+        let flaky = Flaky {
+            bytes: [0x78, 0x56, 0x34, 0x12],
+            reads: 1,
+        };
+        let mut seeder = Seeder::new(flaky, SeedMode::Entropy);
+        assert_eq!(seeder.next_seed(), 0x1234_5678);
+        // the sequence starts where it was, the entropy didn't use it up
+        assert_eq!(seeder.next_seed(), XorShift32::new(0).next_u32());
+    }
+}
@@ -0,0 +1,242 @@
+//! Wall clock time from the RTC, clocked by the 32.768 kHz LSE crystal.
+//!
+//! The RTC and the LSE live in the backup domain, which keeps running through
+//! resets (and on VBAT). [`Rtc::new`] only configures them when they are not
+//! running from the LSE yet, so the time survives resets and reflashing.
+
+use core::ptr::{read_volatile, write_volatile};
+use stm32l4xx_hal::{
+    pwr,
+    rcc::{Clocks, APB1R1, BDCR},
+    stm32::{PWR, RCC, RTC},
+};
+
+// register offsets
+const RCC_APB1ENR1: usize = 0x58;
+const RCC_BDCR: usize = 0x90;
+const PWR_CR1: usize = 0x00;
+const RTC_TR: usize = 0x00;
+const RTC_DR: usize = 0x04;
+const RTC_CR: usize = 0x08;
+const RTC_ISR: usize = 0x0c;
+const RTC_PRER: usize = 0x10;
+const RTC_WPR: usize = 0x24;
+
+const APB1ENR1_RTCAPBEN: u32 = 1 << 10;
+const APB1ENR1_PWREN: u32 = 1 << 28;
+const BDCR_LSEON: u32 = 1 << 0;
+const BDCR_LSERDY: u32 = 1 << 1;
+const BDCR_RTCSEL_MASK: u32 = 0b11 << 8;
+const BDCR_RTCSEL_LSE: u32 = 0b01 << 8;
+const BDCR_RTCEN: u32 = 1 << 15;
+const BDCR_BDRST: u32 = 1 << 16;
+const PWR_CR1_DBP: u32 = 1 << 8;
+const CR_FMT_AMPM: u32 = 1 << 6;
+const ISR_INITS: u32 = 1 << 4;
+const ISR_RSF: u32 = 1 << 5;
+const ISR_INITF: u32 = 1 << 6;
+const ISR_INIT: u32 = 1 << 7;
+
+/// 32768 Hz / (127 + 1) / (255 + 1) = 1 Hz
+const PRER_LSE: u32 = 127 << 16 | 255;
+
+/// The LSE takes up to 2 s to start, more with a marginal crystal.
+const LSE_TIMEOUT_MS: u32 = 5_000;
+/// The RTC flags follow within a few periods of the 32.768 kHz clock.
+const SYNC_TIMEOUT_MS: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// the LSE crystal did not start, e.g. because it is not fitted
+    LseTimeout,
+    /// the RTC did not respond, i.e. it is not clocked
+    Timeout,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    /// 2000 to 2099
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn seconds_of_day(&self) -> u32 {
+        self.hour as u32 * 3600 + self.minute as u32 * 60 + self.second as u32
+    }
+
+    /// Day of the week, 1 (Monday) to 7 (Sunday) like the RTC counts.
+    pub fn weekday(&self) -> u8 {
+        // Sakamoto's method
+        const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let year = if self.month < 3 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let month = (self.month.max(1).min(12) - 1) as usize;
+        let sunday_based =
+            (year + year / 4 - year / 100 + year / 400 + OFFSETS[month] + self.day as u16) % 7;
+        if sunday_based == 0 {
+            7
+        } else {
+            sunday_based as u8
+        }
+    }
+}
+
+fn bcd(value: u8) -> u32 {
+    ((value / 10) << 4 | value % 10) as u32
+}
+
+fn from_bcd(bits: u32) -> u8 {
+    ((bits >> 4 & 0xf) * 10 + (bits & 0xf)) as u8
+}
+
+pub struct Rtc {
+    _rtc: RTC,
+    base: usize,
+    /// core cycles per ms, for the timeouts
+    cycles_per_ms: u32,
+}
+
+impl Rtc {
+    /// Starts the LSE and the RTC unless they are already running.
+    ///
+    /// The RCC and PWR parts are only taken to prove that nobody else is
+    /// configuring them.
+    pub fn new(
+        rtc: RTC,
+        clocks: Clocks,
+        _apb1r1: &mut APB1R1,
+        _bdcr: &mut BDCR,
+        _cr1: &mut pwr::CR1,
+    ) -> Result<Self, Error> {
+        let cycles_per_ms = clocks.sysclk().0 / 1_000;
+        let rcc = RCC::ptr() as usize;
+        unsafe {
+            modify(rcc + RCC_APB1ENR1, |r| {
+                r | APB1ENR1_PWREN | APB1ENR1_RTCAPBEN
+            });
+            // the backup domain is write protected after reset
+            modify(PWR::ptr() as usize + PWR_CR1, |r| r | PWR_CR1_DBP);
+
+            let bdcr = read_volatile((rcc + RCC_BDCR) as *const u32);
+            let running = bdcr & BDCR_RTCEN != 0 && bdcr & BDCR_RTCSEL_MASK == BDCR_RTCSEL_LSE;
+            if !running {
+                // the clock source can only be changed by a backup domain
+                // reset, which also clears the time
+                modify(rcc + RCC_BDCR, |r| r | BDCR_BDRST);
+                modify(rcc + RCC_BDCR, |r| r & !BDCR_BDRST);
+                modify(rcc + RCC_BDCR, |r| r | BDCR_LSEON);
+                let lse_ready = || read_volatile((rcc + RCC_BDCR) as *const u32) & BDCR_LSERDY != 0;
+                if !wait(cycles_per_ms, LSE_TIMEOUT_MS, lse_ready) {
+                    modify(rcc + RCC_BDCR, |r| r & !BDCR_LSEON);
+                    return Err(Error::LseTimeout);
+                }
+                modify(rcc + RCC_BDCR, |r| r | BDCR_RTCSEL_LSE | BDCR_RTCEN);
+            }
+        }
+
+        let rtc = Rtc {
+            _rtc: rtc,
+            base: RTC::ptr() as usize,
+            cycles_per_ms,
+        };
+        // the shadow registers are invalid until the first sync after reset
+        rtc.wait_for(ISR_RSF)?;
+        Ok(rtc)
+    }
+
+    /// Waits until `flag` is set in the ISR register.
+    fn wait_for(&self, flag: u32) -> Result<(), Error> {
+        if wait(self.cycles_per_ms, SYNC_TIMEOUT_MS, || {
+            self.read(RTC_ISR) & flag != 0
+        }) {
+            Ok(())
+        } else {
+            Err(Error::Timeout)
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// False until the time was set for the first time since the backup
+    /// domain lost power.
+    pub fn is_set(&self) -> bool {
+        self.read(RTC_ISR) & ISR_INITS != 0
+    }
+
+    pub fn now(&self) -> DateTime {
+        // reading TR locks DR until it is read, so both are consistent
+        let tr = self.read(RTC_TR);
+        let dr = self.read(RTC_DR);
+        DateTime {
+            year: 2000 + from_bcd(dr >> 16) as u16,
+            month: from_bcd(dr >> 8 & 0x1f),
+            day: from_bcd(dr & 0x3f),
+            hour: from_bcd(tr >> 16 & 0x3f),
+            minute: from_bcd(tr >> 8 & 0x7f),
+            second: from_bcd(tr & 0x7f),
+        }
+    }
+
+    pub fn set(&mut self, time: &DateTime) -> Result<(), Error> {
+        let year = time.year.max(2000).min(2099) - 2000;
+        let tr = bcd(time.hour) << 16 | bcd(time.minute) << 8 | bcd(time.second);
+        let dr = bcd(year as u8) << 16
+            | (time.weekday() as u32) << 13
+            | bcd(time.month) << 8
+            | bcd(time.day);
+
+        // unlock, see the write protection section of the reference manual
+        self.write(RTC_WPR, 0xca);
+        self.write(RTC_WPR, 0x53);
+        let isr = self.read(RTC_ISR);
+        self.write(RTC_ISR, isr | ISR_INIT);
+        if let Err(e) = self.wait_for(ISR_INITF) {
+            self.write(RTC_ISR, isr & !ISR_INIT);
+            self.write(RTC_WPR, 0xff);
+            return Err(e);
+        }
+
+        self.write(RTC_PRER, PRER_LSE);
+        let cr = self.read(RTC_CR);
+        self.write(RTC_CR, cr & !CR_FMT_AMPM);
+        self.write(RTC_TR, tr);
+        self.write(RTC_DR, dr);
+
+        let isr = self.read(RTC_ISR);
+        self.write(RTC_ISR, isr & !(ISR_INIT | ISR_RSF));
+        self.write(RTC_WPR, 0xff);
+        // wait for the new time to reach the shadow registers
+        self.wait_for(ISR_RSF)
+    }
+}
+
+/// Polls `ready` once per ms, false if it did not become true within
+/// `timeout_ms`.
+fn wait(cycles_per_ms: u32, timeout_ms: u32, mut ready: impl FnMut() -> bool) -> bool {
+    for _ in 0..timeout_ms {
+        if ready() {
+            return true;
+        }
+        cortex_m::asm::delay(cycles_per_ms);
+    }
+    ready()
+}
+
+unsafe fn modify(addr: usize, f: impl FnOnce(u32) -> u32) {
+    let reg = addr as *mut u32;
+    write_volatile(reg, f(read_volatile(reg)));
+}
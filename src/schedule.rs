@@ -0,0 +1,340 @@
+//! Time-of-day rules, so an installation runs on its own.
+//!
+//! A [`Rule`] sets the effect, the palette and/or the brightness at a time of
+//! day. Changes are spread over the fade time of the rule, e.g.
+//!
+//! ```text
+//! 07:00 effect fire, brightness 255, fade 30 min    sunrise
+//! 22:00 brightness 26, fade 10 min                  night mode
+//! 01:00 brightness 0                                off
+//! ```
+//!
+//! The scene is computed from the time of day alone, so after a reset (or
+//! after setting the clock) the installation shows what it would have shown,
+//! halfway through a fade if need be. The [`Scheduler`] only reports changes,
+//! settings made by hand stay until the next rule changes them:
+//!
+//! ```ignore
+//! // once a second
+//! let changes = scheduler.update(rtc.now().seconds_of_day());
+//! if let Some(b) = changes.brightness {
+//!     // all of the LEDs, not only the effect
+//!     brightness = b;
+//! }
+//! ```
+
+const SECONDS_PER_DAY: u32 = 86_400;
+
+/// Marks encoded rules, erased flash (all ones) is not a rule.
+const RULE_TAG: u64 = 0x5c;
+/// `None` of the effect and palette fields.
+const NONE: u64 = 0xff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule {
+    /// minutes since midnight
+    pub at: u16,
+    /// the brightness ramps and the palette crossfades over this many minutes
+    pub fade_minutes: u8,
+    /// index of the effect in the registry
+    pub effect: Option<u8>,
+    /// index into [`color::PALETTES`](crate::color::PALETTES)
+    pub palette: Option<u8>,
+    pub brightness: Option<u8>,
+}
+
+impl Rule {
+    /// Packs the rule into one flash double word.
+    pub fn encode(&self) -> u64 {
+        let brightness = match self.brightness {
+            Some(b) => 1 << 8 | b as u64,
+            None => 0,
+        };
+        self.at as u64
+            | (self.fade_minutes as u64) << 16
+            | self.effect.map_or(NONE, u64::from) << 24
+            | self.palette.map_or(NONE, u64::from) << 32
+            | brightness << 40
+            | RULE_TAG << 56
+    }
+
+    /// `None` if `word` is not an encoded rule.
+    pub fn decode(word: u64) -> Option<Rule> {
+        let byte = |shift: u32| (word >> shift) as u8;
+        let optional = |shift: u32| Some(byte(shift)).filter(|b| *b as u64 != NONE);
+        let at = word as u16;
+        if word >> 56 != RULE_TAG || at as u32 >= SECONDS_PER_DAY / 60 {
+            return None;
+        }
+        Some(Rule {
+            at,
+            fade_minutes: byte(16),
+            effect: optional(24),
+            palette: optional(32),
+            brightness: Some(byte(40)).filter(|_| word >> 48 & 1 != 0),
+        })
+    }
+
+    /// Seconds since the rule started at `seconds` since midnight, the
+    /// rule started yesterday if its time is still to come today.
+    fn elapsed(&self, seconds: u32) -> u32 {
+        (seconds + SECONDS_PER_DAY - self.at as u32 * 60) % SECONDS_PER_DAY
+    }
+}
+
+/// What the rules set at a time of day, or the changes since the last update.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Scene {
+    pub effect: Option<u8>,
+    pub palette: Option<u8>,
+    pub brightness: Option<u8>,
+    /// time left of the transition to the palette, in ms
+    pub palette_fade_ms: u32,
+}
+
+/// Up to `N` rules, sorted by time of day.
+pub struct Scheduler<const N: usize> {
+    rules: [Rule; N],
+    len: usize,
+    /// the scene of the last update, `None` after the rules changed
+    applied: Option<Scene>,
+}
+
+impl<const N: usize> Scheduler<N> {
+    pub const fn new() -> Self {
+        Scheduler {
+            rules: [Rule {
+                at: 0,
+                fade_minutes: 0,
+                effect: None,
+                palette: None,
+                brightness: None,
+            }; N],
+            len: 0,
+            applied: None,
+        }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules[..self.len]
+    }
+
+    /// Adds a rule, replacing the one at the same time. Returns false if
+    /// there is no room left.
+    pub fn add(&mut self, rule: Rule) -> bool {
+        let pos = self.rules().iter().position(|r| r.at >= rule.at);
+        match pos {
+            Some(i) if self.rules[i].at == rule.at => self.rules[i] = rule,
+            _ if self.len == N => return false,
+            _ => {
+                let i = pos.unwrap_or(self.len);
+                self.rules.copy_within(i..self.len, i + 1);
+                self.rules[i] = rule;
+                self.len += 1;
+            }
+        }
+        self.applied = None;
+        true
+    }
+
+    /// Removes the rule at `index` in [`Scheduler::rules`], returns false if
+    /// there is none.
+    pub fn remove(&mut self, index: usize) -> bool {
+        if index >= self.len {
+            return false;
+        }
+        self.rules.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+        self.applied = None;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.applied = None;
+    }
+
+    /// Replaces the rules with the ones encoded in `words`, up to the first
+    /// word that is not a rule. Returns the number of rules.
+    pub fn load(&mut self, words: &[u64]) -> usize {
+        self.clear();
+        for word in words {
+            match Rule::decode(*word) {
+                Some(rule) if self.add(rule) => {}
+                _ => break,
+            }
+        }
+        self.len
+    }
+
+    /// The rule that last set a field at `seconds` since midnight, with the
+    /// value it set. Rules from yesterday count, there is no start of the day.
+    fn latest(&self, seconds: u32, field: impl Fn(&Rule) -> Option<u8>) -> Option<(usize, u8)> {
+        let today = self
+            .rules()
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, r)| r.at as u32 * 60 <= seconds);
+        let yesterday = self.rules().iter().enumerate().rev();
+        today
+            .chain(yesterday)
+            .find_map(|(i, r)| field(r).map(|v| (i, v)))
+    }
+
+    /// The scene at `seconds` since midnight. The brightness ramps from the
+    /// value of the rule before, effects and palettes are crossfaded by the
+    /// caller (the palette over `palette_fade_ms`).
+    pub fn scene_at(&self, seconds: u32) -> Scene {
+        let effect = self.latest(seconds, |r| r.effect).map(|(_, e)| e);
+
+        let palette = self.latest(seconds, |r| r.palette);
+        let palette_fade_ms = palette.map_or(0, |(i, _)| {
+            let rule = &self.rules[i];
+            (rule.fade_minutes as u32 * 60).saturating_sub(rule.elapsed(seconds)) * 1000
+        });
+
+        let brightness = self.latest(seconds, |r| r.brightness).map(|(i, to)| {
+            let rule = &self.rules[i];
+            let fade = rule.fade_minutes as u32 * 60;
+            let elapsed = rule.elapsed(seconds);
+            if elapsed >= fade {
+                return to;
+            }
+            // the rule before that one, or this one again if it is the only one
+            let from = (1..=self.len)
+                .map(|k| &self.rules[(i + self.len - k) % self.len])
+                .find_map(|r| r.brightness)
+                .unwrap_or(to);
+            let ramp = (to as i32 - from as i32) * elapsed as i32 / fade as i32;
+            (from as i32 + ramp) as u8
+        });
+
+        Scene {
+            effect,
+            palette: palette.map(|(_, p)| p),
+            brightness,
+            palette_fade_ms,
+        }
+    }
+
+    /// Returns the fields of the scene at `seconds` since midnight that
+    /// changed since the last update, all of them after the rules changed.
+    pub fn update(&mut self, seconds: u32) -> Scene {
+        let scene = self.scene_at(seconds);
+        let last = self.applied.replace(scene);
+        let changed = |field: fn(&Scene) -> Option<u8>| match last {
+            Some(last) if field(&last) == field(&scene) => None,
+            _ => field(&scene),
+        };
+        Scene {
+            effect: changed(|s| s.effect),
+            palette: changed(|s| s.palette),
+            brightness: changed(|s| s.brightness),
+            palette_fade_ms: scene.palette_fade_ms,
+        }
+    }
+}
+
+impl<const N: usize> Default for Scheduler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(at: u16, fade_minutes: u8, brightness: Option<u8>) -> Rule {
+        Rule {
+            at,
+            fade_minutes,
+            effect: None,
+            palette: None,
+            brightness,
+        }
+    }
+
+    fn at(hour: u32, minute: u32) -> u32 {
+        hour * 3600 + minute * 60
+    }
+
+    #[test]
+    fn rules_survive_encoding() {
+        let rules = [
+            rule(0, 0, None),
+            rule(23 * 60 + 59, 255, Some(0)),
+            Rule {
+                at: 7 * 60,
+                fade_minutes: 30,
+                effect: Some(3),
+                palette: Some(0),
+                brightness: Some(255),
+            },
+        ];
+        for r in &rules {
+            assert_eq!(Rule::decode(r.encode()), Some(*r));
+        }
+        // erased flash and times past midnight are no rules
+        assert_eq!(Rule::decode(u64::MAX), None);
+        assert_eq!(Rule::decode(rule(24 * 60, 0, None).encode()), None);
+    }
+
+    #[test]
+    fn rules_wrap_across_midnight() {
+        let mut scheduler = Scheduler::<4>::new();
+        scheduler.add(Rule {
+            effect: Some(1),
+            ..rule(7 * 60, 0, Some(255))
+        });
+        scheduler.add(Rule {
+            effect: Some(2),
+            ..rule(22 * 60, 0, Some(26))
+        });
+        // the rule of last night still holds in the morning
+        let scene = scheduler.scene_at(at(3, 0));
+        assert_eq!(scene.effect, Some(2));
+        assert_eq!(scene.brightness, Some(26));
+        let scene = scheduler.scene_at(at(12, 0));
+        assert_eq!(scene.effect, Some(1));
+        assert_eq!(scene.brightness, Some(255));
+        assert_eq!(scheduler.scene_at(at(23, 59)).effect, Some(2));
+    }
+
+    #[test]
+    fn brightness_ramps_over_the_fade() {
+        let mut scheduler = Scheduler::<4>::new();
+        scheduler.add(rule(7 * 60, 0, Some(200)));
+        scheduler.add(rule(22 * 60, 10, Some(100)));
+        assert_eq!(scheduler.scene_at(at(22, 0)).brightness, Some(200));
+        assert_eq!(scheduler.scene_at(at(22, 5)).brightness, Some(150));
+        assert_eq!(scheduler.scene_at(at(22, 10)).brightness, Some(100));
+
+        // a fade that started before midnight goes on after it
+        scheduler.clear();
+        scheduler.add(rule(7 * 60, 0, Some(200)));
+        scheduler.add(rule(23 * 60 + 55, 10, Some(100)));
+        assert_eq!(scheduler.scene_at(at(0, 0)).brightness, Some(150));
+    }
+
+    #[test]
+    fn a_single_rule_holds_all_day() {
+        let mut scheduler = Scheduler::<4>::new();
+        scheduler.add(Rule {
+            palette: Some(2),
+            ..rule(12 * 60, 10, Some(80))
+        });
+        for &seconds in &[0, at(11, 59), at(12, 0), at(12, 5), at(23, 59)] {
+            let scene = scheduler.scene_at(seconds);
+            assert_eq!(scene.brightness, Some(80));
+            assert_eq!(scene.palette, Some(2));
+        }
+        // the palette crossfade still takes the fade time
+        assert_eq!(scheduler.scene_at(at(12, 5)).palette_fade_ms, 300_000);
+
+        // and only the first update reports it
+        assert_eq!(scheduler.update(at(13, 0)).brightness, Some(80));
+        assert_eq!(scheduler.update(at(13, 1)).brightness, None);
+    }
+}
@@ -0,0 +1,212 @@
+//! Named sections of the frame that behave like strips of their own.
+//!
+//! A [`Segment`] maps a range of the frame buffer to a virtual strip, so
+//! effects can be run on parts of the panel, each with its own [`Player`]
+//! (and thereby its own effect, palette and brightness). Every segment names
+//! the [`Source`] that draws it by its index, like a
+//! [`Route`](crate::output::Route) names its output. Segments are drawn into
+//! the same frame one after the other, later segments overwrite earlier ones:
+//!
+//! ```ignore
+//! let segments = [Segment::new("panel", 0, 0..291), Segment::zone("top", 1, &zones[0])];
+//! segment::render_all(&segments, &mut [&mut player, &mut ambient], now, frame, scratch);
+//! ```
+//!
+//! [`Player`]: crate::effect::Player
+
+use crate::effect::Player;
+use crate::game::Arcade;
+use crate::power::Zone;
+use core::ops::Range;
+use smart_leds::{SmartLedsWrite, RGB8};
+
+/// Draws the LEDs of a segment.
+pub trait Source {
+    fn render(&mut self, now: u32, leds: &mut [RGB8]);
+}
+
+impl<'a, const N: usize> Source for Player<'a, N> {
+    fn render(&mut self, now: u32, leds: &mut [RGB8]) {
+        Player::render(self, now, leds);
+    }
+}
+
+impl<'a, const N: usize> Source for Arcade<'a, N> {
+    fn render(&mut self, now: u32, leds: &mut [RGB8]) {
+        Arcade::render(self, now, leds);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub name: &'static str,
+    /// index of the source that draws the segment, see [`render_all`]
+    pub source: usize,
+    /// LED indices in the frame
+    pub leds: Range<usize>,
+    /// the first LED of the virtual strip is the last LED of the range
+    pub reversed: bool,
+}
+
+impl Segment {
+    pub const fn new(name: &'static str, source: usize, leds: Range<usize>) -> Self {
+        Segment {
+            name,
+            source,
+            leds,
+            reversed: false,
+        }
+    }
+
+    pub const fn reversed(name: &'static str, source: usize, leds: Range<usize>) -> Self {
+        Segment {
+            name,
+            source,
+            leds,
+            reversed: true,
+        }
+    }
+
+    /// Segment covering a power zone.
+    pub fn zone(name: &'static str, source: usize, zone: &Zone) -> Self {
+        Segment::new(name, source, zone.leds.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.leds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leds.is_empty()
+    }
+
+    /// Part of `frame` covered by the segment, cut off at the end of the frame.
+    fn slice<'a>(&self, frame: &'a mut [RGB8]) -> &'a mut [RGB8] {
+        let end = self.leds.end.min(frame.len());
+        let start = self.leds.start.min(end);
+        &mut frame[start..end]
+    }
+
+    /// Lets `f` draw the segment into `frame` as if it was a strip of its own.
+    ///
+    /// Reversed segments are drawn into `scratch` first, which must be at
+    /// least as long as the segment.
+    pub fn render<F: FnOnce(&mut [RGB8])>(&self, frame: &mut [RGB8], scratch: &mut [RGB8], f: F) {
+        let leds = self.slice(frame);
+        if !self.reversed {
+            f(leds);
+            return;
+        }
+        let scratch = &mut scratch[..leds.len()];
+        f(scratch);
+        for (c, s) in leds.iter_mut().zip(scratch.iter().rev()) {
+            *c = *s;
+        }
+    }
+
+    /// The segment as a `SmartLedsWrite` strip, for code that is written
+    /// against a physical strip.
+    pub fn strip<'a>(&self, frame: &'a mut [RGB8]) -> SegmentStrip<'a> {
+        SegmentStrip {
+            leds: self.slice(frame),
+            reversed: self.reversed,
+        }
+    }
+}
+
+/// Draws every segment with its source, in order.
+///
+/// `scratch` must be as long as the longest reversed segment. Segments with
+/// a source index beyond `sources` are left alone.
+pub fn render_all(
+    segments: &[Segment],
+    sources: &mut [&mut dyn Source],
+    now: u32,
+    frame: &mut [RGB8],
+    scratch: &mut [RGB8],
+) {
+    for segment in segments {
+        if let Some(source) = sources.get_mut(segment.source) {
+            segment.render(frame, scratch, |leds| source.render(now, leds));
+        }
+    }
+}
+
+/// Finds a segment by name.
+pub fn find<'a>(segments: &'a [Segment], name: &str) -> Option<&'a Segment> {
+    segments.iter().find(|s| s.name == name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// more LEDs were written than the segment has, the rest was ignored
+    TooLong,
+}
+
+/// A segment of a frame buffer, see [`Segment::strip`].
+pub struct SegmentStrip<'a> {
+    leds: &'a mut [RGB8],
+    reversed: bool,
+}
+
+impl<'a> SmartLedsWrite for SegmentStrip<'a> {
+    type Error = Error;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Error>
+    where
+        T: Iterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let len = self.leds.len();
+        for (i, c) in iterator.enumerate() {
+            if i >= len {
+                return Err(Error::TooLong);
+            }
+            let index = if self.reversed { len - 1 - i } else { i };
+            self.leds[index] = c.into();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills the LEDs with its own value, counting up from there.
+    struct Ramp(u8);
+
+    impl Source for Ramp {
+        fn render(&mut self, _now: u32, leds: &mut [RGB8]) {
+            for (i, c) in leds.iter_mut().enumerate() {
+                c.r = self.0 + i as u8;
+            }
+        }
+    }
+
+    #[test]
+    fn segments_are_drawn_by_their_source() {
+        let segments = [
+            Segment::new("panel", 0, 0..8),
+            Segment::new("top", 1, 0..2),
+            Segment::reversed("bottom", 2, 6..8),
+            Segment::new("missing", 3, 3..4),
+        ];
+        let mut frame = [RGB8::default(); 8];
+        let mut scratch = [RGB8::default(); 8];
+        let (mut panel, mut top, mut bottom) = (Ramp(0), Ramp(10), Ramp(20));
+        render_all(
+            &segments,
+            &mut [&mut panel, &mut top, &mut bottom],
+            0,
+            &mut frame,
+            &mut scratch,
+        );
+        let mut red = [0; 8];
+        for (r, c) in red.iter_mut().zip(frame.iter()) {
+            *r = c.r;
+        }
+        assert_eq!(red, [10, 11, 2, 3, 4, 5, 21, 20]);
+    }
+}
@@ -0,0 +1,262 @@
+//! Execution time, lateness and jitter statistics of RTIC tasks.
+//!
+//! All values are measured in `CYCCNT` cycles, which `init` has to enable.
+//! The tasks are scheduled by [`Tim2Monotonic`], its instants and durations
+//! are converted into cycles:
+//!
+//! ```ignore
+//! // in init
+//! cx.core.DCB.enable_trace();
+//! cx.core.DWT.enable_cycle_counter();
+//!
+//! #[task(schedule = [refresh], resources = [refresh_stats, cpu_load])]
+//! fn refresh(cx: refresh::Context) {
+//!     let probe = cx.resources.refresh_stats.begin(cx.scheduled);
+//!     // ... the actual work
+//!     let busy = cx.resources.refresh_stats.end(probe, PERIOD);
+//!     cx.resources.cpu_load.add_busy(busy);
+//! }
+//! ```
+//!
+//! Only [`MinMax`] builds without the `device` feature.
+
+#[cfg(feature = "device")]
+use crate::monotonic::{Duration, Instant, Tim2Monotonic};
+#[cfg(feature = "device")]
+use core::fmt;
+#[cfg(feature = "device")]
+use cortex_m::peripheral::DWT;
+#[cfg(feature = "device")]
+use rtic::Monotonic;
+
+/// Minimum, average and maximum of a series of values.
+#[derive(Clone, Copy, Debug)]
+pub struct MinMax {
+    min: u32,
+    max: u32,
+    sum: u64,
+    count: u32,
+}
+
+impl Default for MinMax {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MinMax {
+    pub const fn new() -> Self {
+        MinMax {
+            min: u32::MAX,
+            max: 0,
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, v: u32) {
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v as u64;
+        self.count += 1;
+    }
+
+    pub fn min(&self) -> u32 {
+        if self.count == 0 {
+            0
+        } else {
+            self.min
+        }
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    pub fn avg(&self) -> u32 {
+        if self.count == 0 {
+            0
+        } else {
+            (self.sum / self.count as u64) as u32
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+/// Start of a measured task run, returned by [`TaskStats::begin`].
+#[cfg(feature = "device")]
+pub struct Probe {
+    start: u32,
+    lateness: u32,
+}
+
+/// Statistics of a periodic task.
+#[cfg(feature = "device")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskStats {
+    /// execution time
+    pub exec: MinMax,
+    /// delay between `cx.scheduled` and the actual start
+    pub lateness: MinMax,
+    /// deviation of the time between two starts from the period
+    pub jitter: MinMax,
+    /// runs that did not finish before the next run was due
+    pub overruns: u32,
+    last_start: Option<u32>,
+}
+
+#[cfg(feature = "device")]
+impl TaskStats {
+    /// No runs yet, usable in `#[init(..)]`.
+    pub const fn new() -> Self {
+        TaskStats {
+            exec: MinMax::new(),
+            lateness: MinMax::new(),
+            jitter: MinMax::new(),
+            overruns: 0,
+            last_start: None,
+        }
+    }
+
+    /// Must be called first thing in the task.
+    pub fn begin(&mut self, scheduled: Instant) -> Probe {
+        let start = DWT::cycle_count();
+        // zero if the task started early, only as fine as the monotonic
+        let lateness = cycles(Tim2Monotonic::now() - scheduled);
+        Probe { start, lateness }
+    }
+
+    /// Must be called at the end of the task, `period` is the time until the
+    /// next run is due. Returns the execution time.
+    pub fn end(&mut self, probe: Probe, period: Duration) -> u32 {
+        let exec = DWT::cycle_count().wrapping_sub(probe.start);
+        let period = cycles(period);
+
+        self.exec.add(exec);
+        self.lateness.add(probe.lateness);
+        if probe.lateness + exec > period {
+            self.overruns += 1;
+        }
+        if let Some(last) = self.last_start {
+            // CYCCNT wraps after a minute, far beyond any task period
+            let interval = probe.start.wrapping_sub(last);
+            let jitter = if interval > period {
+                interval - period
+            } else {
+                period - interval
+            };
+            self.jitter.add(jitter);
+        }
+        self.last_start = Some(probe.start);
+        exec
+    }
+
+    /// Writes a one line summary, times in µs.
+    pub fn write_summary<W: fmt::Write>(
+        &self,
+        w: &mut W,
+        name: &str,
+        cycles_per_us: u32,
+    ) -> fmt::Result {
+        let us = |c: u32| c / cycles_per_us.max(1);
+        write!(
+            w,
+            "{}: exec {}/{}/{} late {}/{}/{} jitter {}/{}/{} overruns {}",
+            name,
+            us(self.exec.min()),
+            us(self.exec.avg()),
+            us(self.exec.max()),
+            us(self.lateness.min()),
+            us(self.lateness.avg()),
+            us(self.lateness.max()),
+            us(self.jitter.min()),
+            us(self.jitter.avg()),
+            us(self.jitter.max()),
+            self.overruns
+        )
+    }
+}
+
+/// Estimates the CPU load from the execution times of the measured tasks.
+#[cfg(feature = "device")]
+pub struct CpuLoad {
+    window: u32,
+    window_start: Option<u32>,
+    busy: u32,
+    permille: u16,
+}
+
+#[cfg(feature = "device")]
+impl CpuLoad {
+    /// The load is averaged over `window`.
+    pub fn new(window: Duration) -> Self {
+        CpuLoad {
+            window: cycles(window),
+            window_start: None,
+            busy: 0,
+            permille: 0,
+        }
+    }
+
+    /// Accounts `cycles` of work.
+    pub fn add_busy(&mut self, cycles: u32) {
+        self.busy = self.busy.saturating_add(cycles);
+
+        let now = DWT::cycle_count();
+        match self.window_start {
+            None => self.window_start = Some(now),
+            Some(start) => {
+                let elapsed = now.wrapping_sub(start);
+                if elapsed >= self.window {
+                    self.permille = (self.busy as u64 * 1000 / elapsed as u64).min(1000) as u16;
+                    self.busy = 0;
+                    self.window_start = Some(now);
+                }
+            }
+        }
+    }
+
+    /// Load of the last full window in 1/1000.
+    pub fn permille(&self) -> u16 {
+        self.permille
+    }
+}
+
+/// `d` in core cycles, saturated at about a minute.
+#[cfg(feature = "device")]
+pub fn cycles(d: Duration) -> u32 {
+    let ratio = Tim2Monotonic::ratio();
+    let cycles = d.ticks() * ratio.numerator as u64 / ratio.denominator as u64;
+    cycles.min(u32::MAX as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_max_without_values_is_zero() {
+        let m = MinMax::new();
+        assert_eq!((m.min(), m.avg(), m.max(), m.count()), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn min_max_tracks_the_series() {
+        let mut m = MinMax::default();
+        for v in [30, 10, 50, 30].iter() {
+            m.add(*v);
+        }
+        assert_eq!((m.min(), m.avg(), m.max(), m.count()), (10, 30, 50, 4));
+    }
+
+    #[test]
+    fn min_max_averages_without_overflow() {
+        let mut m = MinMax::new();
+        m.add(u32::MAX);
+        m.add(u32::MAX - 2);
+        assert_eq!(m.avg(), u32::MAX - 1);
+    }
+}
@@ -0,0 +1,62 @@
+//! Conversion of real time units into `CYCCNT` durations.
+//!
+//! `CYCCNT` counts core clock cycles, so every period depends on the clock
+//! configuration. A [`Timebase`] is created from the frozen `Clocks` and
+//! keeps task periods correct when the clock config changes.
+
+use rtic::cyccnt::{Duration, U32Ext};
+use stm32l4xx_hal::rcc::Clocks;
+
+/// Converts ms, µs and frequencies into `CYCCNT` durations.
+///
+/// Note that `CYCCNT` can only schedule about 2³¹ cycles ahead (33 s at
+/// 64 MHz).
+#[derive(Clone, Copy, Debug)]
+pub struct Timebase {
+    hz: u32,
+}
+
+impl Timebase {
+    pub fn new(clocks: Clocks) -> Self {
+        Self::from_hz(clocks.sysclk().0)
+    }
+
+    /// Timebase for a core running at `hz`.
+    pub const fn from_hz(hz: u32) -> Self {
+        Timebase { hz }
+    }
+
+    /// Core clock frequency.
+    pub fn hz(&self) -> u32 {
+        self.hz
+    }
+
+    pub fn ticks_per_ms(&self) -> u32 {
+        self.hz / 1_000
+    }
+
+    /// Panics if `ms` is more than 2³¹ cycles.
+    pub fn ms(&self, ms: u32) -> Duration {
+        cycles(self.hz as u64 * ms as u64 / 1_000)
+    }
+
+    /// Panics if `us` is more than 2³¹ cycles.
+    pub fn us(&self, us: u32) -> Duration {
+        cycles(self.hz as u64 * us as u64 / 1_000_000)
+    }
+
+    /// Period of a task that runs `freq` times per second.
+    pub fn period(&self, freq: u32) -> Duration {
+        assert!(freq > 0, "a period needs a frequency of at least 1 Hz");
+        (self.hz / freq).cycles()
+    }
+}
+
+fn cycles(cycles: u64) -> Duration {
+    // longer durations would be scheduled in the past
+    assert!(
+        cycles <= i32::MAX as u64,
+        "duration exceeds the 2^31 cycles CYCCNT can schedule ahead"
+    );
+    (cycles as u32).cycles()
+}
@@ -0,0 +1,479 @@
+//! Keyframe animation and cue lists, for scripted shows.
+//!
+//! A [`Track`] moves a value through [`Key`]s, easing into each key along a
+//! curve. A [`Show`] animates the [`Params`](crate::effect::Params) of a
+//! [`Player`] with tracks and switches effects at the times of its cue list,
+//! a [`Timeline`] plays it:
+//!
+//! ```ignore
+//! const SUNRISE: Show = Show {
+//!     tracks: &[(
+//!         Target::Brightness,
+//!         Track::new(&[Key::new(0, 0, Easing::Linear), Key::new(8_000, 255, Easing::SineOut)]),
+//!     )],
+//!     cues: &[Cue::new(0, Action::Switch("fire", 0)), Cue::new(20_000, Action::Trigger)],
+//!     length_ms: 30_000,
+//!     repeat: false,
+//! };
+//!
+//! let mut timeline = Timeline::new(&SUNRISE, now);
+//! // every frame, before rendering
+//! timeline.update(now, &mut player);
+//! ```
+//!
+//! Times are in ms, the easing is computed in 16.16 fixed point.
+
+use crate::{
+    color,
+    effect::{Control, Player},
+};
+
+/// 1.0 in 16.16 fixed point.
+pub const ONE: u32 = 1 << 16;
+
+/// How a value moves from one key to the next. `In` curves start slowly,
+/// `Out` curves end slowly, `InOut` curves do both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+    /// keeps the value of the key before, then jumps
+    Hold,
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    BounceIn,
+    /// drops like a ball onto the next value
+    BounceOut,
+}
+
+impl Easing {
+    /// The curve at `t`, both from 0 to [`ONE`].
+    pub fn apply(self, t: u32) -> u32 {
+        let t = t.min(ONE);
+        match self {
+            Easing::Hold => {
+                if t == ONE {
+                    ONE
+                } else {
+                    0
+                }
+            }
+            Easing::Linear => t,
+            Easing::QuadIn => quad_in(t),
+            Easing::QuadOut => out(quad_in, t),
+            Easing::QuadInOut => in_out(quad_in, t),
+            Easing::CubicIn => cubic_in(t),
+            Easing::CubicOut => out(cubic_in, t),
+            Easing::CubicInOut => in_out(cubic_in, t),
+            Easing::SineIn => sine_in(t),
+            Easing::SineOut => out(sine_in, t),
+            Easing::SineInOut => in_out(sine_in, t),
+            Easing::BounceIn => out(bounce_out, t),
+            Easing::BounceOut => bounce_out(t),
+        }
+    }
+}
+
+fn mul(a: u32, b: u32) -> u32 {
+    ((a as u64 * b as u64) >> 16) as u32
+}
+
+fn quad_in(t: u32) -> u32 {
+    mul(t, t)
+}
+
+fn cubic_in(t: u32) -> u32 {
+    mul(mul(t, t), t)
+}
+
+/// 1 - cos(t * pi / 2), i.e. sin((1 - t) * pi / 2) mirrored.
+fn sine_in(t: u32) -> u32 {
+    ONE - sin_quarter(ONE - t)
+}
+
+/// sin(t * pi / 2) by an odd polynomial, off by about 1/10000.
+fn sin_quarter(t: u32) -> u32 {
+    // 1.570243, 0.641711 and 0.071468 in 16.16, fitted to the least largest
+    // error with exactly 1.0 at t = 1
+    const A: i64 = 102_907;
+    const B: i64 = 42_055;
+    const C: i64 = 4_684;
+    let t = t as i64;
+    let t2 = (t * t) >> 16;
+    let inner = B - ((C * t2) >> 16);
+    let v = (t * (A - ((t2 * inner) >> 16))) >> 16;
+    v.max(0).min(ONE as i64) as u32
+}
+
+/// The classic bounce with three bounces of 1/4, 1/16 and 1/64 height.
+fn bounce_out(t: u32) -> u32 {
+    // 7.5625 * (t - offset)^2 + base, in elevenths of the duration
+    let parabola = |offset: u32, base: u32| {
+        let d = t.wrapping_sub(offset) as i32 as i64;
+        ((121 * d * d) >> 20) as u32 + base
+    };
+    if t < ONE * 4 / 11 {
+        parabola(0, 0)
+    } else if t < ONE * 8 / 11 {
+        parabola(ONE * 6 / 11, ONE * 3 / 4)
+    } else if t < ONE * 10 / 11 {
+        parabola(ONE * 9 / 11, ONE * 15 / 16)
+    } else {
+        parabola(ONE * 21 / 22, ONE * 63 / 64).min(ONE)
+    }
+}
+
+/// The `In` curve `f` played backwards.
+fn out(f: fn(u32) -> u32, t: u32) -> u32 {
+    ONE - f(ONE - t)
+}
+
+/// The `In` curve `f` over the first half, then backwards.
+fn in_out(f: fn(u32) -> u32, t: u32) -> u32 {
+    if t < ONE / 2 {
+        f(t * 2) / 2
+    } else {
+        ONE - f((ONE - t) * 2) / 2
+    }
+}
+
+/// A value at a point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    pub at: u32,
+    pub value: i32,
+    /// how the value gets here from the key before
+    pub easing: Easing,
+}
+
+impl Key {
+    pub const fn new(at: u32, value: i32, easing: Easing) -> Self {
+        Key { at, value, easing }
+    }
+}
+
+/// Keys sorted by time.
+#[derive(Clone, Copy, Debug)]
+pub struct Track<'a> {
+    pub keys: &'a [Key],
+}
+
+impl<'a> Track<'a> {
+    pub const fn new(keys: &'a [Key]) -> Self {
+        Track { keys }
+    }
+
+    /// Time of the last key.
+    pub fn duration(&self) -> u32 {
+        self.keys.last().map_or(0, |k| k.at)
+    }
+
+    /// The value at `t`, that of the first or last key outside of them.
+    /// `None` without keys.
+    pub fn value_at(&self, t: u32) -> Option<i32> {
+        let next = self.keys.iter().position(|k| k.at > t);
+        let (from, to) = match next {
+            None => return self.keys.last().map(|k| k.value),
+            Some(0) => return Some(self.keys[0].value),
+            Some(i) => (&self.keys[i - 1], &self.keys[i]),
+        };
+        let progress = ((t - from.at) as u64 * ONE as u64 / (to.at - from.at) as u64) as u32;
+        let eased = to.easing.apply(progress) as i64;
+        let change = ((to.value as i64 - from.value as i64) * eased) >> 16;
+        Some(from.value + change as i32)
+    }
+}
+
+/// What a [`Track`] of a [`Show`] animates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// 0 to 255
+    Brightness,
+    /// 256 is normal speed
+    Speed,
+    /// 256 is once along the palette, see
+    /// [`Params::palette_offset`](crate::effect::Params::palette_offset)
+    PalettePosition,
+    /// 256 is once around the color wheel, see
+    /// [`Params::hue_offset`](crate::effect::Params::hue_offset)
+    HueOffset,
+}
+
+/// Something that happens at a point in a [`Show`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action<'a> {
+    /// switches to the effect of this name, crossfading over the given ms
+    Switch(&'a str, u32),
+    /// changes to the palette of this name, crossfading over the given ms
+    Palette(&'a str, u32),
+    /// passes a trigger to the effect, like a button press
+    Trigger,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cue<'a> {
+    pub at: u32,
+    pub action: Action<'a>,
+}
+
+impl<'a> Cue<'a> {
+    pub const fn new(at: u32, action: Action<'a>) -> Self {
+        Cue { at, action }
+    }
+}
+
+/// A script for the [`Player`].
+#[derive(Clone, Copy, Debug)]
+pub struct Show<'a> {
+    pub tracks: &'a [(Target, Track<'a>)],
+    /// sorted by time
+    pub cues: &'a [Cue<'a>],
+    pub length_ms: u32,
+    /// starts over after `length_ms`, otherwise the last values stay
+    pub repeat: bool,
+}
+
+/// Plays a [`Show`].
+pub struct Timeline<'a> {
+    show: &'a Show<'a>,
+    start: u32,
+    /// position of the last update, `None` before the first
+    last: Option<u32>,
+}
+
+impl<'a> Timeline<'a> {
+    /// Starts `show` at `now`.
+    pub fn new(show: &'a Show<'a>, now: u32) -> Self {
+        Timeline {
+            show,
+            start: now,
+            last: None,
+        }
+    }
+
+    pub fn show(&self) -> &'a Show<'a> {
+        self.show
+    }
+
+    /// Time into the show at `now`.
+    pub fn position(&self, now: u32) -> u32 {
+        let elapsed = now.wrapping_sub(self.start);
+        if self.show.repeat && self.show.length_ms > 0 {
+            elapsed % self.show.length_ms
+        } else {
+            elapsed.min(self.show.length_ms)
+        }
+    }
+
+    /// True once a show that doesn't repeat has reached its end.
+    pub fn is_over(&self, now: u32) -> bool {
+        !self.show.repeat && now.wrapping_sub(self.start) >= self.show.length_ms
+    }
+
+    /// Sets the parameters of `player` for `now` and carries out the cues
+    /// since the last update.
+    pub fn update<const N: usize>(&mut self, now: u32, player: &mut Player<'_, N>) {
+        let t = self.position(now);
+        for (target, track) in self.show.tracks {
+            let value = match track.value_at(t) {
+                Some(v) => v,
+                None => continue,
+            };
+            let params = &mut player.params;
+            match target {
+                Target::Brightness => params.brightness = value.clamp(0, 255) as u8,
+                Target::Speed => params.speed = value.clamp(0, u16::MAX as i32) as u16,
+                Target::PalettePosition => params.palette_offset = value as u8,
+                Target::HueOffset => params.hue_offset = value as u8,
+            }
+        }
+
+        // the cues after the last update up to t, across the end of the show
+        let due = |cue: &&Cue| match self.last {
+            None => cue.at <= t,
+            Some(last) if last <= t => cue.at > last && cue.at <= t,
+            Some(last) => cue.at > last || cue.at <= t,
+        };
+        for cue in self.show.cues.iter().filter(due) {
+            match cue.action {
+                Action::Switch(name, fade_ms) => {
+                    player.switch_by_name(name, now, fade_ms);
+                }
+                Action::Palette(name, fade_ms) => {
+                    if let Some(palette) = color::find_palette(name) {
+                        player.fade_palette(palette, now, fade_ms);
+                    }
+                }
+                Action::Trigger => {
+                    player.control(Control::Trigger);
+                }
+            }
+        }
+        self.last = Some(t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effect::{Effect, Params, Registry};
+    use smart_leds::RGB8;
+
+    const CURVES: [Easing; 13] = [
+        Easing::Hold,
+        Easing::Linear,
+        Easing::QuadIn,
+        Easing::QuadOut,
+        Easing::QuadInOut,
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::SineIn,
+        Easing::SineOut,
+        Easing::SineInOut,
+        Easing::BounceIn,
+        Easing::BounceOut,
+    ];
+
+    /// Plain red, counts the triggers.
+    #[derive(Default)]
+    struct Red {
+        triggers: u32,
+    }
+
+    impl Effect for Red {
+        fn render(&mut self, _t: u32, _params: &Params, frame: &mut [RGB8]) {
+            for c in frame.iter_mut() {
+                *c = RGB8 { r: 255, g: 0, b: 0 };
+            }
+        }
+
+        fn control(&mut self, control: Control) -> bool {
+            if control == Control::Trigger {
+                self.triggers += 1;
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn curves_run_from_zero_to_one() {
+        for easing in CURVES.iter() {
+            assert_eq!(easing.apply(0), 0, "{:?}", easing);
+            assert_eq!(easing.apply(ONE), ONE, "{:?}", easing);
+        }
+        assert_eq!(sin_quarter(0), 0);
+        assert_eq!(sin_quarter(ONE), ONE);
+        assert_eq!(bounce_out(0), 0);
+        assert_eq!(bounce_out(ONE), ONE);
+        assert_eq!(in_out(quad_in, 0), 0);
+        assert_eq!(in_out(quad_in, ONE), ONE);
+    }
+
+    #[test]
+    fn curves_without_bounces_never_go_back() {
+        for easing in CURVES.iter() {
+            if matches!(easing, Easing::BounceIn | Easing::BounceOut) {
+                continue;
+            }
+            let mut last = 0;
+            for t in (0..=ONE).step_by(97) {
+                let v = easing.apply(t);
+                assert!(v >= last, "{:?} at {}", easing, t);
+                last = v;
+            }
+        }
+    }
+
+    #[test]
+    fn sin_quarter_is_close_to_sin() {
+        // sin(pi / 6) = 1/2 and sin(pi / 4) = sqrt(1/2)
+        let half = sin_quarter(ONE / 3) as i64;
+        assert!((half - (ONE / 2) as i64).abs() < 16, "{}", half);
+        let diagonal = sin_quarter(ONE / 2) as i64;
+        assert!((diagonal - 46_341).abs() < 16, "{}", diagonal);
+    }
+
+    #[test]
+    fn bounces_stay_in_range() {
+        for t in (0..=ONE).step_by(97) {
+            assert!(bounce_out(t) <= ONE, "{}", t);
+        }
+        // touches the floor between the bounces
+        assert!(bounce_out(ONE * 4 / 11) > ONE - ONE / 64);
+        assert!(bounce_out(ONE * 8 / 11) > ONE - ONE / 64);
+    }
+
+    #[test]
+    fn cues_fire_across_the_wrap() {
+        static SHOW: Show = Show {
+            tracks: &[],
+            cues: &[
+                Cue::new(100, Action::Trigger),
+                Cue::new(900, Action::Trigger),
+            ],
+            length_ms: 1_000,
+            repeat: true,
+        };
+        let mut red = Red::default();
+        let mut scratch = [color::BLACK; 1];
+        let mut player = Player::new(Registry::new([("red", &mut red)]), &mut scratch);
+        let mut timeline = Timeline::new(&SHOW, 0);
+
+        // 100 and 900 in the first round
+        timeline.update(50, &mut player);
+        timeline.update(850, &mut player);
+        timeline.update(950, &mut player);
+        // 100 after the end, once
+        timeline.update(1_150, &mut player);
+        timeline.update(1_200, &mut player);
+        // 900, then nothing across the end
+        timeline.update(1_950, &mut player);
+        timeline.update(2_050, &mut player);
+        assert_eq!(red.triggers, 4);
+    }
+
+    #[test]
+    fn hue_offset_turns_the_colors() {
+        static SHOW: Show = Show {
+            tracks: &[(
+                Target::HueOffset,
+                Track::new(&[
+                    Key::new(0, 0, Easing::Linear),
+                    Key::new(300, 256, Easing::Linear),
+                ]),
+            )],
+            cues: &[],
+            length_ms: 300,
+            repeat: false,
+        };
+        let mut red = Red::default();
+        let mut scratch = [color::BLACK; 1];
+        let mut player = Player::new(Registry::new([("red", &mut red)]), &mut scratch);
+        let mut timeline = Timeline::new(&SHOW, 0);
+        let mut frame = [color::BLACK; 1];
+
+        // a third of the way around the wheel is green
+        timeline.update(100, &mut player);
+        assert_eq!(player.params.hue_offset, 85);
+        assert_eq!(player.params.palette_offset, 0);
+        player.render(100, &mut frame);
+        assert!(
+            frame[0].g > 250 && frame[0].r < 8 && frame[0].b == 0,
+            "{:?}",
+            frame[0]
+        );
+
+        // the whole way around is red again
+        timeline.update(300, &mut player);
+        assert_eq!(player.params.hue_offset, 0);
+        player.render(300, &mut frame);
+        assert_eq!(frame[0], RGB8 { r: 255, g: 0, b: 0 });
+    }
+}
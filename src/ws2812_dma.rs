@@ -0,0 +1,319 @@
+//! WS2812 output over SPI, sent by DMA.
+//!
+//! The frame is encoded into the SPI bit pattern used by `ws2812_spi` (3 MHz,
+//! four SPI bits per WS2812 bit) and then sent by DMA, so the CPU is free
+//! while the frame goes out. Two buffers are used: one is read by the DMA
+//! while the next frame is encoded into the other.
+
+use crate::output::StripOutput;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{compiler_fence, Ordering};
+use smart_leds::{SmartLedsWrite, RGB8};
+use stm32l4xx_hal::{
+    rcc::AHB1,
+    stm32::{DMA1, DMA2, RCC, SPI1, SPI2, SPI3},
+};
+
+/// SPI bytes per LED, 3 colors times 4 bytes
+pub const BYTES_PER_LED: usize = 12;
+
+/// Low bytes after each frame, 293 µs at 3 MHz. Current WS2812B revisions
+/// only latch after 280 µs of low, and the frames are sent back to back.
+pub const RESET_BYTES: usize = 110;
+
+/// Size of a DMA buffer for `leds` LEDs.
+pub const fn buffer_len(leds: usize) -> usize {
+    leds * BYTES_PER_LED + RESET_BYTES
+}
+
+// two WS2812 bits per SPI byte, high time first, then the data bit
+const PATTERNS: [u8; 4] = [0b1000_1000, 0b1000_1110, 0b1110_1000, 0b1110_1110];
+
+fn encode_byte(mut data: u8, out: &mut [u8]) {
+    for o in out[..4].iter_mut() {
+        *o = PATTERNS[(data >> 6) as usize];
+        data <<= 2;
+    }
+}
+
+/// Encodes the LEDs in `frame` into `buf` and returns the number of bytes
+/// used, including the reset bytes. LEDs that do not fit are ignored, a
+/// buffer without room for the reset bytes is an error.
+pub fn encode<I: IntoIterator<Item = RGB8>>(frame: I, buf: &mut [u8]) -> Result<usize, Error> {
+    if buf.len() < RESET_BYTES {
+        return Err(Error::BufferTooShort);
+    }
+    let leds = (buf.len() - RESET_BYTES) / BYTES_PER_LED;
+    let mut len = 0;
+    for c in frame.into_iter().take(leds) {
+        // WS2812 expects GRB
+        encode_byte(c.g, &mut buf[len..]);
+        encode_byte(c.r, &mut buf[len + 4..]);
+        encode_byte(c.b, &mut buf[len + 8..]);
+        len += BYTES_PER_LED;
+    }
+    for b in buf[len..len + RESET_BYTES].iter_mut() {
+        *b = 0;
+    }
+    Ok(len + RESET_BYTES)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// the frame does not fit into the buffers
+    TooLong,
+    /// a buffer can't even hold the reset bytes, see [`buffer_len`]
+    BufferTooShort,
+}
+
+// register offsets
+const DMA_IFCR: usize = 0x04;
+const DMA_CSELR: usize = 0xa8;
+const DMA_CCR: usize = 0x00;
+const DMA_CNDTR: usize = 0x04;
+const DMA_CPAR: usize = 0x08;
+const DMA_CMAR: usize = 0x0c;
+const SPI_CR2: usize = 0x04;
+const SPI_DR: usize = 0x0c;
+const RCC_AHB1ENR: usize = 0x48;
+
+// DMA_CCR bits
+const CCR_EN: u32 = 1 << 0;
+const CCR_TCIE: u32 = 1 << 1;
+const CCR_TEIE: u32 = 1 << 3;
+const CCR_DIR_FROM_MEMORY: u32 = 1 << 4;
+const CCR_MINC: u32 = 1 << 7;
+const CCR_PL_HIGH: u32 = 0b10 << 12;
+
+const SPI_CR2_TXDMAEN: u32 = 1 << 1;
+
+/// A DMA channel that serves the TX request of a SPI peripheral.
+#[derive(Clone, Copy, Debug)]
+pub struct SpiTxDma {
+    dma: usize,
+    /// channel number, starting at 1 like in the reference manual
+    channel: usize,
+    request: u32,
+    spi: usize,
+}
+
+impl SpiTxDma {
+    /// DMA1 channel 3
+    pub fn spi1(ahb1: &mut AHB1) -> Self {
+        Self::enable(ahb1, 0);
+        SpiTxDma {
+            dma: DMA1::ptr() as usize,
+            channel: 3,
+            request: 1,
+            spi: SPI1::ptr() as usize,
+        }
+    }
+
+    /// DMA1 channel 5
+    pub fn spi2(ahb1: &mut AHB1) -> Self {
+        Self::enable(ahb1, 0);
+        SpiTxDma {
+            dma: DMA1::ptr() as usize,
+            channel: 5,
+            request: 1,
+            spi: SPI2::ptr() as usize,
+        }
+    }
+
+    /// DMA2 channel 2
+    pub fn spi3(ahb1: &mut AHB1) -> Self {
+        Self::enable(ahb1, 1);
+        SpiTxDma {
+            dma: DMA2::ptr() as usize,
+            channel: 2,
+            request: 3,
+            spi: SPI3::ptr() as usize,
+        }
+    }
+
+    /// Enables the clock of DMA1 (0) or DMA2 (1).
+    fn enable(_ahb1: &mut AHB1, dma: u32) {
+        // AHB1 is only taken to prove that nobody else is configuring it
+        unsafe { modify(RCC::ptr() as usize + RCC_AHB1ENR, |r| r | 1 << dma) };
+    }
+
+    fn channel_reg(&self, offset: usize) -> usize {
+        self.dma + 0x08 + 0x14 * (self.channel - 1) + offset
+    }
+
+    /// Flag bits of the channel in ISR / IFCR.
+    fn flags(&self) -> u32 {
+        0xf << (4 * (self.channel - 1))
+    }
+
+    fn configure(&self) {
+        unsafe {
+            write_volatile(self.channel_reg(DMA_CCR) as *mut u32, 0);
+            write_volatile(
+                self.channel_reg(DMA_CPAR) as *mut u32,
+                (self.spi + SPI_DR) as u32,
+            );
+            let shift = 4 * (self.channel - 1);
+            modify(self.dma + DMA_CSELR, |r| {
+                r & !(0xf << shift) | self.request << shift
+            });
+            modify(self.spi + SPI_CR2, |r| r | SPI_CR2_TXDMAEN);
+        }
+    }
+
+    fn start(&self, buf: &[u8]) {
+        // the buffer must be written before the DMA reads it
+        compiler_fence(Ordering::Release);
+        unsafe {
+            write_volatile(self.channel_reg(DMA_CCR) as *mut u32, 0);
+            write_volatile((self.dma + DMA_IFCR) as *mut u32, self.flags());
+            write_volatile(self.channel_reg(DMA_CMAR) as *mut u32, buf.as_ptr() as u32);
+            write_volatile(self.channel_reg(DMA_CNDTR) as *mut u32, buf.len() as u32);
+            write_volatile(
+                self.channel_reg(DMA_CCR) as *mut u32,
+                CCR_MINC | CCR_DIR_FROM_MEMORY | CCR_TCIE | CCR_TEIE | CCR_PL_HIGH | CCR_EN,
+            );
+        }
+    }
+
+    /// Clears the interrupt flags and disables the channel.
+    fn finish(&self) {
+        unsafe {
+            write_volatile(self.channel_reg(DMA_CCR) as *mut u32, 0);
+            write_volatile((self.dma + DMA_IFCR) as *mut u32, self.flags());
+        }
+        compiler_fence(Ordering::Acquire);
+    }
+}
+
+unsafe fn modify(addr: usize, f: impl FnOnce(u32) -> u32) {
+    let reg = addr as *mut u32;
+    write_volatile(reg, f(read_volatile(reg)));
+}
+
+/// Double buffered WS2812 driver, see the [module documentation](self).
+///
+/// [`Ws2812Dma::on_interrupt`] must be called from the interrupt of the DMA
+/// channel.
+pub struct Ws2812Dma<SPI> {
+    _spi: SPI,
+    dma: SpiTxDma,
+    buffers: [&'static mut [u8]; 2],
+    lens: [usize; 2],
+    /// buffer that is currently read by the DMA
+    active: Option<usize>,
+    /// encoded frame that waits for the DMA
+    pending: Option<usize>,
+}
+
+impl<SPI> Ws2812Dma<SPI> {
+    /// `spi` must be configured for 3 MHz and `ws2812_spi::MODE`, it is only
+    /// kept to make sure nobody else uses it. Both buffers should hold
+    /// [`buffer_len`] bytes, panics if they can't hold the reset bytes.
+    pub fn new(
+        spi: SPI,
+        dma: SpiTxDma,
+        buffer0: &'static mut [u8],
+        buffer1: &'static mut [u8],
+    ) -> Self {
+        assert!(
+            buffer0.len() >= buffer_len(0) && buffer1.len() >= buffer_len(0),
+            "the buffers need room for the reset bytes, see buffer_len"
+        );
+        dma.configure();
+        Ws2812Dma {
+            _spi: spi,
+            dma,
+            buffers: [buffer0, buffer1],
+            lens: [0; 2],
+            active: None,
+            pending: None,
+        }
+    }
+
+    /// A transfer is running.
+    pub fn is_busy(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Encodes a frame and sends it as soon as the DMA is free.
+    ///
+    /// If the previous frame is still waiting it is replaced.
+    pub fn send<I: IntoIterator<Item = RGB8>>(&mut self, frame: I) {
+        self.prepare(frame);
+        if self.active.is_none() {
+            self.start_pending();
+        }
+    }
+
+    /// Encodes a frame into the buffer that is not read by the DMA, without
+    /// starting the transfer.
+    pub fn prepare<I: IntoIterator<Item = RGB8>>(&mut self, frame: I) {
+        let back = match self.active {
+            Some(0) => 1,
+            _ => 0,
+        };
+        // new() made sure that the buffers hold the reset bytes
+        if let Ok(len) = encode(frame, self.buffers[back]) {
+            self.lens[back] = len;
+            self.pending = Some(back);
+        }
+    }
+
+    /// Starts the next frame, if there is one.
+    pub fn on_interrupt(&mut self) {
+        self.dma.finish();
+        self.active = None;
+        self.start_pending();
+    }
+
+    fn start_pending(&mut self) {
+        if let Some(next) = self.pending.take() {
+            self.dma.start(&self.buffers[next][..self.lens[next]]);
+            self.active = Some(next);
+        }
+    }
+}
+
+impl<SPI> SmartLedsWrite for Ws2812Dma<SPI> {
+    type Error = Error;
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Error>
+    where
+        T: Iterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        let back = match self.active {
+            Some(0) => 1,
+            _ => 0,
+        };
+        let leds = (self.buffers[back].len().saturating_sub(RESET_BYTES)) / BYTES_PER_LED;
+        let mut iterator = iterator.map(|c| c.into()).peekable();
+        self.send(iterator.by_ref().take(leds));
+        if iterator.peek().is_some() {
+            return Err(Error::TooLong);
+        }
+        Ok(())
+    }
+}
+
+impl<SPI> StripOutput for Ws2812Dma<SPI> {
+    fn prepare(&mut self, leds: &mut dyn Iterator<Item = RGB8>) {
+        Ws2812Dma::prepare(self, leds);
+    }
+
+    fn start(&mut self) {
+        if self.active.is_none() {
+            self.start_pending();
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        Ws2812Dma::is_busy(self)
+    }
+
+    fn on_interrupt(&mut self) {
+        Ws2812Dma::on_interrupt(self);
+    }
+}